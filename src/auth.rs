@@ -0,0 +1,234 @@
+use crate::error::ErrorKind;
+use crate::{Request, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::slice;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// A client is refused after failing to authenticate this many times in a row.
+const MAX_FAILURES: u32 = 5;
+// A refused client may try again after this long without failures.
+const LOCKOUT: Duration = Duration::from_secs(30);
+
+/// The user a client authenticated with the shared password is, which access control lists
+/// never restrict, as the nodes of a cluster authenticate to each other with the password.
+pub const DEFAULT_USER: &str = "default";
+
+/// What a client authenticates with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Credentials {
+    /// The password shared by the clients of a server, which authenticates them as
+    /// [`DEFAULT_USER`].
+    Password(String),
+    /// The token of a user.
+    Token(String),
+}
+
+/// The secrets which clients of a server authenticate with, as read from its config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// The password shared by clients, which the nodes of a cluster also authenticate to each
+    /// other with.
+    pub password: Option<String>,
+    /// The token of each user, by the name of the user.
+    pub tokens: HashMap<String, String>,
+    /// The rules of each user, by the name of the user. Without them users may do anything,
+    /// and with them users may only do what their rules grant.
+    pub acl: Option<HashMap<String, Vec<Rule>>>,
+}
+
+/// A permission granted to a user on the keys starting with a prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// The prefix of the keys, which is empty for every key.
+    pub prefix: String,
+    /// What the user may do with the keys.
+    pub permission: Permission,
+}
+
+/// What a user may do with keys, each permission granting the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Get, scan and watch the keys.
+    Read,
+    /// Set and remove the keys.
+    Write,
+    /// Manage the server, which takes a rule with an empty prefix, such as to change the
+    /// members of a cluster or to move shards.
+    Admin,
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Read => write!(f, "read"),
+            Permission::Write => write!(f, "write"),
+            Permission::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Get the credentials the nodes of a cluster authenticate to each other with, if any.
+    pub fn peer_credentials(&self) -> Option<Credentials> {
+        self.password.clone().map(Credentials::Password)
+    }
+}
+
+/// The authentication of the clients of a server, given to `KvsServer::auth` so that every
+/// connection has to authenticate before sending any other request, and may then only send
+/// the requests the access control list grants its user.
+///
+/// Clients which keep failing are refused for a while, by their IP address.
+#[derive(Clone)]
+pub struct Auth(Arc<AuthState>);
+
+struct AuthState {
+    config: RwLock<AuthConfig>,
+    failures: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Auth {
+    /// Authenticate clients with the secrets of a given config.
+    pub fn new(config: AuthConfig) -> Auth {
+        Auth(Arc::new(AuthState {
+            config: RwLock::new(config),
+            failures: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Authenticate a client connected from a given address, and return the user it is.
+    /// Return an error of `ErrorKind::Unauthorized` if the credentials are wrong, or if the
+    /// client failed too many times recently.
+    pub(crate) fn authenticate(&self, peer: IpAddr, credentials: &Credentials) -> Result<String> {
+        self.authenticate_any(peer, slice::from_ref(credentials))
+    }
+
+    /// Authenticate a client with the first of given credentials which is right, where a single
+    /// failure is counted if none is, and return the user it is.
+    pub(crate) fn authenticate_any(&self, peer: IpAddr, candidates: &[Credentials]) -> Result<String> {
+        let mut failures = self.0.failures.lock().unwrap();
+        failures.retain(|_, failures| failures.last.elapsed() < LOCKOUT);
+        if let Some(failures) = failures.get(&peer).filter(|failures| failures.count >= MAX_FAILURES) {
+            let wait = LOCKOUT.saturating_sub(failures.last.elapsed());
+            return Err(ErrorKind::Unauthorized(format!(
+                "too many failed attempts, try again in {} seconds",
+                wait.as_secs() + 1
+            ))
+            .into());
+        }
+
+        match candidates.iter().find_map(|credentials| self.user_of(credentials)) {
+            Some(user) => {
+                failures.remove(&peer);
+                Ok(user)
+            }
+            None => {
+                let failures = failures.entry(peer).or_insert(Failures {
+                    count: 0,
+                    last: Instant::now(),
+                });
+                failures.count += 1;
+                failures.last = Instant::now();
+                Err(ErrorKind::Unauthorized("wrong password or token".to_string()).into())
+            }
+        }
+    }
+
+    /// Replace the secrets and the access control list, which applies to the requests
+    /// authenticated connections send from now on.
+    pub fn reload(&self, config: AuthConfig) {
+        *self.0.config.write().unwrap() = config;
+    }
+
+    /// Return an error of `ErrorKind::Unauthorized` unless a user may send a request.
+    pub(crate) fn authorize(&self, user: &str, request: &Request) -> Result<()> {
+        access(request)
+            .into_iter()
+            .try_for_each(|(permission, key)| self.authorize_key(user, permission, key))
+    }
+
+    /// Return an error of `ErrorKind::Unauthorized` unless a user has a permission on a key,
+    /// or on every key starting with a prefix.
+    pub(crate) fn authorize_key(&self, user: &str, permission: Permission, key: &str) -> Result<()> {
+        let config = self.0.config.read().unwrap();
+        let rules = match &config.acl {
+            Some(acl) if user != DEFAULT_USER => acl.get(user).map_or(&[][..], Vec::as_slice),
+            _ => return Ok(()),
+        };
+
+        if rules
+            .iter()
+            .any(|rule| rule.permission >= permission && key.starts_with(&rule.prefix))
+        {
+            Ok(())
+        } else {
+            Err(ErrorKind::Unauthorized(format!("{} has no {} permission on {:?}", user, permission, key)).into())
+        }
+    }
+
+    fn user_of(&self, credentials: &Credentials) -> Option<String> {
+        let config = self.0.config.read().unwrap();
+        match credentials {
+            Credentials::Password(password) => config
+                .password
+                .as_ref()
+                .filter(|expected| secure_eq(expected, password))
+                .map(|_| DEFAULT_USER.to_string()),
+            Credentials::Token(token) => config
+                .tokens
+                .iter()
+                .find(|(_, expected)| secure_eq(expected, token))
+                .map(|(user, _)| user.clone()),
+        }
+    }
+}
+
+/// Get the permissions a request takes, on keys or on every key starting with a prefix.
+fn access(request: &Request) -> Vec<(Permission, &str)> {
+    use Permission::*;
+    match request {
+        Request::Get { key } => vec![(Read, key)],
+        Request::GetMany { keys } => keys.iter().map(|key| (Read, key.as_str())).collect(),
+        Request::Scan { prefix } | Request::Watch { prefix, .. } => vec![(Read, prefix)],
+        Request::Set { key, .. } | Request::Remove { key } => vec![(Write, key)],
+        Request::SetMany { pairs } => pairs.iter().map(|(key, _)| (Write, key.as_str())).collect(),
+        Request::RemoveMany { keys } => keys.iter().map(|key| (Write, key.as_str())).collect(),
+        // Channels are not keys, and clients need the shard map to route their requests.
+        Request::Auth { .. }
+        | Request::Ping
+        | Request::Publish { .. }
+        | Request::Subscribe { .. }
+        | Request::ShardMap => Vec::new(),
+        Request::Sync { .. }
+        | Request::ReplicationInfo
+        | Request::Info
+        | Request::Compact
+        | Request::SlowLog { .. }
+        | Request::RequestLog { .. }
+        | Request::Raft(_)
+        | Request::ClusterInfo
+        | Request::AddMember { .. }
+        | Request::RemoveMember { .. }
+        | Request::SetShardMap { .. }
+        | Request::MoveShard { .. }
+        | Request::ImportShard { .. } => vec![(Admin, "")],
+    }
+}
+
+/// Compare secrets in a time which does not tell how much of them matched.
+fn secure_eq(expected: &str, given: &str) -> bool {
+    let (expected, given) = (expected.as_bytes(), given.as_bytes());
+    expected.len() == given.len() && expected.iter().zip(given).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,224 @@
+#[macro_use]
+extern crate clap;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use kvs::{ClientTls, Credentials, ErrorKind, KvsClient, Result, ShardMap, Timeouts};
+use std::process;
+
+fn main() -> Result<()> {
+    let matches = App::new("kvs-admin")
+        .version(crate_version!())
+        .author(crate_authors!("\n"))
+        .about("administrate a sharded cluster of key value store servers")
+        .setting(AppSettings::DisableHelpSubcommand)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("version")
+                .short("V")
+                .long("version")
+                .help("Print the version"),
+        )
+        .arg(
+            Arg::with_name("PASSWORD")
+                .long("password")
+                .global(true)
+                .takes_value(true)
+                .env("KVS_PASSWORD")
+                .hide_env_values(true)
+                .help("authenticate with the password shared by the clients of the nodes"),
+        )
+        .arg(
+            Arg::with_name("TOKEN")
+                .long("token")
+                .global(true)
+                .takes_value(true)
+                .env("KVS_TOKEN")
+                .hide_env_values(true)
+                .conflicts_with("PASSWORD")
+                .help("authenticate with the token of a user"),
+        )
+        .arg(
+            Arg::with_name("TLS-CA")
+                .long("tls-ca")
+                .global(true)
+                .takes_value(true)
+                .env("KVS_TLS_CA")
+                .help("connect with TLS, trusting servers with a certificate signed by the authorities of a PEM file"),
+        )
+        .arg(
+            Arg::with_name("TLS-CERT")
+                .long("tls-cert")
+                .global(true)
+                .takes_value(true)
+                .requires_all(&["TLS-CA", "TLS-KEY"])
+                .help("present the certificate of a PEM file to servers asking for a client certificate"),
+        )
+        .arg(
+            Arg::with_name("TLS-KEY")
+                .long("tls-key")
+                .global(true)
+                .takes_value(true)
+                .requires("TLS-CERT")
+                .help("the PEM file of the private key of the client certificate"),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Give the first shard map to the nodes of a new cluster, spreading the shards over them")
+                .arg(
+                    Arg::with_name("NODE")
+                        .required(true)
+                        .multiple(true)
+                        .help("the addresses of the nodes, as given to their --addr"),
+                )
+                .arg(
+                    Arg::with_name("SHARDS")
+                        .long("shards")
+                        .default_value("16")
+                        .validator(|value| match value.parse::<usize>() {
+                            Ok(shards) if shards > 0 => Ok(()),
+                            _ => Err("the number of shards must be a positive number".to_string()),
+                        })
+                        .help("the number of shards, which never changes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("map")
+                .about("Print the shard map of a node, listing the shards of each node")
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("move")
+                .about("Move a shard to another node, streaming its keys from the node serving it")
+                .arg(Arg::with_name("SHARD").required(true).help("the shard to move"))
+                .arg(
+                    Arg::with_name("NODE")
+                        .long("to")
+                        .required(true)
+                        .takes_value(true)
+                        .help("the address of the node to move the shard to, as given to its --addr"),
+                )
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number of any node of the cluster"),
+                ),
+        )
+        .get_matches();
+
+    if matches.is_present("version") {
+        println!(crate_version!());
+        process::exit(0);
+    }
+
+    if let Err(err) = run(matches) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn run(matches: ArgMatches) -> Result<()> {
+    let credentials = match (matches.value_of("PASSWORD"), matches.value_of("TOKEN")) {
+        (Some(password), _) => Some(Credentials::Password(password.to_string())),
+        (_, Some(token)) => Some(Credentials::Token(token.to_string())),
+        (None, None) => None,
+    };
+    let tls = match matches.value_of("TLS-CA") {
+        Some(ca) => {
+            let identity = matches.value_of("TLS-CERT").zip(matches.value_of("TLS-KEY"));
+            Some(ClientTls::new(ca, identity)?)
+        }
+        None => None,
+    };
+    let connect = |address: &str| -> Result<KvsClient> {
+        let mut client = match &tls {
+            Some(tls) => KvsClient::connect_tls(address, Timeouts::default(), tls.clone())?,
+            None => KvsClient::connect(address)?,
+        };
+        if let Some(credentials) = &credentials {
+            client.authenticate(credentials.clone())?;
+        }
+        Ok(client)
+    };
+
+    match matches.subcommand() {
+        ("init", Some(matches)) => {
+            let nodes = matches
+                .values_of("NODE")
+                .expect("NODE argument is missing")
+                .map(ToString::to_string)
+                .collect::<Vec<String>>();
+            let shards = matches
+                .value_of("SHARDS")
+                .and_then(|shards| shards.parse().ok())
+                .expect("SHARDS argument is missing");
+
+            // Every node is checked first, so that a running cluster is never given another map.
+            let mut clients = Vec::with_capacity(nodes.len());
+            for node in &nodes {
+                let mut client = connect(node)?;
+                let map = client.shard_map()?;
+                if map.version > 0 {
+                    return Err(ErrorKind::InvalidRequest(format!(
+                        "{} already has version {} of a shard map",
+                        node, map.version
+                    ))
+                    .into());
+                }
+                clients.push(client);
+            }
+
+            let map = ShardMap::new(shards, &nodes);
+            for client in &mut clients {
+                client.set_shard_map(map.clone())?;
+            }
+            print_map(&map);
+        }
+        ("map", Some(matches)) => {
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+            print_map(&connect(address)?.shard_map()?);
+        }
+        ("move", Some(matches)) => {
+            let shard = match matches.value_of("SHARD").map(str::parse::<usize>) {
+                Some(Ok(shard)) => shard,
+                _ => return Err(ErrorKind::InvalidRequest("the shard must be a number".to_string()).into()),
+            };
+            let to = matches
+                .value_of("NODE")
+                .expect("NODE argument is missing")
+                .to_string();
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+
+            // The request is redirected to the node serving the shard.
+            print_map(&connect(address)?.move_shard(shard, to)?);
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn print_map(map: &ShardMap) {
+    println!("version: {}", map.version);
+    for node in map.nodes() {
+        let shards = map
+            .shards_of(node)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>();
+        println!("{}: {}", node, shards.join(" "));
+    }
+}
@@ -0,0 +1,408 @@
+#[macro_use]
+extern crate clap;
+
+use clap::{App, AppSettings, Arg, ArgMatches};
+use kvs::{ClientTls, Credentials, KvsClient, Request, Response, Result, Timeouts};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Keys are preloaded in pipelines of this many requests.
+const PRELOAD_CHUNK: usize = 1024;
+
+fn main() -> Result<()> {
+    let matches = App::new("kvs-bench")
+        .version(crate_version!())
+        .author(crate_authors!("\n"))
+        .about("a load generator for a running key value store server")
+        .setting(AppSettings::DisableHelpSubcommand)
+        .arg(
+            Arg::with_name("version")
+                .short("V")
+                .long("version")
+                .help("Print the version"),
+        )
+        .arg(
+            Arg::with_name("IP-PORT")
+                .short("a")
+                .long("addr")
+                .default_value("127.0.0.1:4000")
+                .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+        )
+        .arg(
+            Arg::with_name("CONNECTIONS")
+                .short("c")
+                .long("connections")
+                .default_value("8")
+                .validator(positive::<usize>)
+                .help("the number of connections, each one driven by its own thread"),
+        )
+        .arg(
+            Arg::with_name("REQUESTS")
+                .short("n")
+                .long("requests")
+                .default_value("100000")
+                .validator(positive::<usize>)
+                .help("the total number of requests"),
+        )
+        .arg(
+            Arg::with_name("KEYSPACE")
+                .short("k")
+                .long("keyspace")
+                .default_value("10000")
+                .validator(positive::<usize>)
+                .help("the number of distinct keys, named key0, key1 and so on"),
+        )
+        .arg(
+            Arg::with_name("BYTES")
+                .short("d")
+                .long("value-size")
+                .default_value("100")
+                .validator(|value| parse_range(&value).map(|_| ()))
+                .help("the size of values, or a range MIN-MAX of sizes picked uniformly"),
+        )
+        .arg(
+            Arg::with_name("RATIO")
+                .short("r")
+                .long("read-ratio")
+                .default_value("0.5")
+                .validator(|value| match value.parse::<f64>() {
+                    Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(()),
+                    _ => Err("the ratio must be between 0 and 1".to_string()),
+                })
+                .help("the share of gets among the requests, the others being sets"),
+        )
+        .arg(
+            Arg::with_name("DISTRIBUTION")
+                .long("distribution")
+                .possible_values(&["uniform", "zipfian"])
+                .default_value("uniform")
+                .help("how keys are picked from the key space"),
+        )
+        .arg(
+            Arg::with_name("EXPONENT")
+                .long("zipf-exponent")
+                .default_value("0.99")
+                .validator(positive::<f64>)
+                .help("the skew of the zipfian distribution, higher values picking the first keys more often"),
+        )
+        .arg(
+            Arg::with_name("preload")
+                .long("preload")
+                .help("set every key of the key space before the benchmark, so that gets find their keys"),
+        )
+        .arg(
+            Arg::with_name("PASSWORD")
+                .long("password")
+                .takes_value(true)
+                .env("KVS_PASSWORD")
+                .hide_env_values(true)
+                .help("authenticate with the password shared by the clients of the server"),
+        )
+        .arg(
+            Arg::with_name("TOKEN")
+                .long("token")
+                .takes_value(true)
+                .env("KVS_TOKEN")
+                .hide_env_values(true)
+                .conflicts_with("PASSWORD")
+                .help("authenticate with the token of a user"),
+        )
+        .arg(
+            Arg::with_name("TLS-CA")
+                .long("tls-ca")
+                .takes_value(true)
+                .env("KVS_TLS_CA")
+                .help("connect with TLS, trusting servers with a certificate signed by the authorities of a PEM file"),
+        )
+        .arg(
+            Arg::with_name("TLS-CERT")
+                .long("tls-cert")
+                .takes_value(true)
+                .requires_all(&["TLS-CA", "TLS-KEY"])
+                .help("present the certificate of a PEM file to servers asking for a client certificate"),
+        )
+        .arg(
+            Arg::with_name("TLS-KEY")
+                .long("tls-key")
+                .takes_value(true)
+                .requires("TLS-CERT")
+                .help("the PEM file of the private key of the client certificate"),
+        )
+        .get_matches();
+
+    if matches.is_present("version") {
+        println!(crate_version!());
+        process::exit(0);
+    }
+
+    if let Err(err) = Options::from_matches(&matches).and_then(run) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn positive<T: FromStr + PartialOrd + Default>(value: String) -> std::result::Result<(), String> {
+    match value.parse::<T>() {
+        Ok(number) if number > T::default() => Ok(()),
+        _ => Err("the value must be a positive number".to_string()),
+    }
+}
+
+/// Parse a size, or a range of sizes written as MIN-MAX.
+fn parse_range(value: &str) -> std::result::Result<(usize, usize), String> {
+    let (min, max) = value.split_once('-').unwrap_or((value, value));
+    match (min.parse::<usize>(), max.parse::<usize>()) {
+        (Ok(min), Ok(max)) if min <= max => Ok((min, max)),
+        _ => Err("expected a size, or a range MIN-MAX of sizes".to_string()),
+    }
+}
+
+struct Options {
+    addr: String,
+    connections: usize,
+    requests: usize,
+    keyspace: usize,
+    value_size: (usize, usize),
+    read_ratio: f64,
+    keys: KeyDistribution,
+    preload: bool,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
+}
+
+impl Options {
+    fn from_matches(matches: &ArgMatches) -> Result<Options> {
+        let value = |name| matches.value_of(name).expect("argument is missing");
+        let keyspace = value("KEYSPACE").parse().unwrap_or(1);
+        let tls = match matches.value_of("TLS-CA") {
+            Some(ca) => {
+                let identity = matches.value_of("TLS-CERT").zip(matches.value_of("TLS-KEY"));
+                Some(ClientTls::new(ca, identity)?)
+            }
+            None => None,
+        };
+
+        Ok(Options {
+            addr: value("IP-PORT").to_string(),
+            connections: value("CONNECTIONS").parse().unwrap_or(1),
+            requests: value("REQUESTS").parse().unwrap_or(1),
+            keyspace,
+            value_size: parse_range(value("BYTES")).unwrap_or_default(),
+            read_ratio: value("RATIO").parse().unwrap_or_default(),
+            keys: match value("DISTRIBUTION") {
+                "zipfian" => KeyDistribution::zipfian(keyspace, value("EXPONENT").parse().unwrap_or(1.0)),
+                _ => KeyDistribution::Uniform(keyspace),
+            },
+            preload: matches.is_present("preload"),
+            credentials: match (matches.value_of("PASSWORD"), matches.value_of("TOKEN")) {
+                (Some(password), _) => Some(Credentials::Password(password.to_string())),
+                (_, Some(token)) => Some(Credentials::Token(token.to_string())),
+                (None, None) => None,
+            },
+            tls,
+        })
+    }
+
+    /// Open a connection to the server, with TLS if its settings were given, and authenticate if
+    /// credentials were given.
+    fn connect(&self) -> Result<KvsClient> {
+        let mut client = match &self.tls {
+            Some(tls) => KvsClient::connect_tls(&self.addr, Timeouts::default(), tls.clone())?,
+            None => KvsClient::connect(&self.addr)?,
+        };
+        if let Some(credentials) = &self.credentials {
+            client.authenticate(credentials.clone())?;
+        }
+        Ok(client)
+    }
+}
+
+/// How the key of each request is picked.
+enum KeyDistribution {
+    Uniform(usize),
+    /// The cumulative probabilities of the keys, where key i is picked with a probability
+    /// proportional to 1 / (i + 1) ^ exponent.
+    Zipfian(Vec<f64>),
+}
+
+impl KeyDistribution {
+    fn zipfian(keyspace: usize, exponent: f64) -> Self {
+        let mut cdf = Vec::with_capacity(keyspace);
+        let mut sum = 0.0;
+        for rank in 1..=keyspace {
+            sum += 1.0 / (rank as f64).powf(exponent);
+            cdf.push(sum);
+        }
+        cdf.iter_mut().for_each(|p| *p /= sum);
+        KeyDistribution::Zipfian(cdf)
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> usize {
+        match self {
+            KeyDistribution::Uniform(keyspace) => rng.gen_range(0, *keyspace),
+            KeyDistribution::Zipfian(cdf) => {
+                let p = rng.gen::<f64>();
+                cdf.partition_point(|&q| q < p).min(cdf.len() - 1)
+            }
+        }
+    }
+}
+
+/// The latencies of the requests of one kind, in microseconds.
+#[derive(Default)]
+struct Latencies {
+    samples: Vec<u64>,
+    errors: usize,
+}
+
+impl Latencies {
+    fn merge(&mut self, other: Latencies) {
+        self.samples.extend(other.samples);
+        self.errors += other.errors;
+    }
+
+    fn report(&mut self, name: &str, elapsed: Duration) {
+        if self.samples.is_empty() {
+            return;
+        }
+
+        self.samples.sort_unstable();
+        let percentile = |p: f64| {
+            let rank = ((p / 100.0) * self.samples.len() as f64).ceil() as usize;
+            self.samples[rank.saturating_sub(1)]
+        };
+
+        println!(
+            "{}: {} requests, {} errors, {:.2} requests per second",
+            name,
+            self.samples.len(),
+            self.errors,
+            self.samples.len() as f64 / elapsed.as_secs_f64()
+        );
+        println!(
+            "  latency (us): p50 {}, p90 {}, p99 {}, p99.9 {}, max {}",
+            percentile(50.0),
+            percentile(90.0),
+            percentile(99.0),
+            percentile(99.9),
+            self.samples[self.samples.len() - 1]
+        );
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    gets: Latencies,
+    sets: Latencies,
+}
+
+fn run(options: Options) -> Result<()> {
+    if options.preload {
+        preload(&options)?;
+    }
+
+    let options = Arc::new(options);
+    let mut clients = Vec::with_capacity(options.connections);
+    for _ in 0..options.connections {
+        clients.push(options.connect()?);
+    }
+
+    let start = Instant::now();
+    let workers = clients
+        .into_iter()
+        .enumerate()
+        .map(|(index, client)| {
+            // The requests are split evenly, the first connections taking the remainder.
+            let requests = options.requests / options.connections
+                + usize::from(index < options.requests % options.connections);
+            let options = Arc::clone(&options);
+            thread::spawn(move || drive(client, &options, requests))
+        })
+        .collect::<Vec<_>>();
+
+    let mut report = Report::default();
+    for worker in workers {
+        let worker_report = worker.join().expect("a connection panicked")?;
+        report.gets.merge(worker_report.gets);
+        report.sets.merge(worker_report.sets);
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} requests over {} connections in {:.2?}, {} keys, {} distribution",
+        options.requests,
+        options.connections,
+        elapsed,
+        options.keyspace,
+        match options.keys {
+            KeyDistribution::Uniform(_) => "uniform",
+            KeyDistribution::Zipfian(_) => "zipfian",
+        }
+    );
+    report.sets.report("SET", elapsed);
+    report.gets.report("GET", elapsed);
+
+    let mut total = Latencies::default();
+    total.merge(report.sets);
+    total.merge(report.gets);
+    total.report("TOTAL", elapsed);
+    Ok(())
+}
+
+/// Send requests one at a time, timing each of them.
+fn drive(mut client: KvsClient, options: &Options, requests: usize) -> Result<Report> {
+    let mut rng = rand::thread_rng();
+    let (min_size, max_size) = options.value_size;
+    let values = random_string(&mut rng, max_size);
+    let mut report = Report::default();
+
+    for _ in 0..requests {
+        let key = format!("key{}", options.keys.sample(&mut rng));
+        let (request, latencies) = if rng.gen::<f64>() < options.read_ratio {
+            (Request::Get { key }, &mut report.gets)
+        } else {
+            let size = rng.gen_range(min_size, max_size + 1);
+            let value = values[..size].to_string();
+            (Request::Set { key, value }, &mut report.sets)
+        };
+
+        let start = Instant::now();
+        let response = client.call(request)?;
+        latencies.samples.push(start.elapsed().as_micros() as u64);
+
+        if let Response::Get(Err(_)) | Response::Set(Err(_)) | Response::Error(_) = response {
+            latencies.errors += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Set every key of the key space with pipelines over one connection.
+fn preload(options: &Options) -> Result<()> {
+    let mut client = options.connect()?;
+    let mut rng = rand::thread_rng();
+    let value = random_string(&mut rng, options.value_size.1);
+
+    let start = Instant::now();
+    for chunk in (0..options.keyspace).collect::<Vec<usize>>().chunks(PRELOAD_CHUNK) {
+        let mut pipeline = client.pipeline();
+        for index in chunk {
+            pipeline.set(format!("key{}", index), value.clone());
+        }
+        pipeline.execute()?;
+    }
+
+    println!("preloaded {} keys in {:.2?}", options.keyspace, start.elapsed());
+    Ok(())
+}
+
+fn random_string(rng: &mut impl Rng, len: usize) -> String {
+    rng.sample_iter(&Alphanumeric).take(len).collect()
+}
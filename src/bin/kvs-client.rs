@@ -2,8 +2,26 @@
 extern crate clap;
 
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use kvs::{KvsClient, Result};
+use kvs::{ClientTls, Command, Credentials, Error, ErrorCode, ErrorKind, KvsClient, ProtocolError, Request, Response, Result, Role, Timeouts};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:4000";
+
+// Exit codes telling why a command failed, where usage errors and any other failure exit with 1.
+const EXIT_NOT_FOUND: i32 = 4;
+const EXIT_SERVER_ERROR: i32 = 2;
+const EXIT_CONNECTION_ERROR: i32 = 3;
+
+// Commands of a batch are pipelined in chunks of this many requests.
+const BATCH_CHUNK: usize = 1024;
 
 fn main() -> Result<()> {
     let matches = App::new("kvs-client")
@@ -11,7 +29,6 @@ fn main() -> Result<()> {
         .author(crate_authors!("\n"))
         .about("a client for the key value store")
         .setting(AppSettings::DisableHelpSubcommand)
-        .setting(AppSettings::ArgRequiredElseHelp)
         .setting(AppSettings::VersionlessSubcommands)
         .arg(
             Arg::with_name("version")
@@ -19,6 +36,68 @@ fn main() -> Result<()> {
                 .long("version")
                 .help("Print the version"),
         )
+        .arg(
+            Arg::with_name("SECONDS")
+                .short("t")
+                .long("timeout")
+                .global(true)
+                .takes_value(true)
+                .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("give up connecting, sending or receiving after this many seconds"),
+        )
+        .arg(
+            Arg::with_name("PASSWORD")
+                .long("password")
+                .global(true)
+                .takes_value(true)
+                .env("KVS_PASSWORD")
+                .hide_env_values(true)
+                .help("authenticate with the password shared by the clients of the server"),
+        )
+        .arg(
+            Arg::with_name("TOKEN")
+                .long("token")
+                .global(true)
+                .takes_value(true)
+                .env("KVS_TOKEN")
+                .hide_env_values(true)
+                .conflicts_with("PASSWORD")
+                .help("authenticate with the token of a user"),
+        )
+        .arg(
+            Arg::with_name("TLS-CA")
+                .long("tls-ca")
+                .global(true)
+                .takes_value(true)
+                .env("KVS_TLS_CA")
+                .help("connect with TLS, trusting servers with a certificate signed by the authorities of a PEM file"),
+        )
+        .arg(
+            Arg::with_name("TLS-CERT")
+                .long("tls-cert")
+                .global(true)
+                .takes_value(true)
+                .requires_all(&["TLS-CA", "TLS-KEY"])
+                .help("present the certificate of a PEM file to servers asking for a client certificate"),
+        )
+        .arg(
+            Arg::with_name("TLS-KEY")
+                .long("tls-key")
+                .global(true)
+                .takes_value(true)
+                .requires("TLS-CERT")
+                .help("the PEM file of the private key of the client certificate"),
+        )
+        .arg(
+            Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["raw", "json", "table"])
+                .default_value("raw")
+                .help("print the results of get, set and rm as raw values, JSON lines or a table"),
+        )
         .subcommand(
             SubCommand::with_name("set")
                 .about("Set the value of a string key to a string")
@@ -33,31 +112,257 @@ fn main() -> Result<()> {
                         .short("a")
                         .long("addr")
                         .default_value("127.0.0.1:4000")
-                        .help("a v4 or v6 IP address with a port number"),
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("get")
-                .about("Get the string value of a given string key")
-                .arg(Arg::with_name("KEY").required(true).help("a string key"))
+                .about("Get the string values of given string keys")
+                .arg(
+                    Arg::with_name("KEY")
+                        .required(true)
+                        .multiple(true)
+                        .help("string keys"),
+                )
+                .arg(
+                    Arg::with_name("fail-missing")
+                        .long("fail-missing")
+                        .help("exit with 4 if a key is not found, rather than 0"),
+                )
                 .arg(
                     Arg::with_name("IP-PORT")
                         .short("a")
                         .long("addr")
                         .default_value("127.0.0.1:4000")
-                        .help("a v4 or v6 IP address with a port number"),
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("rm")
-                .about("Remove a given key")
-                .arg(Arg::with_name("KEY").required(true).help("a string key"))
+                .about("Remove given keys")
+                .arg(
+                    Arg::with_name("KEY")
+                        .required(true)
+                        .multiple(true)
+                        .help("string keys"),
+                )
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Open an interactive session, which is also the default without a subcommand")
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("Pipeline commands or key/value pairs from a file or stdin over one connection")
+                .arg(
+                    Arg::with_name("FILE")
+                        .short("f")
+                        .long("file")
+                        .takes_value(true)
+                        .help("the file to read, stdin if not given"),
+                )
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["cmd", "csv", "jsonl"])
+                        .help("cmd lines like `set KEY VALUE`, or KEY,VALUE pairs in CSV, or {\"key\", \"value\"} \
+                               objects in JSON lines [default: by the file extension, else cmd]"),
+                )
+                .arg(
+                    Arg::with_name("stop-on-error")
+                        .long("stop-on-error")
+                        .help("send commands one at a time, stopping at the first failure"),
+                )
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cluster")
+                .about("Print the Raft state of a cluster node, or add or remove a member through the leader")
+                .arg(
+                    Arg::with_name("ADD")
+                        .long("add")
+                        .takes_value(true)
+                        .value_name("MEMBER-IP-PORT")
+                        .help("add the node on this address as a member"),
+                )
+                .arg(
+                    Arg::with_name("REMOVE")
+                        .long("remove")
+                        .takes_value(true)
+                        .value_name("MEMBER-IP-PORT")
+                        .conflicts_with("ADD")
+                        .help("remove the member on this address"),
+                )
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Print the changes of the keys starting with a prefix as they are committed, until interrupted")
+                .arg(Arg::with_name("PREFIX").default_value("").help("a key prefix, all keys if not given"))
+                .arg(
+                    Arg::with_name("STREAM")
+                        .long("stream")
+                        .takes_value(true)
+                        .requires("SEQ")
+                        .help("the stream of a previous watch to resume"),
+                )
+                .arg(
+                    Arg::with_name("SEQ")
+                        .long("after")
+                        .takes_value(true)
+                        .requires("STREAM")
+                        .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                        .help("the seq of the last change printed by the watch to resume"),
+                )
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("publish")
+                .about("Publish a message to the subscribers of a channel, printing how many received it")
+                .arg(Arg::with_name("CHANNEL").required(true).help("a channel name"))
+                .arg(Arg::with_name("MESSAGE").required(true).help("a string message"))
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("subscribe")
+                .about("Print the messages published to channels as they are published, until interrupted")
+                .arg(
+                    Arg::with_name("CHANNEL")
+                        .multiple(true)
+                        .required_unless("PATTERN")
+                        .help("channel names"),
+                )
+                .arg(
+                    Arg::with_name("PATTERN")
+                        .short("p")
+                        .long("pattern")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("also subscribe the channels matching a glob-style pattern, like news.*"),
+                )
                 .arg(
                     Arg::with_name("IP-PORT")
                         .short("a")
                         .long("addr")
                         .default_value("127.0.0.1:4000")
-                        .help("a v4 or v6 IP address with a port number"),
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("admin")
+                .about("Run maintenance on the server, which takes the admin permission")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .setting(AppSettings::DisableHelpSubcommand)
+                .subcommand(
+                    SubCommand::with_name("compact")
+                        .about("Compact the storage of the engine, printing the space reclaimed and the time taken")
+                        .arg(
+                            Arg::with_name("IP-PORT")
+                                .short("a")
+                                .long("addr")
+                                .default_value("127.0.0.1:4000")
+                                .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("request-log")
+                        .about("Switch the logging of every request and response of connections, printing those logged")
+                        .arg(
+                            Arg::with_name("SWITCH")
+                                .possible_values(&["on", "off"])
+                                .required(true)
+                                .help("on to log the requests, off to stop logging them"),
+                        )
+                        .arg(
+                            Arg::with_name("CLIENT")
+                                .long("client")
+                                .takes_value(true)
+                                .help("only switch the connections of a client, by its IP address with or without a port"),
+                        )
+                        .arg(
+                            Arg::with_name("IP-PORT")
+                                .short("a")
+                                .long("addr")
+                                .default_value("127.0.0.1:4000")
+                                .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Print the state of the server, such as its engine, its uptime and its request counts")
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("slowlog")
+                .about("Print the requests which took longer than the threshold of the slow log, newest first")
+                .arg(
+                    Arg::with_name("reset")
+                        .long("reset")
+                        .help("clear the slow log once printed"),
+                )
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replication")
+                .about("Print the replication state of the server, such as the lag of a follower")
+                .arg(
+                    Arg::with_name("IP-PORT")
+                        .short("a")
+                        .long("addr")
+                        .default_value("127.0.0.1:4000")
+                        .help("a v4 or v6 IP address with a port number, or unix:PATH of a Unix socket"),
                 ),
         )
         .get_matches();
@@ -67,10 +372,46 @@ fn main() -> Result<()> {
         process::exit(0);
     }
 
-    run(matches)
+    // Other errors are reported by the commands, so an error here means the connection failed,
+    // or the server refused the credentials.
+    if let Err(err) = run(matches) {
+        eprintln!("{}", err);
+        if is_connection_error(&err) {
+            process::exit(EXIT_CONNECTION_ERROR);
+        }
+        process::exit(EXIT_SERVER_ERROR);
+    }
+    Ok(())
 }
 
 fn run(matches: ArgMatches) -> Result<()> {
+    let timeouts = match matches.value_of("SECONDS") {
+        Some(seconds) => Timeouts::all(Duration::from_secs(seconds.parse().unwrap_or_default())),
+        None => Timeouts::default(),
+    };
+    let credentials = match (matches.value_of("PASSWORD"), matches.value_of("TOKEN")) {
+        (Some(password), _) => Some(Credentials::Password(password.to_string())),
+        (_, Some(token)) => Some(Credentials::Token(token.to_string())),
+        (None, None) => None,
+    };
+    let tls = match matches.value_of("TLS-CA") {
+        Some(ca) => {
+            let identity = matches.value_of("TLS-CERT").zip(matches.value_of("TLS-KEY"));
+            Some(ClientTls::new(ca, identity)?)
+        }
+        None => None,
+    };
+    let connection = Connection {
+        timeouts,
+        credentials,
+        tls,
+    };
+    let output = match matches.value_of("OUTPUT") {
+        Some("json") => Output::Json,
+        Some("table") => Output::Table,
+        _ => Output::Raw,
+    };
+
     match matches.subcommand() {
         ("set", Some(matches)) => {
             let key = matches
@@ -85,48 +426,955 @@ fn run(matches: ArgMatches) -> Result<()> {
                 .value_of("IP-PORT")
                 .expect("IP-PORT argument is missing");
 
-            let mut client = KvsClient::connect(address)?;
-            if let Err(err) = client.set(key, value) {
-                eprintln!("{}", err);
-                process::exit(1);
-            }
+            let mut client = connection.open(address)?;
+            let request = Request::Set {
+                key: key.clone(),
+                value: value.clone(),
+            };
+            let error = match client.call(request)? {
+                Response::Set(result) => result.err(),
+                response => Some(error_of(response)),
+            };
+
+            let records = vec![Record {
+                key,
+                exists: error.is_none(),
+                value: Some(value),
+                error,
+            }];
+            print_records(&records, output, false);
+            exit_with(&records);
         }
         ("get", Some(matches)) => {
-            let key = matches
-                .value_of("KEY")
+            let keys = matches
+                .values_of("KEY")
+                .expect("KEY argument is missing")
                 .map(ToString::to_string)
-                .expect("KEY argument is missing");
+                .collect::<Vec<String>>();
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+            let fail_missing = matches.is_present("fail-missing");
+
+            let mut client = connection.open(address)?;
+            let records = match client.call(Request::GetMany { keys: keys.clone() })? {
+                Response::GetMany(Ok(values)) => keys
+                    .into_iter()
+                    .zip(values)
+                    .map(|(key, value)| Record {
+                        key,
+                        exists: value.is_some(),
+                        error: (value.is_none() && fail_missing)
+                            .then(|| ProtocolError::new(ErrorCode::NotFound, "Key not found")),
+                        value,
+                    })
+                    .collect(),
+                response => Record::failed(keys, error_of(response)),
+            };
+            print_records(&records, output, true);
+            exit_with(&records);
+        }
+        ("rm", Some(matches)) => {
+            let keys = matches
+                .values_of("KEY")
+                .expect("KEY argument is missing")
+                .map(ToString::to_string)
+                .collect::<Vec<String>>();
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+
+            let mut client = connection.open(address)?;
+            let records = match client.call(Request::RemoveMany { keys: keys.clone() })? {
+                Response::RemoveMany(Ok(removed)) => keys
+                    .into_iter()
+                    .zip(removed)
+                    .map(|(key, removed)| Record {
+                        key,
+                        value: None,
+                        exists: removed,
+                        error: (!removed).then(|| ProtocolError::new(ErrorCode::NotFound, "Key not found")),
+                    })
+                    .collect(),
+                response => Record::failed(keys, error_of(response)),
+            };
+            print_records(&records, output, false);
+            exit_with(&records);
+        }
+        ("admin", Some(matches)) => match matches.subcommand() {
+            ("compact", Some(matches)) => {
+                let address = matches
+                    .value_of("IP-PORT")
+                    .expect("IP-PORT argument is missing");
+
+                let mut client = connection.open(address)?;
+                let compaction = match client.call(Request::Compact)? {
+                    Response::Compact(Ok(compaction)) => compaction,
+                    response => {
+                        eprintln!("{}", error_of(response));
+                        process::exit(EXIT_SERVER_ERROR);
+                    }
+                };
+
+                match output {
+                    Output::Json => println!("{}", serde_json::to_string(&compaction)?),
+                    Output::Raw | Output::Table => println!(
+                        "reclaimed {} in {}ms",
+                        format_bytes(compaction.reclaimed_bytes),
+                        compaction.duration_ms
+                    ),
+                }
+            }
+            ("request-log", Some(matches)) => {
+                let address = matches
+                    .value_of("IP-PORT")
+                    .expect("IP-PORT argument is missing");
+
+                let mut client = connection.open(address)?;
+                let request = Request::RequestLog {
+                    enabled: matches.value_of("SWITCH") == Some("on"),
+                    client: matches.value_of("CLIENT").map(str::to_string),
+                };
+                let state = match client.call(request)? {
+                    Response::RequestLog(state) => state,
+                    response => {
+                        eprintln!("{}", error_of(response));
+                        process::exit(EXIT_SERVER_ERROR);
+                    }
+                };
+
+                match output {
+                    Output::Json => println!("{}", serde_json::to_string(&state)?),
+                    Output::Raw | Output::Table if state.all => println!("logging every connection"),
+                    Output::Raw | Output::Table if state.clients.is_empty() => println!("logging no connection"),
+                    Output::Raw | Output::Table => println!("logging the connections of {}", state.clients.join(", ")),
+                }
+            }
+            _ => unreachable!(),
+        },
+        ("info", Some(matches)) => {
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+
+            let mut client = connection.open(address)?;
+            let info = match client.call(Request::Info)? {
+                Response::Info(info) => info,
+                response => {
+                    eprintln!("{}", error_of(response));
+                    process::exit(EXIT_SERVER_ERROR);
+                }
+            };
+
+            match output {
+                Output::Json => println!("{}", serde_json::to_string(&info)?),
+                Output::Raw | Output::Table => {
+                    println!("engine: {}", info.engine);
+                    println!("version: {}", info.version);
+                    println!("uptime: {}", format_duration(info.uptime_secs));
+                    println!("keys: {}", info.keys);
+                    println!("disk size: {}", format_bytes(info.disk_bytes));
+                    println!("uncompacted: {}", format_bytes(info.uncompacted_bytes));
+                    println!("generation: {}", info.generation);
+                    println!("connected clients: {}", info.connected_clients);
+                    println!("commands:");
+                    for (command, count) in info.commands {
+                        println!("  {}: {}", command, count);
+                    }
+                }
+            }
+        }
+        ("slowlog", Some(matches)) => {
             let address = matches
                 .value_of("IP-PORT")
                 .expect("IP-PORT argument is missing");
 
-            let mut client = KvsClient::connect(address)?;
-            match client.get(key) {
-                Ok(Some(value)) => println!("{}", value),
-                Ok(None) => println!("Key not found"),
-                Err(err) => {
-                    eprintln!("{}", err);
-                    process::exit(1);
+            let mut client = connection.open(address)?;
+            let entries = match client.call(Request::SlowLog { reset: matches.is_present("reset") })? {
+                Response::SlowLog(entries) => entries,
+                response => {
+                    eprintln!("{}", error_of(response));
+                    process::exit(EXIT_SERVER_ERROR);
+                }
+            };
+
+            match output {
+                Output::Json => {
+                    for entry in entries {
+                        println!("{}", serde_json::to_string(&entry)?);
+                    }
+                }
+                Output::Raw => {
+                    for entry in entries {
+                        println!(
+                            "{} {} {} {} {}",
+                            entry.id,
+                            entry.timestamp_ms,
+                            format_micros(entry.duration_us),
+                            entry.client,
+                            entry.request
+                        );
+                    }
+                }
+                Output::Table => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                    let rows = entries
+                        .into_iter()
+                        .map(|entry| {
+                            [
+                                entry.id.to_string(),
+                                format!("{} ago", format_duration(now.saturating_sub(entry.timestamp_ms) / 1000)),
+                                format_micros(entry.duration_us),
+                                entry.client,
+                                entry.request,
+                            ]
+                        })
+                        .collect::<Vec<_>>();
+                    print_table(["ID", "WHEN", "DURATION", "CLIENT", "REQUEST"], &rows);
                 }
             }
         }
-        ("rm", Some(matches)) => {
-            let key = matches
-                .value_of("KEY")
+        ("replication", Some(matches)) => {
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+
+            let mut client = connection.open(address)?;
+            let info = match client.call(Request::ReplicationInfo)? {
+                Response::ReplicationInfo(info) => info,
+                response => {
+                    eprintln!("{}", error_of(response));
+                    process::exit(EXIT_SERVER_ERROR);
+                }
+            };
+
+            match output {
+                Output::Json => println!("{}", serde_json::to_string(&info)?),
+                Output::Raw | Output::Table => {
+                    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+                    println!("role: {:?}", info.role);
+                    println!("replid: {}", optional(info.replid));
+                    println!("offset: {}", info.offset);
+                    match info.role {
+                        Role::Leader => println!("followers: {}", info.followers),
+                        Role::Follower => {
+                            println!("leader: {}", optional(info.leader));
+                            println!("connected: {}", info.connected);
+                            println!("lag: {}", optional(info.lag.map(|lag| lag.to_string())));
+                            println!(
+                                "last contact: {}",
+                                optional(info.last_contact_ms.map(|ms| format!("{}ms ago", ms)))
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        ("cluster", Some(matches)) => {
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+
+            let mut client = connection.open(address)?;
+            let request = match (matches.value_of("ADD"), matches.value_of("REMOVE")) {
+                (Some(addr), _) => Request::AddMember { addr: addr.to_string() },
+                (_, Some(addr)) => Request::RemoveMember { addr: addr.to_string() },
+                (None, None) => Request::ClusterInfo,
+            };
+
+            match client.call(request)? {
+                Response::Members(Ok(members)) => match output {
+                    Output::Json => println!("{}", serde_json::to_string(&members)?),
+                    Output::Raw | Output::Table => members.iter().for_each(|member| println!("{}", member)),
+                },
+                Response::ClusterInfo(info) => match output {
+                    Output::Json => println!("{}", serde_json::to_string(&info)?),
+                    Output::Raw | Output::Table => {
+                        println!("id: {}", info.id);
+                        println!("role: {:?}", info.role);
+                        println!("term: {}", info.term);
+                        println!("leader: {}", info.leader.unwrap_or_else(|| "-".to_string()));
+                        println!("members: {}", info.members.join(","));
+                        println!("commit index: {}", info.commit_index);
+                        println!("last applied: {}", info.last_applied);
+                        println!("last index: {}", info.last_index);
+                        println!("snapshot index: {}", info.snapshot_index);
+                    }
+                },
+                response => {
+                    eprintln!("{}", error_of(response));
+                    process::exit(EXIT_SERVER_ERROR);
+                }
+            }
+        }
+        ("publish", Some(matches)) => {
+            let channel = matches
+                .value_of("CHANNEL")
                 .map(ToString::to_string)
-                .expect("KEY argument is missing");
+                .expect("CHANNEL argument is missing");
+            let message = matches
+                .value_of("MESSAGE")
+                .map(ToString::to_string)
+                .expect("MESSAGE argument is missing");
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+
+            let mut client = connection.open(address)?;
+            match client.call(Request::Publish { channel, message })? {
+                Response::Publish(Ok(receivers)) => println!("{}", receivers),
+                response => {
+                    eprintln!("{}", error_of(response));
+                    process::exit(EXIT_SERVER_ERROR);
+                }
+            }
+        }
+        ("subscribe", Some(matches)) => {
+            let values = |name| {
+                matches
+                    .values_of(name)
+                    .map(|values| values.map(ToString::to_string).collect::<Vec<String>>())
+                    .unwrap_or_default()
+            };
+            let (channels, patterns) = (values("CHANNEL"), values("PATTERN"));
             let address = matches
                 .value_of("IP-PORT")
                 .expect("IP-PORT argument is missing");
 
-            let mut client = KvsClient::connect(address)?;
-            if let Err(err) = client.remove(key) {
+            if let Err(err) = subscribe(address, &connection, channels, patterns, output) {
+                if is_connection_error(&err) {
+                    return Err(err);
+                }
                 eprintln!("{}", err);
+                process::exit(EXIT_SERVER_ERROR);
+            }
+        }
+        ("watch", Some(matches)) => {
+            let prefix = matches
+                .value_of("PREFIX")
+                .map(ToString::to_string)
+                .expect("PREFIX argument is missing");
+            let resume = match (matches.value_of("STREAM"), matches.value_of("SEQ")) {
+                (Some(stream), Some(seq)) => Some((stream.to_string(), seq.parse().unwrap_or_default())),
+                _ => None,
+            };
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+
+            if let Err(err) = watch(address, &connection, prefix, resume, output) {
+                if is_connection_error(&err) {
+                    return Err(err);
+                }
+                eprintln!("{}", err);
+                process::exit(EXIT_SERVER_ERROR);
+            }
+        }
+        ("repl", Some(matches)) => {
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+            repl(address, &connection)?;
+        }
+        ("batch", Some(matches)) => {
+            let address = matches
+                .value_of("IP-PORT")
+                .expect("IP-PORT argument is missing");
+            let path = matches.value_of("FILE").map(Path::new);
+            let format = match (matches.value_of("FORMAT"), path.and_then(Path::extension)) {
+                (Some("csv"), _) => Format::Csv,
+                (Some("jsonl"), _) => Format::JsonLines,
+                (Some(_), _) => Format::Command,
+                (None, Some(extension)) if extension == "csv" => Format::Csv,
+                (None, Some(extension)) if extension == "jsonl" || extension == "ndjson" => Format::JsonLines,
+                (None, _) => Format::Command,
+            };
+            let input: Box<dyn BufRead> = match path {
+                Some(path) => match File::open(path) {
+                    Ok(file) => Box::new(BufReader::new(file)),
+                    Err(err) => {
+                        eprintln!("{}: {}", path.display(), err);
+                        process::exit(1);
+                    }
+                },
+                None => Box::new(BufReader::new(io::stdin())),
+            };
+
+            let mut client = connection.open(address)?;
+            let summary = batch(&mut client, input, format, matches.is_present("stop-on-error"))?;
+            eprintln!("{} succeeded, {} failed", summary.succeeded, summary.failed);
+            if summary.failed > 0 {
                 process::exit(1);
             }
         }
+        ("", None) => repl(DEFAULT_ADDR, &connection)?,
         _ => unreachable!(),
     };
 
     Ok(())
 }
+
+/// How to reach the server, as given by the global options.
+struct Connection {
+    timeouts: Timeouts,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
+}
+
+impl Connection {
+    /// Connect the server, with TLS if its settings were given, and authenticate if credentials
+    /// were given.
+    fn open(&self, address: &str) -> Result<KvsClient> {
+        let mut client = match &self.tls {
+            Some(tls) => KvsClient::connect_tls(address, self.timeouts, tls.clone())?,
+            None => KvsClient::connect_with(address, self.timeouts)?,
+        };
+        if let Some(credentials) = &self.credentials {
+            client.authenticate(credentials.clone())?;
+        }
+        Ok(client)
+    }
+}
+
+const REPL_HELP: &str = "\
+set KEY VALUE    set the value of a key
+get KEY...       get the values of keys
+rm KEY...        remove keys
+scan [PREFIX]    list the keys starting with a prefix
+ping             check that the server is alive
+help             print this help
+quit             close the session
+
+Words may be quoted with \" or ', and escaped with \\ inside double quotes.";
+
+/// Run an interactive session over one connection, which is reopened if it breaks.
+fn repl(address: &str, connection: &Connection) -> Result<()> {
+    let mut client = Some(connection.open(address)?);
+    let mut editor = Editor::<()>::new();
+    let history = history_path();
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    let prompt = format!("{}> ", address);
+    loop {
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        };
+
+        let words = match split_words(&line) {
+            Some(words) if words.is_empty() => continue,
+            Some(words) => words,
+            None => {
+                editor.add_history_entry(line.as_str());
+                println!("(error) unbalanced quotes");
+                continue;
+            }
+        };
+        editor.add_history_entry(line.as_str());
+
+        let words = words.iter().map(String::as_str).collect::<Vec<&str>>();
+        match words[0].to_ascii_lowercase().as_str() {
+            "quit" | "exit" => break,
+            "help" => {
+                println!("{}", REPL_HELP);
+                continue;
+            }
+            _ => (),
+        }
+
+        let start = Instant::now();
+        let result = match client.take() {
+            Some(connected) => Ok(connected),
+            None => connection.open(address),
+        }
+        .and_then(|mut connected| {
+            let result = execute(&mut connected, &words);
+            client = Some(connected);
+            result
+        });
+
+        match result {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(err) => {
+                // The connection may be broken, so it is reopened before the next command.
+                if let ErrorKind::Io(_) | ErrorKind::Timeout(_) | ErrorKind::Serde(_) = err.kind() {
+                    client = None;
+                }
+                println!("(error) {}", err);
+            }
+        }
+        println!("({:.2?})", start.elapsed());
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+/// Run a command of the session, and return the lines to print.
+fn execute(client: &mut KvsClient, words: &[&str]) -> Result<Vec<String>> {
+    let to_strings = |words: &[&str]| words.iter().map(ToString::to_string).collect::<Vec<String>>();
+
+    match (words[0].to_ascii_lowercase().as_str(), &words[1..]) {
+        ("set", [key, value]) => {
+            client.set(key.to_string(), value.to_string())?;
+            Ok(vec!["OK".to_string()])
+        }
+        ("get", keys) if !keys.is_empty() => Ok(client
+            .get_many(to_strings(keys))?
+            .into_iter()
+            .map(|value| value.unwrap_or_else(|| "Key not found".to_string()))
+            .collect()),
+        ("rm", keys) if !keys.is_empty() => {
+            let removed = client.remove_many(to_strings(keys))?;
+            Ok(keys
+                .iter()
+                .zip(removed)
+                .map(|(key, removed)| match (removed, keys.len()) {
+                    (true, _) => "OK".to_string(),
+                    (false, 1) => "Key not found".to_string(),
+                    (false, _) => format!("Key not found: {}", key),
+                })
+                .collect())
+        }
+        ("scan", []) => client.scan(String::new()),
+        ("scan", [prefix]) => client.scan(prefix.to_string()),
+        ("ping", []) => client.ping().map(|_| vec!["PONG".to_string()]),
+        ("set", _) | ("get", _) | ("rm", _) | ("scan", _) | ("ping", _) => Ok(vec![format!(
+            "(error) wrong number of arguments for '{}', see help",
+            words[0]
+        )]),
+        _ => Ok(vec![format!("(error) unknown command '{}', see help", words[0])]),
+    }
+}
+
+/// Split a line into words separated by whitespace, where quoted words may contain whitespace.
+/// Return None if a quote is not closed.
+fn split_words(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return Some(words),
+        };
+
+        let mut word = String::new();
+        match first {
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => word.push(chars.next()?),
+                    c => word.push(c),
+                }
+            },
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    c => word.push(c),
+                }
+            },
+            c => {
+                word.push(c);
+                while let Some(c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                    word.push(*c);
+                    chars.next();
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".kvs_history"))
+}
+
+/// The format of the lines of a batch.
+#[derive(Clone, Copy)]
+enum Format {
+    Command,
+    Csv,
+    JsonLines,
+}
+
+#[derive(Deserialize)]
+struct Pair {
+    key: String,
+    value: String,
+}
+
+#[derive(Default)]
+struct Summary {
+    succeeded: usize,
+    failed: usize,
+}
+
+/// Pipeline the requests of every line, printing the values got and the failures.
+/// With `stop_on_error`, requests are sent one at a time, so that none is applied after the first
+/// failure.
+fn batch(client: &mut KvsClient, input: impl BufRead, format: Format, stop_on_error: bool) -> Result<Summary> {
+    let chunk = if stop_on_error { 1 } else { BATCH_CHUNK };
+    let mut summary = Summary::default();
+    let mut pending = Vec::with_capacity(chunk);
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        let requests = match parse_line(&line, format) {
+            Ok(requests) => requests.into_iter().map(Ok).collect(),
+            Err(message) => vec![Err(message)],
+        };
+
+        for request in requests {
+            pending.push((number + 1, request));
+            if pending.len() >= chunk {
+                send_batch(client, &mut pending, &mut summary)?;
+                if stop_on_error && summary.failed > 0 {
+                    return Ok(summary);
+                }
+            }
+        }
+    }
+
+    send_batch(client, &mut pending, &mut summary)?;
+    Ok(summary)
+}
+
+/// Send the pending requests, and report their outcomes along with the lines which could not
+/// be parsed, in the order of the lines.
+fn send_batch(
+    client: &mut KvsClient,
+    pending: &mut Vec<(usize, std::result::Result<Request, String>)>,
+    summary: &mut Summary,
+) -> Result<()> {
+    let mut pipeline = client.pipeline();
+    let mut lines = Vec::with_capacity(pending.len());
+    for (number, request) in pending.drain(..) {
+        match request {
+            Ok(request) => {
+                pipeline.request(request);
+                lines.push((number, None));
+            }
+            Err(message) => lines.push((number, Some(message))),
+        }
+    }
+    let mut responses = pipeline.execute()?.into_iter();
+
+    for (number, parse_error) in lines {
+        let result = match parse_error {
+            None => outcome(responses.next().expect("a response is missing")).map_err(|err| err.to_string()),
+            Some(message) => Err(message),
+        };
+
+        match result {
+            Ok(Some(value)) => {
+                println!("{}", value);
+                summary.succeeded += 1;
+            }
+            Ok(None) => summary.succeeded += 1,
+            Err(message) => {
+                eprintln!("line {}: {}", number, message);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a line into its requests, where blank lines and command lines starting with # have none.
+fn parse_line(line: &str, format: Format) -> std::result::Result<Vec<Request>, String> {
+    if line.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    match format {
+        Format::Command => {
+            let words = split_words(line).ok_or("unbalanced quotes")?;
+            let (command, args) = match words.split_first() {
+                Some((command, _)) if command.starts_with('#') => return Ok(Vec::new()),
+                Some((command, args)) => (command.to_ascii_lowercase(), args),
+                None => return Ok(Vec::new()),
+            };
+
+            match (command.as_str(), args) {
+                ("set", [key, value]) => Ok(vec![Request::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                }]),
+                ("get", keys) if !keys.is_empty() => Ok(keys.iter().map(|key| Request::Get { key: key.clone() }).collect()),
+                ("rm", keys) if !keys.is_empty() => Ok(keys
+                    .iter()
+                    .map(|key| Request::Remove { key: key.clone() })
+                    .collect()),
+                ("set", _) | ("get", _) | ("rm", _) => Err(format!("wrong number of arguments for '{}'", command)),
+                _ => Err(format!("unknown command '{}'", command)),
+            }
+        }
+        Format::Csv => match split_csv(line).as_deref() {
+            Some([key, value]) => Ok(vec![Request::Set {
+                key: key.clone(),
+                value: value.clone(),
+            }]),
+            _ => Err("expected a KEY,VALUE record".to_string()),
+        },
+        Format::JsonLines => {
+            let Pair { key, value } = serde_json::from_str(line).map_err(|err| err.to_string())?;
+            Ok(vec![Request::Set { key, value }])
+        }
+    }
+}
+
+/// Split a CSV record, where quoted fields may contain commas and doubled quotes.
+/// Return None if a quote is not closed.
+fn split_csv(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => break,
+                    c => field.push(c),
+                }
+            }
+        }
+        while let Some(c) = chars.next_if(|c| *c != ',') {
+            field.push(c);
+        }
+        fields.push(field);
+
+        if chars.next().is_none() {
+            return Some(fields);
+        }
+    }
+}
+
+/// Print the changes of a watch, one per line, telling on stderr where the watch starts so that
+/// it can be resumed.
+fn watch(address: &str, connection: &Connection, prefix: String, resume: Option<(String, u64)>, output: Output) -> Result<()> {
+    let resuming = resume.is_some();
+    let mut watch = connection.open(address)?.watch(prefix, resume)?;
+    if resuming && !watch.resumed() {
+        eprintln!("the server no longer has the changes to resume after, some changes were missed");
+    }
+    eprintln!("watching stream {} after seq {}", watch.stream(), watch.seq());
+
+    loop {
+        let change = watch.next_change()?;
+        match output {
+            Output::Json => println!("{}", serde_json::to_string(&change)?),
+            Output::Raw | Output::Table => match change.command {
+                Command::Set { key, value } => println!("{} set {} {}", change.seq, key, value),
+                Command::Remove { key } => println!("{} rm {}", change.seq, key),
+            },
+        }
+    }
+}
+
+/// Print the messages of a subscription, one per line after the channel it was published to.
+fn subscribe(address: &str, connection: &Connection, channels: Vec<String>, patterns: Vec<String>, output: Output) -> Result<()> {
+    let mut subscription = connection.open(address)?.subscribe(channels, patterns)?;
+    loop {
+        let message = subscription.next_message()?;
+        match output {
+            Output::Json => println!("{}", serde_json::to_string(&message)?),
+            Output::Raw | Output::Table => println!("{} {}", message.channel, message.message),
+        }
+    }
+}
+
+/// Tell whether an error means the connection failed or timed out, rather than the server refusing
+/// a request or replying something unreadable.
+fn is_connection_error(err: &Error) -> bool {
+    matches!(err.kind(), ErrorKind::Io(_) | ErrorKind::Timeout(_))
+}
+
+/// Tell whether a response succeeded, with the value of a get to print.
+fn outcome(response: Response) -> std::result::Result<Option<String>, ProtocolError> {
+    match response {
+        Response::Get(Ok(value)) => Ok(Some(value.unwrap_or_else(|| "Key not found".to_string()))),
+        Response::Set(Ok(_)) | Response::Remove(Ok(_)) => Ok(None),
+        Response::Get(Err(err))
+        | Response::Set(Err(err))
+        | Response::Remove(Err(err))
+        | Response::Error(err) => Err(err),
+        response => Err(error_of(response)),
+    }
+}
+
+/// Get the error of a response which failed, or of an unexpected response.
+fn error_of(response: Response) -> ProtocolError {
+    match response {
+        Response::Set(Err(err))
+        | Response::Get(Err(err))
+        | Response::Remove(Err(err))
+        | Response::GetMany(Err(err))
+        | Response::SetMany(Err(err))
+        | Response::RemoveMany(Err(err))
+        | Response::Scan(Err(err))
+        | Response::Members(Err(err))
+        | Response::Publish(Err(err))
+        | Response::Error(err) => err,
+        response => ProtocolError::new(ErrorCode::Internal, format!("unexpected response {:?}", response)),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Output {
+    Raw,
+    Json,
+    Table,
+}
+
+/// Format seconds such as `1d 2h 3m 4s`, leaving out the leading units which are zero.
+fn format_duration(secs: u64) -> String {
+    let units = [(secs / 86400, "d"), (secs / 3600 % 24, "h"), (secs / 60 % 60, "m"), (secs % 60, "s")];
+    let start = units.iter().position(|(value, _)| *value > 0).unwrap_or(units.len() - 1);
+    units[start..]
+        .iter()
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format microseconds such as `12.3ms`.
+fn format_micros(micros: u64) -> String {
+    match micros {
+        0..=999 => format!("{}us", micros),
+        1000..=999_999 => format!("{:.1}ms", micros as f64 / 1000.0),
+        _ => format!("{:.2}s", micros as f64 / 1_000_000.0),
+    }
+}
+
+/// Format bytes in binary units, such as `1.5 KiB`.
+fn format_bytes(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+/// The result of a command for one key.
+#[derive(Serialize)]
+struct Record {
+    key: String,
+    value: Option<String>,
+    exists: bool,
+    error: Option<ProtocolError>,
+}
+
+impl Record {
+    fn failed(keys: Vec<String>, error: ProtocolError) -> Vec<Record> {
+        keys.into_iter()
+            .map(|key| Record {
+                key,
+                value: None,
+                exists: false,
+                error: Some(error.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Print the records, where raw output prints values only if `values` is set,
+/// and prints errors on stderr.
+fn print_records(records: &[Record], output: Output, values: bool) {
+    match output {
+        Output::Raw => {
+            for record in records {
+                match (&record.error, &record.value) {
+                    (Some(err), _) if err.code == ErrorCode::NotFound && records.len() > 1 => {
+                        eprintln!("Key not found: {}", record.key)
+                    }
+                    (Some(err), _) => eprintln!("{}", err),
+                    (None, Some(value)) if values => println!("{}", value),
+                    (None, None) if values => println!("Key not found"),
+                    (None, _) => (),
+                }
+            }
+        }
+        Output::Json => {
+            for record in records {
+                println!("{}", serde_json::to_string(record).expect("records are serializable"));
+            }
+        }
+        Output::Table => {
+            let rows = records
+                .iter()
+                .map(|record| {
+                    [
+                        record.key.clone(),
+                        record.value.clone().unwrap_or_default(),
+                        record.exists.to_string(),
+                        record
+                            .error
+                            .as_ref()
+                            .map(|err| format!("{:?}: {}", err.code, err))
+                            .unwrap_or_default(),
+                    ]
+                })
+                .collect::<Vec<[String; 4]>>();
+            print_table(["KEY", "VALUE", "EXISTS", "ERROR"], &rows);
+        }
+    }
+}
+
+/// Print rows under a header, padding the cells of each column to the same width.
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let header = header.map(ToString::to_string);
+    let mut widths = [0; N];
+    for row in std::iter::once(&header).chain(rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in std::iter::once(&header).chain(rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<String>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Exit with the code telling the worst error of the records, if any, where any other error is worse
+/// than a key not found.
+fn exit_with(records: &[Record]) {
+    let errors = || records.iter().filter_map(|record| record.error.as_ref());
+    if errors().any(|err| err.code != ErrorCode::NotFound) {
+        process::exit(EXIT_SERVER_ERROR);
+    }
+    if errors().next().is_some() {
+        process::exit(EXIT_NOT_FOUND);
+    }
+}
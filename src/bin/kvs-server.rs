@@ -1,16 +1,24 @@
 use clap::*;
 use slog::*;
 
-use kvs::{KvStore, KvsEngine, KvsServer, Result, SledKvsEngine};
+use kvs::{
+    Auth, AuthConfig, Follower, KvStore, KvsEngine, KvsServer, Leader, LogConfig, LogFormat, LogOutput, Metrics, Raft,
+    RaftConfig, RaftNode, Replication, Result, ServerTls, ShardNode, Sharding, SledKvsEngine, TlsConfig,
+};
+use serde::Deserialize;
 use slog::Logger;
 use std::env::current_dir;
 use std::fs;
 use std::path::PathBuf;
 use std::process;
+use std::thread;
+use std::time::Duration;
 use kvs::thread_pool::{NaiveThreadPool, ThreadPool};
 
+// The config file is checked for changes this often.
+const CONFIG_POLL: Duration = Duration::from_secs(1);
+
 fn main() -> Result<()> {
-    let logger = get_logger();
     let matches = App::new("kvs-server")
         .version(crate_version!())
         .author(crate_authors!("\n"))
@@ -31,6 +39,20 @@ fn main() -> Result<()> {
                 .default_value("127.0.0.1:4000")
                 .help("a v4 or v6 IP address with a port number"),
         )
+        .arg(
+            Arg::with_name("SOCKET")
+                .long("unix")
+                .takes_value(true)
+                .conflicts_with_all(&["MEMBERS", "join", "sharded"])
+                .help("listen on a Unix socket of this path rather than on --addr, for clients on the same host"),
+        )
+        .arg(
+            Arg::with_name("MODE")
+                .long("unix-mode")
+                .default_value("660")
+                .validator(|value| u32::from_str_radix(&value, 8).map(|_| ()).map_err(|err| err.to_string()))
+                .help("the octal permissions of the Unix socket, which users need write permission to connect to"),
+        )
         .arg(
             Arg::with_name("ENGINE-NAME")
                 .short("e")
@@ -39,6 +61,137 @@ fn main() -> Result<()> {
                 .default_value("kvs")
                 .help("the key-value store engine name"),
         )
+        .arg(
+            Arg::with_name("PROTOCOL")
+                .short("p")
+                .long("protocol")
+                .possible_values(&["kvs", "resp"])
+                .default_value("kvs")
+                .help("the protocol spoken to clients, resp for redis clients"),
+        )
+        .arg(
+            Arg::with_name("HTTP-IP-PORT")
+                .long("http-addr")
+                .takes_value(true)
+                .help("serve the HTTP/JSON gateway on a v4 or v6 IP address with a port number"),
+        )
+        .arg(
+            Arg::with_name("METRICS-IP-PORT")
+                .long("metrics-addr")
+                .takes_value(true)
+                .help("serve Prometheus metrics on GET /metrics of a v4 or v6 IP address with a port number"),
+        )
+        .arg(
+            Arg::with_name("SECONDS")
+                .long("idle-timeout")
+                .default_value("300")
+                .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("close connections idle for this many seconds, 0 to never close them"),
+        )
+        .arg(
+            Arg::with_name("MILLISECONDS")
+                .long("slowlog-threshold")
+                .default_value("10")
+                .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("keep the requests taking at least this many milliseconds in the slow log"),
+        )
+        .arg(
+            Arg::with_name("ENTRIES-KEPT")
+                .long("slowlog-len")
+                .default_value("128")
+                .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("keep this many of the latest slow requests, 0 to keep none"),
+        )
+        .arg(
+            Arg::with_name("LEVEL")
+                .long("log-level")
+                .possible_values(&["critical", "error", "warning", "info", "debug", "trace"])
+                .default_value("info")
+                .help("log the lines at least as severe as this level, where requests are logged at debug"),
+        )
+        .arg(
+            Arg::with_name("FORMAT")
+                .long("log-format")
+                .possible_values(&["terminal", "json"])
+                .default_value("terminal")
+                .help("log lines for people to read, or a JSON object per line for log collectors"),
+        )
+        .arg(
+            Arg::with_name("LOG-FILE")
+                .long("log-file")
+                .takes_value(true)
+                .help("append the log to a file rather than writing it to stderr"),
+        )
+        .arg(
+            Arg::with_name("MEGABYTES")
+                .long("log-rotate-size")
+                .takes_value(true)
+                .requires("LOG-FILE")
+                .validator(|value| match value.parse::<u64>() {
+                    Ok(size) if size > 0 => Ok(()),
+                    _ => Err("the size must be a positive number".to_string()),
+                })
+                .help("rotate the log file to FILE.1, FILE.2... once it grows past this many megabytes"),
+        )
+        .arg(
+            Arg::with_name("FILES-KEPT")
+                .long("log-keep")
+                .default_value("5")
+                .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("keep this many rotated log files, removing the oldest"),
+        )
+        .arg(
+            Arg::with_name("log-values")
+                .long("log-values")
+                .help("log the values of requests and responses, which are left out of the log otherwise"),
+        )
+        .arg(
+            Arg::with_name("request-log")
+                .long("request-log")
+                .help("log every request and response at info from the start, as kvs-client admin request-log on does"),
+        )
+        .arg(
+            Arg::with_name("LEADER-IP-PORT")
+                .long("replica-of")
+                .takes_value(true)
+                .help("run as a read-only follower of the leader on a v4 or v6 IP address with a port number"),
+        )
+        .arg(
+            Arg::with_name("MEMBERS")
+                .long("cluster")
+                .takes_value(true)
+                .conflicts_with("LEADER-IP-PORT")
+                .help("run as a node of a Raft cluster of the nodes on comma-separated addresses, including --addr"),
+        )
+        .arg(
+            Arg::with_name("join")
+                .long("join")
+                .conflicts_with_all(&["LEADER-IP-PORT", "MEMBERS"])
+                .help("run as a node of a Raft cluster, waiting to be added as a member by the leader"),
+        )
+        .arg(
+            Arg::with_name("sharded")
+                .long("sharded")
+                .conflicts_with_all(&["LEADER-IP-PORT", "MEMBERS", "join"])
+                .help("run as a node of a sharded cluster, serving the shards given to --addr by kvs-admin"),
+        )
+        .arg(
+            Arg::with_name("ENTRIES")
+                .long("snapshot-threshold")
+                .default_value("10000")
+                .validator(|value| match value.parse::<u64>() {
+                    Ok(entries) if entries > 0 => Ok(()),
+                    _ => Err("the threshold must be a positive number".to_string()),
+                })
+                .help("compact the Raft log into a snapshot once it holds this many applied entries"),
+        )
+        .arg(
+            Arg::with_name("FILE")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help("read the secrets of the server from a JSON config file, which is reloaded when it changes"),
+        )
         .get_matches();
 
     if matches.is_present("version") {
@@ -46,32 +199,195 @@ fn main() -> Result<()> {
         process::exit(0);
     }
 
-    let addr = matches
-        .value_of("IP-PORT")
-        .expect("IP-PORT argument is missing.");
+    let logger = match log_config(&matches).logger() {
+        Ok(logger) => logger,
+        Err(err) => {
+            eprintln!("can not open the log: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let config = match matches.value_of("FILE") {
+        Some(path) => match read_config(path) {
+            Ok(config) => config,
+            Err(err) => {
+                error!(logger, "can not read the config file"; "path" => path, "error" => format!("{}", err));
+                process::exit(1);
+            }
+        },
+        None => Config::default(),
+    };
 
-    let engine = matches
-        .value_of("ENGINE-NAME")
-        .expect("ENGINE-NAME argument is missing.");
+    let options = Options {
+        addr: matches
+            .value_of("IP-PORT")
+            .expect("IP-PORT argument is missing."),
+        engine: matches
+            .value_of("ENGINE-NAME")
+            .expect("ENGINE-NAME argument is missing."),
+        protocol: matches
+            .value_of("PROTOCOL")
+            .expect("PROTOCOL argument is missing."),
+        unix: matches.value_of("SOCKET"),
+        unix_mode: matches
+            .value_of("MODE")
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .expect("MODE argument is missing."),
+        http_addr: matches.value_of("HTTP-IP-PORT"),
+        metrics_addr: matches.value_of("METRICS-IP-PORT"),
+        idle_timeout: matches
+            .value_of("SECONDS")
+            .and_then(|seconds| seconds.parse().ok())
+            .filter(|seconds| *seconds > 0)
+            .map(Duration::from_secs),
+        slowlog_threshold: matches
+            .value_of("MILLISECONDS")
+            .and_then(|ms| ms.parse().ok())
+            .map(Duration::from_millis)
+            .expect("MILLISECONDS argument is missing."),
+        slowlog_len: matches
+            .value_of("ENTRIES-KEPT")
+            .and_then(|len| len.parse().ok())
+            .expect("ENTRIES-KEPT argument is missing."),
+        replica_of: matches.value_of("LEADER-IP-PORT"),
+        cluster: match matches.value_of("MEMBERS") {
+            Some(members) => Some(members.split(',').map(|member| member.trim().to_string()).collect()),
+            None if matches.is_present("join") => Some(Vec::new()),
+            None => None,
+        },
+        sharded: matches.is_present("sharded"),
+        request_log: matches.is_present("request-log"),
+        log_values: matches.is_present("log-values"),
+        snapshot_threshold: matches
+            .value_of("ENTRIES")
+            .and_then(|entries| entries.parse().ok())
+            .expect("ENTRIES argument is missing."),
+        config_path: matches.value_of("FILE"),
+        config,
+    };
+
+    run(options, logger)
+}
 
-    run(addr, engine, logger)
+/// Options of the server given on the command line.
+struct Options<'a> {
+    addr: &'a str,
+    engine: &'a str,
+    protocol: &'a str,
+    /// The Unix socket listened on rather than the address.
+    unix: Option<&'a str>,
+    unix_mode: u32,
+    http_addr: Option<&'a str>,
+    metrics_addr: Option<&'a str>,
+    idle_timeout: Option<Duration>,
+    slowlog_threshold: Duration,
+    slowlog_len: usize,
+    replica_of: Option<&'a str>,
+    /// The members of a Raft cluster, empty for a node joining one.
+    cluster: Option<Vec<String>>,
+    sharded: bool,
+    request_log: bool,
+    log_values: bool,
+    snapshot_threshold: u64,
+    config_path: Option<&'a str>,
+    config: Config,
+}
+
+/// The settings of the server read from its config file, which keeps what should not be given
+/// on the command line.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    /// Require clients to authenticate with a password or a token.
+    auth: Option<AuthConfig>,
+    /// Require clients to connect with TLS.
+    tls: Option<TlsConfig>,
+}
+
+fn read_config(path: &str) -> Result<Config> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Reload the secrets and the access control list of the server whenever its config file is
+/// modified, keeping the ones in use if the file can not be read.
+fn reload_config(path: PathBuf, auth: Auth, logger: Logger) {
+    let modified = |path: &PathBuf| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let mut last = modified(&path);
+    loop {
+        thread::sleep(CONFIG_POLL);
+        let current = modified(&path);
+        if current == last {
+            continue;
+        }
+        last = current;
+
+        match read_config(&path.to_string_lossy()) {
+            Ok(Config { auth: Some(config), .. }) => {
+                auth.reload(config);
+                info!(logger, "reloaded the config file");
+            }
+            Ok(Config { auth: None, .. }) => {
+                error!(logger, "authentication can not be disabled without a restart, keeping the old config");
+            }
+            Err(err) => {
+                error!(logger, "can not reload the config file, keeping the old config"; "error" => format!("{}", err));
+            }
+        }
+    }
 }
 
-fn get_logger() -> Logger {
-    let drain = slog_term::TermDecorator::new().build();
-    let drain = slog_term::FullFormat::new(drain).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
+fn log_config(matches: &ArgMatches) -> LogConfig {
+    let level = match matches.value_of("LEVEL") {
+        Some("critical") => Level::Critical,
+        Some("error") => Level::Error,
+        Some("warning") => Level::Warning,
+        Some("debug") => Level::Debug,
+        Some("trace") => Level::Trace,
+        _ => Level::Info,
+    };
+    let format = match matches.value_of("FORMAT") {
+        Some("json") => LogFormat::Json,
+        _ => LogFormat::Terminal,
+    };
+    let keep = matches
+        .value_of("FILES-KEPT")
+        .and_then(|keep| keep.parse().ok())
+        .expect("FILES-KEPT argument is missing.");
+    let output = match (matches.value_of("LOG-FILE"), matches.value_of("MEGABYTES")) {
+        (Some(path), Some(size)) => LogOutput::Rotating {
+            path: PathBuf::from(path),
+            max_bytes: size.parse::<u64>().expect("MEGABYTES argument is invalid.") << 20,
+            keep,
+        },
+        (Some(path), None) => LogOutput::File(PathBuf::from(path)),
+        (None, _) => LogOutput::Stderr,
+    };
 
-    slog::Logger::root(drain, o!())
+    LogConfig { level, format, output }
 }
 
-fn run(addr: &str, engine: &str, logger: Logger) -> Result<()> {
+fn run(options: Options, logger: Logger) -> Result<()> {
+    let engine = options.engine;
     info!(logger, "kvs initializing";
         "version" => crate_version!(),
         "engine" => engine,
-         "ip" => addr
+        "protocol" => options.protocol,
+        "unix" => options.unix,
+        "http" => options.http_addr,
+        "metrics" => options.metrics_addr,
+        "replica_of" => options.replica_of,
+        "cluster" => options.cluster.as_ref().map(|members| members.join(",")),
+        "sharded" => options.sharded,
+        "auth" => options.config.auth.is_some(),
+        "tls" => options.config.tls.is_some(),
+         "ip" => options.addr
     );
 
+    if options.unix.is_some() && options.protocol == "resp" {
+        error!(logger, "the Unix socket only speaks the kvs protocol");
+        process::exit(1);
+    }
+
     let current_dir = current_dir()?;
     match current_engine(&current_dir)? {
         Some(e) if e != engine => {
@@ -84,8 +400,8 @@ fn run(addr: &str, engine: &str, logger: Logger) -> Result<()> {
     fs::write(current_dir.join("engine"), engine)?;
 
     match engine {
-        "kvs" => run_with_engine(KvStore::open(current_dir)?, addr, logger),
-        "sled" => run_with_engine(SledKvsEngine::open(current_dir)?, addr, logger),
+        "kvs" => run_with_engine(KvStore::open(&current_dir)?, current_dir, options, logger),
+        "sled" => run_with_engine(SledKvsEngine::open(&current_dir)?, current_dir, options, logger),
         _ => {
             eprintln!("Unsupported engine");
             process::exit(1);
@@ -93,10 +409,136 @@ fn run(addr: &str, engine: &str, logger: Logger) -> Result<()> {
     }
 }
 
-fn run_with_engine(engine: impl KvsEngine, addr: &str, logger: Logger) -> Result<()> {
-    let thread_pool = NaiveThreadPool::new(4)?;
-    let mut server = KvsServer::new(engine, thread_pool);
-    server.run(addr, logger)
+fn run_with_engine(engine: impl KvsEngine, dir: PathBuf, options: Options, logger: Logger) -> Result<()> {
+    // The nodes of a cluster share the config, so they authenticate to each other with it.
+    let credentials = options.config.auth.as_ref().and_then(AuthConfig::peer_credentials);
+    let tls = options.config.tls.as_ref().map(TlsConfig::peer_tls).transpose()?;
+    if let Some(members) = options.cluster.clone() {
+        let mut config = RaftConfig::new(options.addr, members);
+        config.snapshot_threshold = options.snapshot_threshold;
+        config.credentials = credentials;
+        config.tls = tls;
+        let node = RaftNode::start(engine, config, dir, logger.new(o!("raft" => options.addr.to_string())))?;
+        let services = Services {
+            raft: Some(node.raft()),
+            ..Services::default()
+        };
+        return serve_engine(node, services, options, logger);
+    }
+
+    match options.replica_of {
+        Some(leader) => {
+            let logger = logger.new(o!("leader" => leader.to_string()));
+            let follower = Follower::start(engine, leader, credentials, tls, dir, logger.clone())?;
+            let services = Services {
+                replication: Some(follower.replication()),
+                ..Services::default()
+            };
+            serve_engine(follower, services, options, logger)
+        }
+        None => {
+            let leader = Leader::new(engine);
+            let mut services = Services {
+                replication: Some(leader.replication()),
+                ..Services::default()
+            };
+            if options.sharded {
+                let node = ShardNode::open(
+                    leader,
+                    options.addr,
+                    credentials,
+                    tls,
+                    dir,
+                    logger.new(o!("shards" => options.addr.to_string())),
+                )?;
+                services.sharding = Some(node.sharding());
+                return serve_engine(node, services, options, logger);
+            }
+            serve_engine(leader, services, options, logger)
+        }
+    }
+}
+
+/// How the engine is replicated and sharded, which the server serves and reports.
+#[derive(Clone, Default)]
+struct Services {
+    replication: Option<Replication>,
+    raft: Option<Raft>,
+    sharding: Option<Sharding>,
+}
+
+fn serve_engine(engine: impl KvsEngine, services: Services, options: Options, logger: Logger) -> Result<()> {
+    // The listeners share the failures of clients to authenticate.
+    let auth = options.config.auth.clone().map(Auth::new);
+    let tls = options.config.tls.as_ref().map(ServerTls::new).transpose()?;
+    if let (Some(auth), Some(path)) = (auth.clone(), options.config_path) {
+        let (path, logger) = (PathBuf::from(path), logger.new(o!("config" => path.to_string())));
+        thread::spawn(move || reload_config(path, auth, logger));
+    }
+    let metrics = Metrics::new();
+    let new_server = |engine| -> Result<_> {
+        let server = KvsServer::new(engine, NaiveThreadPool::new(4)?)
+            .metrics(metrics.clone())
+            .slowlog(options.slowlog_threshold, options.slowlog_len)
+            .request_log(options.request_log)
+            .log_values(options.log_values);
+        let server = match auth.clone() {
+            Some(auth) => server.auth(auth),
+            None => server,
+        };
+        let server = match tls.clone() {
+            Some(tls) => server.tls(tls),
+            None => server,
+        };
+        let Services {
+            replication,
+            raft,
+            sharding,
+        } = services.clone();
+        let server = match replication {
+            Some(replication) => server.replication(replication),
+            None => server,
+        };
+        let server = match raft {
+            Some(raft) => server.raft(raft),
+            None => server,
+        };
+        let server = match sharding {
+            Some(sharding) => server.sharding(sharding),
+            None => server,
+        };
+        Ok(match options.idle_timeout {
+            Some(timeout) => server.idle_timeout(timeout),
+            None => server,
+        })
+    };
+
+    if let Some(http_addr) = options.http_addr {
+        let mut server = new_server(engine.clone())?;
+        let (http_addr, logger) = (http_addr.to_string(), logger.new(o!("listener" => "http")));
+        thread::spawn(move || {
+            if let Err(err) = server.run_http(&http_addr, logger.clone()) {
+                error!(logger, "HTTP gateway failed"; "error" => format!("{}", err));
+            }
+        });
+    }
+
+    if let Some(metrics_addr) = options.metrics_addr {
+        let mut server = new_server(engine.clone())?;
+        let (metrics_addr, logger) = (metrics_addr.to_string(), logger.new(o!("listener" => "metrics")));
+        thread::spawn(move || {
+            if let Err(err) = server.run_metrics(&metrics_addr, logger.clone()) {
+                error!(logger, "metrics endpoint failed"; "error" => format!("{}", err));
+            }
+        });
+    }
+
+    let mut server = new_server(engine)?;
+    match (options.unix, options.protocol) {
+        (Some(path), _) => server.unix_mode(options.unix_mode).run_unix(path, logger),
+        (None, "resp") => server.run_resp(options.addr, logger),
+        (None, _) => server.run(options.addr, logger),
+    }
 }
 
 fn current_engine(path: &PathBuf) -> Result<Option<String>> {
@@ -1,88 +1,775 @@
 use crate::error::{Error, ErrorKind};
-use crate::{Request, Response, Result};
-use serde_json::de::IoRead;
-use serde_json::StreamDeserializer;
-use std::io::{BufReader, BufWriter, Write};
-use std::net::TcpStream;
+use crate::protocol::ResponseFrame;
+use crate::stream::Stream;
+use crate::{
+    Change, ClientTls, ClusterInfo, Compaction, Credentials, Message, ReplicationInfo, Request, RequestLogState,
+    Response, Result, ServerInfo, ShardMap, SlowRequest, SubscribeEvent, WatchEvent,
+};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+// At most this many requests of a pipeline are in flight, so that neither peer blocks on
+// writing while the other one is not reading.
+const PIPELINE_WINDOW: usize = 1024;
+// A request is sent again to at most this many servers it is redirected to.
+pub(crate) const MAX_REDIRECTS: usize = 5;
+
+/// Timeouts of a client connection, where None waits forever.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    /// How long to wait for the connection to be established.
+    pub connect: Option<Duration>,
+    /// How long to wait for a response.
+    pub read: Option<Duration>,
+    /// How long to wait for a request to be sent.
+    pub write: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Use the same timeout to connect, read and write.
+    pub fn all(timeout: Duration) -> Self {
+        Timeouts {
+            connect: Some(timeout),
+            read: Some(timeout),
+            write: Some(timeout),
+        }
+    }
+}
+
+/// The prefix of the addresses of Unix sockets, such as `unix:/run/kvs.sock`.
+const UNIX_PREFIX: &str = "unix:";
 
 /// The client of key-value store.
-pub struct KvsClient<'de> {
-    reader: StreamDeserializer<'de, IoRead<BufReader<TcpStream>>, Response>,
-    writer: BufWriter<TcpStream>,
+pub struct KvsClient {
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>,
+    next_id: u64,
+    timeouts: Timeouts,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
 }
 
-impl<'de> KvsClient<'de> {
-    /// Connect the remote server, and get a new key-value store client.
-    pub fn connect(addr: &str) -> Result<KvsClient<'de>> {
-        let stream = TcpStream::connect(addr)?;
+impl KvsClient {
+    /// Connect the remote server, and get a new key-value store client. An address such as
+    /// `unix:/run/kvs.sock` connects the Unix socket of a server on the same host.
+    pub fn connect(addr: &str) -> Result<KvsClient> {
+        KvsClient::connect_with(addr, Timeouts::default())
+    }
+
+    /// Connect the remote server with given timeouts, and get a new key-value store client.
+    /// Requests which time out return an error of `ErrorKind::Timeout`,
+    /// after which the client should be dropped.
+    pub fn connect_with(addr: &str, timeouts: Timeouts) -> Result<KvsClient> {
+        KvsClient::open(addr, timeouts, None)
+    }
+
+    /// Connect the remote server with TLS and given timeouts, and get a new key-value store
+    /// client. The servers the client is redirected to are also connected with TLS.
+    pub fn connect_tls(addr: &str, timeouts: Timeouts, tls: ClientTls) -> Result<KvsClient> {
+        KvsClient::open(addr, timeouts, Some(&tls))
+    }
+
+    /// Connect the Unix socket of a server on the same host, and get a new key-value store
+    /// client.
+    pub fn connect_unix(path: impl AsRef<Path>) -> Result<KvsClient> {
+        KvsClient::connect_with(&format!("{}{}", UNIX_PREFIX, path.as_ref().display()), Timeouts::default())
+    }
+
+    // Unix sockets are never encrypted, so that the TLS settings only apply to TCP addresses.
+    fn open(addr: &str, timeouts: Timeouts, tls: Option<&ClientTls>) -> Result<KvsClient> {
+        let stream = if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+            Stream::Unix(UnixStream::connect(path)?)
+        } else {
+            let stream = match timeouts.connect {
+                Some(timeout) => connect_timeout(addr, timeout)?,
+                None => TcpStream::connect(addr)?,
+            };
+            match tls {
+                Some(tls) => tls.connect(addr, stream)?,
+                None => Stream::Tcp(stream),
+            }
+        };
+        stream.set_timeouts(timeouts.read, timeouts.write)?;
+
         let writer = BufWriter::new(stream.try_clone()?);
         let reader = BufReader::new(stream);
-        let reader = serde_json::de::Deserializer::from_reader(reader).into_iter::<Response>();
 
-        Ok(KvsClient { reader, writer })
+        Ok(KvsClient {
+            reader,
+            writer,
+            next_id: 0,
+            timeouts,
+            credentials: None,
+            tls: tls.cloned(),
+        })
+    }
+
+    /// Connect the remote server with given timeouts, with TLS if given its settings, and
+    /// authenticate with given credentials if any.
+    pub(crate) fn connect_as(
+        addr: &str,
+        timeouts: Timeouts,
+        credentials: Option<&Credentials>,
+        tls: Option<&ClientTls>,
+    ) -> Result<KvsClient> {
+        let mut client = KvsClient::open(addr, timeouts, tls)?;
+        if let Some(credentials) = credentials {
+            client.authenticate(credentials.clone())?;
+        }
+        Ok(client)
+    }
+
+    /// Authenticate the connection, and return the user it is authenticated as.
+    /// The credentials are also sent to the servers the client is redirected to.
+    pub fn authenticate(&mut self, credentials: Credentials) -> Result<String> {
+        let user = self
+            .call_once(&Request::Auth {
+                credentials: credentials.clone(),
+            })?
+            .into_auth()?;
+        self.credentials = Some(credentials);
+        Ok(user)
     }
 
     /// Sets the value of a string key to a string.
     /// Return an error if the value is not written successfully.
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
-        let request = Request::Set { key, value };
-        let request = serde_json::to_vec(&request)?;
-
-        self.writer.write_all(&request.as_slice())?;
-        self.writer.flush()?;
-
-        let next = self.reader.next().ok_or(ErrorKind::UnexpectedError(
-            "Can not deserialize next response",
-        ))?;
-        match next? {
-            Response::Set(Ok(_)) => Ok(()),
-            Response::Set(Err(err)) => Err(Error::from(ErrorKind::StringError(err))),
-            _ => Err(Error::from(ErrorKind::UnexpectedError(
-                "Client received an unexpected response",
-            ))),
-        }
+        self.call(Request::Set { key, value })?.into_set()
     }
 
     /// Gets the string value of the a string key.
     /// If the key does not exist, return None.
     /// Return an error if the value is not read successfully.
     pub fn get(&mut self, key: String) -> Result<Option<String>> {
-        let request = Request::Get { key };
-        let request = serde_json::to_vec(&request)?;
-
-        self.writer.write_all(&request.as_slice())?;
-        self.writer.flush()?;
-
-        let next = self.reader.next().ok_or(ErrorKind::UnexpectedError(
-            "Can not deserialize next response",
-        ))?;
-        match next? {
-            Response::Get(Ok(content)) => Ok(content),
-            Response::Get(Err(err)) => Err(Error::from(ErrorKind::StringError(err))),
-            _ => Err(Error::from(ErrorKind::UnexpectedError(
-                "Client received an unexpected response",
-            ))),
-        }
+        self.call(Request::Get { key })?.into_get()
     }
 
     /// Removes a given key.
     /// Return an error if the key does not exist or is not removed successfully.
     pub fn remove(&mut self, key: String) -> Result<()> {
-        let request = Request::Remove { key };
-        let request = serde_json::to_vec(&request)?;
+        self.call(Request::Remove { key })?.into_remove()
+    }
+
+    /// Gets the string values of many string keys with one request, in the order of the keys.
+    /// The value of a key which does not exist is None.
+    pub fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.call(Request::GetMany { keys })?.into_get_many()
+    }
+
+    /// Sets the values of many string keys with one request.
+    pub fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.call(Request::SetMany { pairs })?.into_set_many()
+    }
+
+    /// Removes many keys with one request, and tells whether each key existed.
+    pub fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<bool>> {
+        self.call(Request::RemoveMany { keys })?.into_remove_many()
+    }
 
-        self.writer.write_all(&request.as_slice())?;
+    /// Gets all keys starting with a given prefix, in sorted order.
+    pub fn scan(&mut self, prefix: String) -> Result<Vec<String>> {
+        self.call(Request::Scan { prefix })?.into_scan()
+    }
+
+    /// Gets the replication state of the server, such as how far a follower is behind its leader.
+    pub fn replication_info(&mut self) -> Result<ReplicationInfo> {
+        self.call(Request::ReplicationInfo)?.into_replication_info()
+    }
+
+    /// Gets the state of the server, such as its engine, its uptime and how many requests of each
+    /// type it replied.
+    pub fn info(&mut self) -> Result<ServerInfo> {
+        self.call(Request::Info)?.into_info()
+    }
+
+    /// Compacts the storage of the engine of the server, and returns how much space it reclaimed.
+    pub fn compact(&mut self) -> Result<Compaction> {
+        self.call(Request::Compact)?.into_compaction()
+    }
+
+    /// Gets the requests which took longer than the threshold of the slow log of the server,
+    /// newest first, and clears it if `reset` is set.
+    pub fn slowlog(&mut self, reset: bool) -> Result<Vec<SlowRequest>> {
+        self.call(Request::SlowLog { reset })?.into_slowlog()
+    }
+
+    /// Switches the request log of the server for every connection, or for the connections of a
+    /// client given by its address with or without a port, and returns the connections logged.
+    pub fn request_log(&mut self, enabled: bool, client: Option<&str>) -> Result<RequestLogState> {
+        let client = client.map(str::to_string);
+        self.call(Request::RequestLog { enabled, client })?.into_request_log()
+    }
+
+    /// Gets the Raft state of a node of a cluster, such as its role and the leader.
+    pub fn cluster_info(&mut self) -> Result<ClusterInfo> {
+        self.call(Request::ClusterInfo)?.into_cluster_info()
+    }
+
+    /// Adds a member to a Raft cluster, and returns the members once the change is committed.
+    pub fn add_member(&mut self, addr: String) -> Result<Vec<String>> {
+        self.call(Request::AddMember { addr })?.into_members()
+    }
+
+    /// Removes a member from a Raft cluster, and returns the members once the change is committed.
+    pub fn remove_member(&mut self, addr: String) -> Result<Vec<String>> {
+        self.call(Request::RemoveMember { addr })?.into_members()
+    }
+
+    /// Gets the shard map of a node of a sharded cluster.
+    pub fn shard_map(&mut self) -> Result<ShardMap> {
+        self.call(Request::ShardMap)?.into_shard_map()
+    }
+
+    /// Replaces the shard map of a node of a sharded cluster by a newer version, and returns the
+    /// map of the node after the request.
+    pub fn set_shard_map(&mut self, map: ShardMap) -> Result<ShardMap> {
+        self.call(Request::SetShardMap { map })?.into_shard_map()
+    }
+
+    /// Moves a shard to another node through the node serving it, and returns the shard map once
+    /// the move is done.
+    pub fn move_shard(&mut self, shard: usize, to: String) -> Result<ShardMap> {
+        self.call(Request::MoveShard { shard, to })?.into_shard_map()
+    }
+
+    pub(crate) fn import_shard(
+        &mut self,
+        shard: usize,
+        first: bool,
+        pairs: Vec<(String, String)>,
+        removed: Vec<String>,
+    ) -> Result<()> {
+        let request = Request::ImportShard {
+            shard,
+            first,
+            pairs,
+            removed,
+        };
+        self.call(request)?.into_import_shard()
+    }
+
+    /// Publishes a message to a channel, and returns the number of subscribers connected to the
+    /// server which it is delivered to.
+    pub fn publish(&mut self, channel: String, message: String) -> Result<usize> {
+        self.call(Request::Publish { channel, message })?.into_publish()
+    }
+
+    /// Subscribe given channels, and the channels matching given glob-style patterns, turning the
+    /// connection into a stream of the messages published to them from now on.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use kvs::{KvsClient, Result};
+    /// # fn main() -> Result<()> {
+    /// let client = KvsClient::connect("127.0.0.1:4000")?;
+    /// let mut subscription = client.subscribe(vec!["news".to_string()], vec!["user:*".to_string()])?;
+    /// loop {
+    ///     let message = subscription.next_message()?;
+    ///     println!("{}: {}", message.channel, message.message);
+    /// }
+    /// # }
+    /// ```
+    pub fn subscribe(mut self, channels: Vec<String>, patterns: Vec<String>) -> Result<Subscription> {
+        let id = self.send(&Request::Subscribe { channels, patterns })?;
+        self.flush()?;
+
+        let frame = self.receive()?;
+        match frame.response {
+            Response::Subscribe(SubscribeEvent::Subscribed { .. }) if frame.id == id => Ok(Subscription {
+                client: self,
+                id,
+                pending: VecDeque::new(),
+            }),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    /// Checks that the server is alive and serving this connection.
+    pub fn ping(&mut self) -> Result<()> {
+        self.call(Request::Ping)?.into_pong()
+    }
+
+    /// Start a pipeline, which sends many requests before reading any of their responses.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use kvs::{KvsClient, Result};
+    /// # fn main() -> Result<()> {
+    /// let mut client = KvsClient::connect("127.0.0.1:4000")?;
+    /// let responses = client
+    ///     .pipeline()
+    ///     .set("key".to_string(), "value".to_string())
+    ///     .get("key".to_string())
+    ///     .execute()?;
+    /// assert_eq!(responses.len(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pipeline(&mut self) -> Pipeline<'_> {
+        Pipeline {
+            client: self,
+            requests: Vec::new(),
+        }
+    }
+
+    /// Watch the changes of keys starting with a given prefix, turning the connection into a
+    /// stream of changes. Given the stream and the seq of the last change of a previous watch,
+    /// the watch resumes after it if the server still has the changes since.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use kvs::{KvsClient, Result};
+    /// # fn main() -> Result<()> {
+    /// let mut watch = KvsClient::connect("127.0.0.1:4000")?.watch("user:".to_string(), None)?;
+    /// loop {
+    ///     let change = watch.next_change()?;
+    ///     println!("{} {:?}", change.seq, change.command);
+    /// }
+    /// # }
+    /// ```
+    pub fn watch(mut self, prefix: String, resume: Option<(String, u64)>) -> Result<Watch> {
+        let (stream, seq) = match resume {
+            Some((stream, seq)) => (Some(stream), seq),
+            None => (None, 0),
+        };
+        let id = self.send(&Request::Watch { prefix, stream, seq })?;
+        self.flush()?;
+
+        let frame = self.receive()?;
+        match frame.response {
+            Response::Watch(WatchEvent::Start { stream, seq, resumed }) if frame.id == id => Ok(Watch {
+                client: self,
+                id,
+                stream,
+                seq,
+                resumed,
+                pending: VecDeque::new(),
+            }),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    /// Check without blocking whether the server closed the connection, which happens to idle
+    /// connections when the server restarts.
+    pub(crate) fn is_closed(&self) -> bool {
+        // Nothing is expected from the server, so any readable data means the connection is unusable.
+        !self.reader.buffer().is_empty() || self.reader.get_ref().is_closed()
+    }
+
+    /// Send a request and wait for its response.
+    /// An error returned here means the connection failed, while errors of the request
+    /// itself are carried by the response.
+    ///
+    /// A request refused by a follower of a Raft cluster is sent again to the leader, to which
+    /// the client stays connected.
+    pub fn call(&mut self, request: Request) -> Result<Response> {
+        let mut response = self.call_once(&request)?;
+        for _ in 0..MAX_REDIRECTS {
+            let addr = match response.error().and_then(|err| err.redirect.as_ref()) {
+                Some(addr) => addr.clone(),
+                None => break,
+            };
+
+            *self = KvsClient::connect_as(&addr, self.timeouts, self.credentials.as_ref(), self.tls.as_ref())?;
+            response = self.call_once(&request)?;
+        }
+
+        Ok(response)
+    }
+
+    pub(crate) fn call_once(&mut self, request: &Request) -> Result<Response> {
+        let id = self.send(request)?;
         self.writer.flush()?;
 
-        let next = self.reader.next().ok_or(ErrorKind::UnexpectedError(
-            "Can not deserialize next response",
+        let frame = self.receive()?;
+        if frame.id != id {
+            return Err(Error::from(ErrorKind::UnexpectedError(
+                "Client received a response to another request",
+            )));
+        }
+
+        Ok(frame.response)
+    }
+
+    pub(crate) fn send(&mut self, request: &Request) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        serde_json::to_writer(&mut self.writer, &RequestFrameRef { id, request })?;
+        self.writer.write_all(b"\n")?;
+        Ok(id)
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub(crate) fn receive(&mut self) -> Result<ResponseFrame> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The server closed the connection",
+            )));
+        }
+
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// Serialized as a `RequestFrame`, without taking the request.
+#[derive(Serialize)]
+struct RequestFrameRef<'a> {
+    id: u64,
+    request: &'a Request,
+}
+
+/// A batch of requests sent over one connection without waiting for each response.
+///
+/// Created by [`KvsClient::pipeline`].
+pub struct Pipeline<'a> {
+    client: &'a mut KvsClient,
+    requests: Vec<Request>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Queue a request setting the value of a string key to a string.
+    pub fn set(&mut self, key: String, value: String) -> &mut Self {
+        self.request(Request::Set { key, value })
+    }
+
+    /// Queue a request getting the string value of a string key.
+    pub fn get(&mut self, key: String) -> &mut Self {
+        self.request(Request::Get { key })
+    }
+
+    /// Queue a request removing a given key.
+    pub fn remove(&mut self, key: String) -> &mut Self {
+        self.request(Request::Remove { key })
+    }
+
+    /// Queue any request.
+    pub fn request(&mut self, request: Request) -> &mut Self {
+        self.requests.push(request);
+        self
+    }
+
+    /// Get the number of queued requests.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Return true if no request is queued.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Send all queued requests, and return their responses in the order of the requests.
+    /// Errors of single requests are reported in their responses, while the returned error
+    /// means the connection failed. Unlike `KvsClient::call`, redirected requests are not
+    /// sent again.
+    pub fn execute(&mut self) -> Result<Vec<Response>> {
+        let requests = std::mem::take(&mut self.requests);
+        let mut ids = HashMap::with_capacity(requests.len());
+        let mut responses = Vec::with_capacity(requests.len());
+        responses.resize_with(requests.len(), || None);
+
+        let mut in_flight = 0;
+        for (index, request) in requests.iter().enumerate() {
+            ids.insert(self.client.send(request)?, index);
+            in_flight += 1;
+
+            if in_flight == PIPELINE_WINDOW {
+                self.client.writer.flush()?;
+                while in_flight > PIPELINE_WINDOW / 2 {
+                    self.receive(&mut ids, &mut responses)?;
+                    in_flight -= 1;
+                }
+            }
+        }
+
+        self.client.writer.flush()?;
+        for _ in 0..in_flight {
+            self.receive(&mut ids, &mut responses)?;
+        }
+
+        Ok(responses.into_iter().flatten().collect())
+    }
+
+    fn receive(&mut self, ids: &mut HashMap<u64, usize>, responses: &mut [Option<Response>]) -> Result<()> {
+        let frame = self.client.receive()?;
+        let index = ids.remove(&frame.id).ok_or(ErrorKind::UnexpectedError(
+            "Client received a response to another request",
         ))?;
-        match next? {
+
+        responses[index] = Some(frame.response);
+        Ok(())
+    }
+}
+
+/// The changes of the keys starting with a prefix, which a server pushes as they are committed.
+///
+/// Created by [`KvsClient::watch`]. The server sends a heartbeat every second when nothing
+/// changes, so a read timeout of the client longer than that detects a dead server.
+pub struct Watch {
+    client: KvsClient,
+    id: u64,
+    stream: String,
+    seq: u64,
+    resumed: bool,
+    pending: VecDeque<Change>,
+}
+
+impl Watch {
+    /// Get the id of the change feed of the server, which is sent with the seq of the last change
+    /// to resume the watch.
+    pub fn stream(&self) -> &str {
+        &self.stream
+    }
+
+    /// Get the seq of the last change returned, or the one the watch started after.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Return true if the watch resumed after the position it was given. Otherwise, the changes
+    /// before the watch started were missed.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Wait for the next change of a watched key.
+    /// Return an error if the connection fails, or if the server drops a watcher which fell behind.
+    pub fn next_change(&mut self) -> Result<Change> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                self.seq = change.seq;
+                return Ok(change);
+            }
+
+            let frame = self.client.receive()?;
+            match frame.response {
+                Response::Watch(WatchEvent::Changes(changes)) if frame.id == self.id => self.pending.extend(changes),
+                Response::Error(err) => return Err(Error::from(err)),
+                _ => return unexpected(),
+            }
+        }
+    }
+}
+
+/// The messages published to the channels subscribed, which a server pushes as they are published.
+///
+/// Created by [`KvsClient::subscribe`]. The server sends a heartbeat every second when nothing
+/// is published, so a read timeout of the client longer than that detects a dead server.
+pub struct Subscription {
+    client: KvsClient,
+    id: u64,
+    pending: VecDeque<Message>,
+}
+
+impl Subscription {
+    /// Wait for the next message.
+    /// Return an error if the connection fails, or if the server drops a subscriber which fell
+    /// behind.
+    pub fn next_message(&mut self) -> Result<Message> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Ok(message);
+            }
+
+            let frame = self.client.receive()?;
+            match frame.response {
+                Response::Subscribe(SubscribeEvent::Messages(messages)) if frame.id == self.id => {
+                    self.pending.extend(messages)
+                }
+                Response::Error(err) => return Err(Error::from(err)),
+                _ => return unexpected(),
+            }
+        }
+    }
+}
+
+/// Try every address the host name resolves to, as `TcpStream::connect` does.
+fn connect_timeout(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+    }))
+}
+
+fn unexpected<T>() -> Result<T> {
+    Err(Error::from(ErrorKind::UnexpectedError(
+        "Client received an unexpected response",
+    )))
+}
+
+impl Response {
+    pub(crate) fn into_auth(self) -> Result<String> {
+        match self {
+            Response::Auth(Ok(user)) => Ok(user),
+            Response::Auth(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_set(self) -> Result<()> {
+        match self {
+            Response::Set(Ok(_)) => Ok(()),
+            Response::Set(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_get(self) -> Result<Option<String>> {
+        match self {
+            Response::Get(Ok(content)) => Ok(content),
+            Response::Get(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_remove(self) -> Result<()> {
+        match self {
             Response::Remove(Ok(_)) => Ok(()),
-            Response::Remove(Err(err)) => Err(Error::from(ErrorKind::StringError(err))),
-            _ => Err(Error::from(ErrorKind::UnexpectedError(
-                "Client received an unexpected response",
-            ))),
+            Response::Remove(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_get_many(self) -> Result<Vec<Option<String>>> {
+        match self {
+            Response::GetMany(Ok(values)) => Ok(values),
+            Response::GetMany(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_set_many(self) -> Result<()> {
+        match self {
+            Response::SetMany(Ok(_)) => Ok(()),
+            Response::SetMany(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_remove_many(self) -> Result<Vec<bool>> {
+        match self {
+            Response::RemoveMany(Ok(removed)) => Ok(removed),
+            Response::RemoveMany(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_scan(self) -> Result<Vec<String>> {
+        match self {
+            Response::Scan(Ok(keys)) => Ok(keys),
+            Response::Scan(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_replication_info(self) -> Result<ReplicationInfo> {
+        match self {
+            Response::ReplicationInfo(info) => Ok(info),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_info(self) -> Result<ServerInfo> {
+        match self {
+            Response::Info(info) => Ok(info),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_compaction(self) -> Result<Compaction> {
+        match self {
+            Response::Compact(Ok(compaction)) => Ok(compaction),
+            Response::Compact(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_slowlog(self) -> Result<Vec<SlowRequest>> {
+        match self {
+            Response::SlowLog(entries) => Ok(entries),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_request_log(self) -> Result<RequestLogState> {
+        match self {
+            Response::RequestLog(state) => Ok(state),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_cluster_info(self) -> Result<ClusterInfo> {
+        match self {
+            Response::ClusterInfo(info) => Ok(info),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_members(self) -> Result<Vec<String>> {
+        match self {
+            Response::Members(Ok(members)) => Ok(members),
+            Response::Members(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_shard_map(self) -> Result<ShardMap> {
+        match self {
+            Response::ShardMap(Ok(map)) => Ok(map),
+            Response::ShardMap(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_import_shard(self) -> Result<()> {
+        match self {
+            Response::ImportShard(Ok(())) => Ok(()),
+            Response::ImportShard(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_publish(self) -> Result<usize> {
+        match self {
+            Response::Publish(Ok(receivers)) => Ok(receivers),
+            Response::Publish(Err(err)) | Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
+        }
+    }
+
+    pub(crate) fn into_pong(self) -> Result<()> {
+        match self {
+            Response::Pong => Ok(()),
+            Response::Error(err) => Err(Error::from(err)),
+            _ => unexpected(),
         }
     }
 }
@@ -1,8 +1,10 @@
-use crate::engine::KvsEngine;
+use crate::engine::{EngineStats, KvsEngine};
 use crate::error::{Error, ErrorKind, Result};
+use crate::replication;
+use crate::watch::ChangeFeed;
 use serde::{Deserialize, Serialize};
 use serde_json::Deserializer;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
@@ -39,6 +41,7 @@ pub struct KvStore {
     writer: Arc<Mutex<KvStoreWriter>>,
     reader: KvStoreReader,
     index: Arc<RwLock<HashMap<String, CommandOffset>>>,
+    feed: ChangeFeed,
 }
 
 impl KvStore {
@@ -66,7 +69,8 @@ impl KvStore {
         let (new_writer, new_reader) = new_db_log(&db_path(&path, current_gen))?;
         reader.add_reader(&current_gen, new_reader);
 
-        let writer = KvStoreWriter::new(Arc::clone(&path), new_writer, reader.clone(), Arc::clone(&index), current_gen)?;
+        let feed = ChangeFeed::new();
+        let writer = KvStoreWriter::new(Arc::clone(&path), new_writer, reader.clone(), Arc::clone(&index), current_gen, feed.clone())?;
         let writer = Arc::new(Mutex::new(writer));
 
         Ok(KvStore {
@@ -74,6 +78,7 @@ impl KvStore {
             writer,
             reader,
             index,
+            feed,
         })
     }
 
@@ -91,6 +96,7 @@ impl Clone for KvStore {
             writer: Arc::clone(&self.writer),
             reader: self.reader.clone(),
             index: Arc::clone(&self.index),
+            feed: self.feed.clone(),
         }
     }
 }
@@ -159,6 +165,128 @@ impl KvsEngine for KvStore {
     fn remove(&self, key: String) -> Result<()> {
         self.writer.lock().unwrap().remove(key)
     }
+
+    /// Gets the string values of many string keys, reading a consistent view of the store.
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        let index = self.index.read().unwrap();
+        keys.iter()
+            .map(|key| match index.get(key) {
+                Some(offset) => match self.reader.read_command(offset)? {
+                    Command::Set { key: _, value } => Ok(Some(value)),
+                    Command::Remove { .. } => unreachable!(),
+                },
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Sets the values of many string keys, flushing the log only once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use kvs::KvStore;
+    /// # use kvs::KvsEngine;
+    /// # use tempfile::TempDir;
+    /// # let dir = TempDir::new().unwrap();
+    /// let kvs = KvStore::open(dir.path()).unwrap();
+    /// kvs.set_many(vec![
+    ///     ("key1".to_string(), "value1".to_string()),
+    ///     ("key2".to_string(), "value2".to_string()),
+    /// ]).unwrap();
+    ///
+    /// let values = kvs.get_many(vec!["key1".to_string(), "key3".to_string()]).unwrap();
+    /// assert_eq!(values, vec![Some("value1".to_string()), None]);
+    /// ```
+    fn set_many(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.writer.lock().unwrap().set_many(pairs)
+    }
+
+    /// Removes many keys, flushing the log only once.
+    fn remove_many(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        self.writer.lock().unwrap().remove_many(keys)
+    }
+
+    /// Gets all keys starting with a given prefix, in ascending order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use kvs::KvStore;
+    /// # use kvs::KvsEngine;
+    /// # use tempfile::TempDir;
+    /// # let dir = TempDir::new().unwrap();
+    /// let kvs = KvStore::open(dir.path()).unwrap();
+    /// kvs.set("user:2".to_string(), "bob".to_string());
+    /// kvs.set("user:1".to_string(), "alice".to_string());
+    /// kvs.set("item:1".to_string(), "apple".to_string());
+    ///
+    /// let keys = kvs.scan("user:".to_string()).unwrap();
+    /// assert_eq!(keys, vec!["user:1".to_string(), "user:2".to_string()]);
+    /// ```
+    fn scan(&self, prefix: String) -> Result<Vec<String>> {
+        let mut keys = self
+            .index
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// Gets at most `limit` keys following a given key, sorting only those rather than every key.
+    fn scan_after(&self, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        let index = self.index.read().unwrap();
+        let mut keys = index
+            .keys()
+            .filter(|key| after.as_ref().is_none_or(|after| *key > after))
+            .collect::<Vec<&String>>();
+
+        if keys.len() > limit {
+            if limit > 0 {
+                keys.select_nth_unstable(limit - 1);
+            }
+            keys.truncate(limit);
+        }
+        keys.sort_unstable();
+        Ok(keys.into_iter().cloned().collect())
+    }
+
+    /// Gets the feed of the writes committed to the store since it was opened.
+    fn changes(&self) -> Option<ChangeFeed> {
+        Some(self.feed.clone())
+    }
+
+    /// Compacts the log into a new generation, and removes the generations it replaces.
+    fn compact(&self) -> Result<()> {
+        KvStore::compact(self)
+    }
+
+    /// Gets the size of the index and of the log of the store.
+    fn stats(&self) -> Option<EngineStats> {
+        let (uncompacted_bytes, generation, compactions) = {
+            let writer = self.writer.lock().unwrap();
+            (writer.uncompacted, writer.current_gen, writer.compactions)
+        };
+        let gens = generations(&self.path).ok()?;
+        let disk_bytes = gens
+            .iter()
+            .map(|gen| fs::metadata(db_path(&self.path, *gen)).map_or(0, |metadata| metadata.len()))
+            .sum();
+        Some(EngineStats {
+            engine: "kvs",
+            keys: self.index.read().unwrap().len() as u64,
+            disk_bytes,
+            uncompacted_bytes,
+            generation,
+            generations: gens.len() as u64,
+            compactions,
+        })
+    }
 }
 
 // ========================= KvStoreReader =========================
@@ -267,6 +395,8 @@ struct KvStoreWriter {
     index: Arc<RwLock<HashMap<String, CommandOffset>>>,
     current_gen: u64,
     uncompacted: u64,
+    compactions: u64,
+    feed: ChangeFeed,
 }
 
 impl KvStoreWriter {
@@ -274,7 +404,8 @@ impl KvStoreWriter {
            writer: BufWriter<File>,
            reader: KvStoreReader,
            index: Arc<RwLock<HashMap<String, CommandOffset>>>,
-           current_gen: u64) -> Result<Self> {
+           current_gen: u64,
+           feed: ChangeFeed) -> Result<Self> {
 
         Ok(KvStoreWriter {
             path,
@@ -282,28 +413,44 @@ impl KvStoreWriter {
             reader,
             index,
             current_gen,
-            uncompacted: 0
+            uncompacted: 0,
+            compactions: 0,
+            feed,
         })
     }
 
     fn set(&mut self, key: String, value: String) -> Result<()> {
-        let command = Command::Set {
-            key: key.clone(),
-            value,
-        };
+        self.set_many(vec![(key, value)])
+    }
 
-        let pos = self.writer.pos;
-        serde_json::to_writer(&mut self.writer, &command)?;
+    /// Append all commands before flushing once, which is what makes batches cheap.
+    /// The writes are published once they are in the index.
+    fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        let mut offsets = Vec::with_capacity(pairs.len());
+        let mut changes = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            let command = Command::Set {
+                key: key.clone(),
+                value: value.clone(),
+            };
+
+            let pos = self.writer.pos;
+            serde_json::to_writer(&mut self.writer, &command)?;
+            offsets.push((key.clone(), pos..self.writer.pos));
+            changes.push(replication::Command::Set { key, value });
+        }
         self.writer.flush()?;
 
         {
-            let new_pos = self.writer.pos;
-            let offset = CommandOffset::from((self.current_gen, pos..new_pos));
             let mut index = self.index.write().unwrap();
-            if let Some(offset) = index.insert(key, offset) {
-                self.uncompacted += offset.len;
+            for (key, range) in offsets {
+                let offset = CommandOffset::from((self.current_gen, range));
+                if let Some(offset) = index.insert(key, offset) {
+                    self.uncompacted += offset.len;
+                }
             }
         }
+        self.feed.publish(changes);
 
         if self.uncompacted >= COMPACTION_THRESHOLD {
             self.compact()?;
@@ -313,24 +460,46 @@ impl KvStoreWriter {
     }
 
     fn remove(&mut self, key: String) -> Result<()> {
-        if !self.index.read().unwrap().contains_key(&key) {
-            Err(Error::from(ErrorKind::KeyNotFound))
+        if self.remove_many(vec![key])?[0] {
+            Ok(())
         } else {
-            let command = Command::Remove { key: key.clone() };
+            Err(Error::from(ErrorKind::KeyNotFound))
+        }
+    }
 
-            serde_json::to_writer(&mut self.writer, &command)?;
-            self.writer.flush()?;
+    fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<bool>> {
+        let mut stale = HashSet::new();
+        let removed = {
+            let index = self.index.read().unwrap();
+            keys.into_iter()
+                .map(|key| index.contains_key(&key) && stale.insert(key))
+                .collect::<Vec<bool>>()
+        };
+
+        if stale.is_empty() {
+            return Ok(removed);
+        }
 
-            let offset = self.index.write().unwrap().remove(&key)
-                .expect("Unreachable: key not found");
-            self.uncompacted += offset.len;
+        for key in stale.iter() {
+            let command = Command::Remove { key: key.clone() };
+            serde_json::to_writer(&mut self.writer, &command)?;
+        }
+        self.writer.flush()?;
 
-            if self.uncompacted >= COMPACTION_THRESHOLD {
-                self.compact()?;
+        {
+            let mut index = self.index.write().unwrap();
+            for key in stale.iter() {
+                let offset = index.remove(key).expect("Unreachable: key not found");
+                self.uncompacted += offset.len;
             }
+        }
+        self.feed.publish(stale.into_iter().map(|key| replication::Command::Remove { key }).collect());
 
-            Ok(())
+        if self.uncompacted >= COMPACTION_THRESHOLD {
+            self.compact()?;
         }
+
+        Ok(removed)
     }
 
     fn compact(&mut self) -> Result<()> {
@@ -372,6 +541,8 @@ impl KvStoreWriter {
             fs::remove_file(path)?;
         }
 
+        self.uncompacted = 0;
+        self.compactions += 1;
         Ok(())
     }
 }
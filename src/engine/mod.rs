@@ -1,8 +1,30 @@
 pub(crate) mod kvs;
 pub(crate) mod sled;
 
+use crate::error::ErrorKind;
+use crate::watch::ChangeFeed;
 use crate::Result;
 
+/// The statistics of the storage of an engine, where those of the log are zero for engines
+/// without one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineStats {
+    /// The name of the engine, such as `kvs`.
+    pub engine: &'static str,
+    /// The number of keys stored.
+    pub keys: u64,
+    /// The bytes the engine takes on disk.
+    pub disk_bytes: u64,
+    /// The bytes of the log which compaction would reclaim.
+    pub uncompacted_bytes: u64,
+    /// The generation of the log written to.
+    pub generation: u64,
+    /// The number of generations of the log on disk.
+    pub generations: u64,
+    /// The number of compactions run since the engine was opened.
+    pub compactions: u64,
+}
+
 /// KvsEngine trait provides key-value store methods.
 pub trait KvsEngine: Clone + Send + 'static {
     /// Sets the value of a string key to a string.
@@ -17,4 +39,80 @@ pub trait KvsEngine: Clone + Send + 'static {
     /// Removes a given key.
     /// Return an error if the key does not exist or is not removed successfully.
     fn remove(&self, key: String) -> Result<()>;
+
+    /// Gets all keys starting with a given prefix, in ascending order.
+    /// Return an error if the keys are not read successfully.
+    fn scan(&self, prefix: String) -> Result<Vec<String>>;
+
+    /// Gets at most `limit` keys following a given key, or the first keys, in ascending order.
+    /// Return an error if the keys are not read successfully.
+    fn scan_after(&self, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        let mut keys = self.scan(String::new())?;
+        let start = match after {
+            Some(after) => keys.partition_point(|key| *key <= after),
+            None => 0,
+        };
+        keys.truncate(start.saturating_add(limit));
+        keys.drain(..start);
+        Ok(keys)
+    }
+
+    /// Gets the feed of the writes committed to the engine, which watchers follow.
+    /// Return None if the engine does not publish its writes.
+    fn changes(&self) -> Option<ChangeFeed> {
+        None
+    }
+
+    /// Compacts the storage of the engine, reclaiming the space taken by stale data.
+    /// Engines which reclaim it by themselves only flush.
+    /// Return an error if the storage is not compacted successfully.
+    fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Gets the statistics of the storage of the engine, which the server exports as metrics.
+    /// Return None if the engine keeps none.
+    fn stats(&self) -> Option<EngineStats> {
+        None
+    }
+
+    /// Gets the string values of many string keys, in the order of the keys.
+    /// The value of a key which does not exist is None.
+    /// Return an error if any value is not read successfully.
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        keys.into_iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Sets the values of many string keys.
+    /// Return an error if any value is not written successfully.
+    fn set_many(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        for (key, value) in pairs {
+            self.set(key, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes many keys, and tells whether each key existed, in the order of the keys.
+    /// Return an error if any key is not removed successfully.
+    fn remove_many(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        keys.into_iter()
+            .map(|key| match self.remove(key) {
+                Ok(()) => Ok(true),
+                Err(err) => match err.kind() {
+                    ErrorKind::KeyNotFound => Ok(false),
+                    _ => Err(err),
+                },
+            })
+            .collect()
+    }
+}
+
+/// Count the keys of an engine from its statistics, scanning every key only for engines which
+/// keep none.
+pub(crate) fn key_count<E: KvsEngine>(store: &E) -> Result<u64> {
+    match store.stats() {
+        Some(stats) => Ok(stats.keys),
+        None => Ok(store.scan(String::new())?.len() as u64),
+    }
 }
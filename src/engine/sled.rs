@@ -1,12 +1,17 @@
-use crate::engine::KvsEngine;
+use crate::engine::{EngineStats, KvsEngine};
 use crate::error::ErrorKind;
+use crate::replication::Command;
+use crate::watch::ChangeFeed;
 use crate::Result;
-use sled::Db;
+use sled::{Batch, Db, Event};
+use std::ops::Bound;
 use std::path::PathBuf;
+use std::thread;
 
 /// Used to store a string key to a string value with sled engine.
 pub struct SledKvsEngine {
     db: Db,
+    feed: ChangeFeed,
 }
 
 impl SledKvsEngine {
@@ -17,13 +22,36 @@ impl SledKvsEngine {
 
         let db = sled::open(path)?;
 
-        Ok(SledKvsEngine { db })
+        // The subscriber of sled is told every write, and ends when the db is dropped.
+        let feed = ChangeFeed::new();
+        let subscriber = db.watch_prefix(vec![]);
+        let publisher = feed.clone();
+        thread::spawn(move || {
+            for event in subscriber {
+                let command = match event {
+                    Event::Insert { key, value } => match (String::from_utf8(key.to_vec()), String::from_utf8(value.to_vec())) {
+                        (Ok(key), Ok(value)) => Command::Set { key, value },
+                        _ => continue,
+                    },
+                    Event::Remove { key } => match String::from_utf8(key.to_vec()) {
+                        Ok(key) => Command::Remove { key },
+                        Err(_) => continue,
+                    },
+                };
+                publisher.publish(vec![command]);
+            }
+        });
+
+        Ok(SledKvsEngine { db, feed })
     }
 }
 
 impl Clone for SledKvsEngine {
     fn clone(&self) -> Self {
-        SledKvsEngine { db: self.db.clone() }
+        SledKvsEngine {
+            db: self.db.clone(),
+            feed: self.feed.clone(),
+        }
     }
 }
 
@@ -57,4 +85,87 @@ impl KvsEngine for SledKvsEngine {
         self.db.flush()?;
         Ok(())
     }
+
+    /// Gets the string values of many string keys.
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(match self.db.get(key)? {
+                Some(ivec) => Some(String::from_utf8(ivec.to_vec())?),
+                None => None,
+            });
+        }
+
+        Ok(values)
+    }
+
+    /// Sets the values of many string keys atomically, flushing only once.
+    fn set_many(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        let mut batch = Batch::default();
+        for (key, value) in pairs {
+            batch.insert(key.as_bytes(), value.as_bytes());
+        }
+
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Removes many keys, flushing only once.
+    fn remove_many(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        let mut removed = Vec::with_capacity(keys.len());
+        for key in keys {
+            removed.push(self.db.remove(key)?.is_some());
+        }
+
+        self.db.flush()?;
+        Ok(removed)
+    }
+
+    /// Gets all keys starting with a given prefix, in ascending order.
+    fn scan(&self, prefix: String) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for entry in self.db.scan_prefix(prefix) {
+            let (key, _) = entry?;
+            keys.push(String::from_utf8(key.to_vec())?);
+        }
+
+        Ok(keys)
+    }
+
+    /// Gets at most `limit` keys following a given key, reading only those from the tree.
+    fn scan_after(&self, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after.into_bytes()),
+            None => Bound::Unbounded,
+        };
+        let mut keys = Vec::new();
+        for entry in self.db.range::<Vec<u8>, _>((start, Bound::Unbounded)).take(limit) {
+            let (key, _) = entry?;
+            keys.push(String::from_utf8(key.to_vec())?);
+        }
+
+        Ok(keys)
+    }
+
+    /// Gets the feed of the writes sled tells its subscriber since the engine was opened.
+    fn changes(&self) -> Option<ChangeFeed> {
+        Some(self.feed.clone())
+    }
+
+    /// Flushes the db, as sled reclaims the space of stale data by itself.
+    fn compact(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Gets the number of keys and the size on disk of the db.
+    fn stats(&self) -> Option<EngineStats> {
+        Some(EngineStats {
+            engine: "sled",
+            keys: self.db.len() as u64,
+            disk_bytes: self.db.size_on_disk().ok()?,
+            ..EngineStats::default()
+        })
+    }
 }
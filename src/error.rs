@@ -37,15 +37,74 @@ pub enum ErrorKind {
     #[fail(display = "{}", _0)]
     StringError(String),
 
+    /// Error for stored data which can not be read back.
+    #[fail(display = "Data corruption: {}", _0)]
+    Corruption(String),
+
+    /// Error for a server which can not serve the request now.
+    #[fail(display = "Server busy: {}", _0)]
+    Busy(String),
+
+    /// Error for a request which is not permitted.
+    #[fail(display = "Unauthorized: {}", _0)]
+    Unauthorized(String),
+
+    /// Error for a connection which timed out connecting, reading or writing.
+    #[fail(display = "Timed out: {}", _0)]
+    Timeout(#[cause] io::Error),
+
+    /// Error for a write sent to a read-only follower.
+    #[fail(display = "Read only: {}", _0)]
+    ReadOnly(String),
+
+    /// Error for a request which only the leader of a Raft cluster can serve.
+    #[fail(display = "Not leader: {}", message)]
+    NotLeader {
+        /// Why the request was refused.
+        message: String,
+        /// The address of the leader, if it is known.
+        leader: Option<String>,
+    },
+
+    /// Error for a key of a shard which another node of a sharded cluster serves.
+    #[fail(display = "Moved: {}", message)]
+    Moved {
+        /// Which shard the key belongs to, and where it is served.
+        message: String,
+        /// The address of the node serving the shard.
+        node: String,
+    },
+
+    /// Error for TLS settings which can not be used, such as a certificate which can not be read.
+    #[fail(display = "TLS error: {}", _0)]
+    Tls(String),
+
+    /// Error for a request which the server can not understand.
+    #[fail(display = "Invalid request: {}", _0)]
+    InvalidRequest(String),
+
     /// Error for unexpected status.
     #[fail(display = "Unexpected: {}", _0)]
     UnexpectedError(&'static str),
 }
 
+impl Error {
+    /// Get the kind of this error.
+    pub fn kind(&self) -> &ErrorKind {
+        self.inner.get_context()
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
+        // Sockets report an expired read or write timeout as either of them.
+        let kind = match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout(err),
+            _ => ErrorKind::Io(err),
+        };
+
         Error {
-            inner: Context::new(ErrorKind::Io(err)),
+            inner: Context::new(kind),
         }
     }
 }
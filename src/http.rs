@@ -0,0 +1,497 @@
+use crate::engine::{key_count, KvsEngine};
+use crate::error::{Error, ErrorKind, Result};
+use crate::metrics::Metrics;
+use crate::protocol::ErrorCode;
+use crate::{Auth, Credentials, Permission};
+use serde_json::json;
+use slog::{error, info, Logger};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use crate::stream::Stream;
+use std::net::IpAddr;
+use std::time::Instant;
+
+const MAX_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+
+/// A parsed HTTP/1.1 request.
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Option<String>,
+    headers: Vec<(String, String)>,
+    /// The length of the body, which is read once the request is admitted.
+    body_len: usize,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query_param(&self, name: &str) -> Option<String> {
+        self.query.as_ref()?.split('&').find_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some(key), value) if percent_decode(&key.replace('+', " ")).as_deref() == Some(name) => {
+                    percent_decode(&value.unwrap_or("").replace('+', " "))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn keep_alive(&self) -> bool {
+        !matches!(self.header("Connection"), Some(value) if value.eq_ignore_ascii_case("close"))
+    }
+}
+
+/// A HTTP response about to be written.
+struct HttpResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    /// The code of the error replied, by which the response is counted in the metrics.
+    error: Option<ErrorCode>,
+}
+
+impl HttpResponse {
+    fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        HttpResponse {
+            status,
+            content_type,
+            body: body.into(),
+            error: None,
+        }
+    }
+
+    fn json(status: u16, body: serde_json::Value) -> Self {
+        HttpResponse::new(status, "application/json", body.to_string())
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        let code = match status {
+            401 | 403 => ErrorCode::Unauthorized,
+            404 => ErrorCode::NotFound,
+            503 => ErrorCode::Busy,
+            400..=499 => ErrorCode::InvalidRequest,
+            _ => ErrorCode::Internal,
+        };
+        HttpResponse {
+            error: Some(code),
+            ..HttpResponse::json(status, json!({ "error": message.to_string() }))
+        }
+    }
+
+    /// Reply an error of the engine, with the status and the code of its kind.
+    fn engine_error(err: Error) -> Self {
+        HttpResponse {
+            error: Some(ErrorCode::from(err.kind())),
+            ..HttpResponse::error(status_of(&err), err)
+        }
+    }
+
+    fn no_content() -> Self {
+        HttpResponse::new(204, "text/plain; charset=utf-8", Vec::new())
+    }
+
+    fn write_to(&self, writer: &mut impl Write, keep_alive: bool) -> Result<()> {
+        write!(writer, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        write!(writer, "Content-Type: {}\r\n", self.content_type)?;
+        write!(writer, "Content-Length: {}\r\n", self.body.len())?;
+        if !keep_alive {
+            write!(writer, "Connection: close\r\n")?;
+        }
+        write!(writer, "\r\n")?;
+        writer.write_all(&self.body)?;
+        Ok(())
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Read a line terminated by LF, without the terminator.
+/// Return None if the peer closed the connection.
+fn read_line(reader: &mut impl BufRead) -> std::result::Result<Option<String>, HttpResponse> {
+    let mut line = Vec::new();
+    let len = reader
+        .by_ref()
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => HttpResponse::error(408, "request timeout"),
+            _ => HttpResponse::error(400, err),
+        })?;
+
+    if len == 0 {
+        return Ok(None);
+    }
+    if len > MAX_LINE_LEN || !line.ends_with(b"\n") {
+        return Err(HttpResponse::error(431, "line too long"));
+    }
+
+    let line = String::from_utf8(line).map_err(|_| HttpResponse::error(400, "invalid request"))?;
+    Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()))
+}
+
+fn read_request(reader: &mut impl BufRead) -> std::result::Result<Option<HttpRequest>, HttpResponse> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let mut parts = line.split(' ');
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), target.to_string())
+        }
+        _ => return Err(HttpResponse::error(400, "invalid request line")),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| HttpResponse::error(400, "unexpected end of stream"))?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() >= MAX_HEADERS {
+            return Err(HttpResponse::error(431, "too many headers"));
+        }
+
+        let mut header = line.splitn(2, ':');
+        match (header.next(), header.next()) {
+            (Some(name), Some(value)) => headers.push((name.trim().to_string(), value.trim().to_string())),
+            _ => return Err(HttpResponse::error(400, "invalid header")),
+        }
+    }
+
+    let mut target = target.splitn(2, '?');
+    let mut request = HttpRequest {
+        method,
+        path: target.next().unwrap_or("").to_string(),
+        query: target.next().map(ToString::to_string),
+        headers,
+        body_len: 0,
+        body: Vec::new(),
+    };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(HttpResponse::error(411, "chunked bodies are not supported"));
+    }
+
+    if let Some(len) = request.header("Content-Length") {
+        let len = len
+            .parse::<usize>()
+            .map_err(|_| HttpResponse::error(400, "invalid Content-Length"))?;
+        if len > MAX_BODY_LEN {
+            return Err(HttpResponse::error(413, "body too large"));
+        }
+        request.body_len = len;
+    }
+
+    Ok(Some(request))
+}
+
+/// Read the body of a request, which grows as it is received rather than by the length the
+/// client announced.
+fn read_body(reader: &mut impl BufRead, request: &mut HttpRequest) -> std::result::Result<(), HttpResponse> {
+    let len = reader
+        .by_ref()
+        .take(request.body_len as u64)
+        .read_to_end(&mut request.body)
+        .map_err(|err| HttpResponse::error(400, err))?;
+    if len < request.body_len {
+        return Err(HttpResponse::error(400, "unexpected end of stream"));
+    }
+    Ok(())
+}
+
+/// Decode a percent-encoded URL component, return None if it is not valid UTF-8.
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                output.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                output.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(output).ok()
+}
+
+/// Serve a client speaking HTTP/1.1, mapping the REST resources onto the engine.
+/// With authentication, every request but health checks carries a token as
+/// `Authorization: Bearer TOKEN`.
+pub(crate) fn serve_http<E: KvsEngine>(
+    store: E,
+    stream: Stream,
+    auth: Option<&Auth>,
+    metrics: &Metrics,
+    logger: &Logger,
+) -> Result<()> {
+    let peer = stream.peer_ip()?;
+    serve_requests(
+        stream,
+        Some(metrics),
+        logger,
+        |request| authenticate(auth, peer, request),
+        |request, user| route(&store, request, user.as_ref().map(|(auth, user)| (*auth, user.as_str()))),
+    )
+}
+
+/// Serve the metrics of a server in the Prometheus text format on `GET /metrics`.
+pub(crate) fn serve_metrics<E: KvsEngine>(store: E, stream: Stream, metrics: &Metrics, logger: &Logger) -> Result<()> {
+    // Scrapes are not counted as requests of clients.
+    serve_requests(
+        stream,
+        None,
+        logger,
+        |_| Ok(()),
+        |request, ()| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {
+                HttpResponse::new(200, "text/plain; version=0.0.4; charset=utf-8", metrics.render(store.stats()))
+            }
+            (_, "/metrics") => HttpResponse::error(405, "method not allowed"),
+            _ => HttpResponse::error(404, "not found"),
+        },
+    )
+}
+
+/// Reply the requests of a connection until the client closes it or asks to.
+///
+/// A request is admitted from its head before its body is read, so that a client which may not
+/// send it is refused without the server reading the body. Requests are recorded into given
+/// metrics, if any.
+fn serve_requests<A, U, F>(
+    stream: Stream,
+    metrics: Option<&Metrics>,
+    logger: &Logger,
+    admit: A,
+    handle: F,
+) -> Result<()>
+where
+    A: Fn(&HttpRequest) -> std::result::Result<U, HttpResponse>,
+    F: Fn(&HttpRequest, U) -> HttpResponse,
+{
+    let mut writer = BufWriter::new(&stream);
+    let mut reader = BufReader::new(&stream);
+
+    loop {
+        let started = Instant::now();
+        let (response, keep_alive, name) = match read_request(&mut reader) {
+            Ok(Some(mut request)) => {
+                info!(logger, "request came"; "method" => &request.method, "path" => &request.path);
+                let admitted = admit(&request)
+                    .and_then(|admitted| read_body(&mut reader, &mut request).map(|()| admitted));
+                match admitted {
+                    Ok(admitted) => (handle(&request, admitted), request.keep_alive(), request_name(&request)),
+                    // The body left unread would be taken for the next request.
+                    Err(response) => {
+                        let keep_alive = request.body_len == 0 && request.keep_alive();
+                        (response, keep_alive, request_name(&request))
+                    }
+                }
+            }
+            Ok(None) => break,
+            Err(response) => {
+                error!(logger, "can not parse the request");
+                (response, false, None)
+            }
+        };
+
+        info!(logger, "reply"; "status" => response.status);
+        if let Some(metrics) = metrics {
+            metrics.observe(name, started.elapsed(), response.error);
+        }
+        response.write_to(&mut writer, keep_alive)?;
+        writer.flush()?;
+
+        if !keep_alive {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the type of request a request is counted as in the metrics, as the requests of the
+/// protocol of kvs doing the same are.
+fn request_name(request: &HttpRequest) -> Option<&'static str> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => Some("Ping"),
+        ("GET", "/stats") => Some("Info"),
+        ("GET", "/keys") => Some("Scan"),
+        ("GET", path) if path.starts_with("/keys/") => Some("Get"),
+        ("PUT", path) if path.starts_with("/keys/") => Some("Set"),
+        ("DELETE", path) if path.starts_with("/keys/") => Some("Remove"),
+        _ => None,
+    }
+}
+
+/// Authenticate the bearer of a request, and return the user it is.
+fn authenticate<'a>(
+    auth: Option<&'a Auth>,
+    peer: IpAddr,
+    request: &HttpRequest,
+) -> std::result::Result<Option<(&'a Auth, String)>, HttpResponse> {
+    let auth = match auth {
+        Some(auth) if request.path != "/health" => auth,
+        _ => return Ok(None),
+    };
+
+    // The bearer is either the token of a user or the shared password, as for `Auth`.
+    let secret = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|secret| secret.trim().to_string())
+        .ok_or_else(|| HttpResponse::error(401, "a bearer token or password is required"))?;
+    match auth.authenticate_any(peer, &[Credentials::Token(secret.clone()), Credentials::Password(secret)]) {
+        Ok(user) => Ok(Some((auth, user))),
+        Err(err) => Err(HttpResponse::error(401, err)),
+    }
+}
+
+/// Refuse a request unless its user has a permission on a key, or on every key starting with
+/// a prefix.
+fn forbidden(user: Option<(&Auth, &str)>, permission: Permission, key: &str) -> Option<HttpResponse> {
+    let (auth, user) = user?;
+    auth.authorize_key(user, permission, key)
+        .err()
+        .map(|err| HttpResponse::error(403, err))
+}
+
+fn route<E: KvsEngine>(store: &E, request: &HttpRequest, user: Option<(&Auth, &str)>) -> HttpResponse {
+    let path = request.path.as_str();
+    let permission = match request.method.as_str() {
+        "GET" => Permission::Read,
+        _ => Permission::Write,
+    };
+    match (request.method.as_str(), path) {
+        ("GET", "/health") => HttpResponse::json(200, json!({ "status": "ok" })),
+        ("GET", "/stats") => {
+            if let Some(response) = forbidden(user, permission, "") {
+                return response;
+            }
+            match key_count(store) {
+                Ok(keys) => HttpResponse::json(200, json!({ "version": env!("CARGO_PKG_VERSION"), "keys": keys })),
+                Err(err) => HttpResponse::engine_error(err),
+            }
+        }
+        ("GET", "/keys") => {
+            let prefix = request.query_param("prefix").unwrap_or_default();
+            if let Some(response) = forbidden(user, permission, &prefix) {
+                return response;
+            }
+            match store.scan(prefix) {
+                Ok(keys) => HttpResponse::json(200, json!(keys)),
+                Err(err) => HttpResponse::engine_error(err),
+            }
+        }
+        (_, "/health") | (_, "/stats") | (_, "/keys") => HttpResponse::error(405, "method not allowed"),
+        (method, path) if path.starts_with("/keys/") => {
+            let key = match percent_decode(&path["/keys/".len()..]) {
+                Some(key) if !key.is_empty() => key,
+                _ => return HttpResponse::error(400, "invalid key"),
+            };
+            if let Some(response) = forbidden(user, permission, &key) {
+                return response;
+            }
+
+            match method {
+                "GET" => match store.get(key) {
+                    Ok(Some(value)) if accepts_octet_stream(request) => {
+                        HttpResponse::new(200, "application/octet-stream", value)
+                    }
+                    Ok(Some(value)) => HttpResponse::new(200, "text/plain; charset=utf-8", value),
+                    Ok(None) => HttpResponse::error(404, ErrorKind::KeyNotFound),
+                    Err(err) => HttpResponse::engine_error(err),
+                },
+                "PUT" => put(store, key, request),
+                "DELETE" => match store.remove(key) {
+                    Ok(()) => HttpResponse::no_content(),
+                    Err(err) => HttpResponse::engine_error(err),
+                },
+                _ => HttpResponse::error(405, "method not allowed"),
+            }
+        }
+        _ => HttpResponse::error(404, "no such resource"),
+    }
+}
+
+/// Whether a request asks for values as an octet stream rather than as text.
+fn accepts_octet_stream(request: &HttpRequest) -> bool {
+    request.header("Accept").is_some_and(|accept| {
+        accept
+            .split(',')
+            .any(|media| media.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("application/octet-stream"))
+    })
+}
+
+/// Values are stored as strings, so a body is accepted as text, JSON or an octet stream, and
+/// refused if it is not valid UTF-8, as binary values are not supported.
+fn put<E: KvsEngine>(store: &E, key: String, request: &HttpRequest) -> HttpResponse {
+    let content_type = request
+        .header("Content-Type")
+        .map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+    match content_type.as_deref() {
+        None | Some("application/octet-stream") | Some("application/json") => (),
+        Some(content_type) if content_type.starts_with("text/") => (),
+        Some(_) => {
+            return HttpResponse::error(415, "values must be text/*, application/json or application/octet-stream")
+        }
+    }
+
+    let value = match String::from_utf8(request.body.clone()) {
+        Ok(value) => value,
+        Err(_) => return HttpResponse::error(422, "values must be valid UTF-8, binary values are not supported"),
+    };
+
+    match store.set(key, value) {
+        Ok(()) => HttpResponse::no_content(),
+        Err(err) => HttpResponse::engine_error(err),
+    }
+}
+
+/// Get the status replying an error of the engine.
+fn status_of(err: &Error) -> u16 {
+    match err.kind() {
+        ErrorKind::KeyNotFound => 404,
+        ErrorKind::ReadOnly(_) => 403,
+        ErrorKind::Moved { .. } => 421,
+        ErrorKind::NotLeader { .. } | ErrorKind::Busy(_) => 503,
+        _ => 500,
+    }
+}
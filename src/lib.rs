@@ -1,17 +1,42 @@
 #![deny(missing_docs)]
 //! A simple key-value store.
 
-pub use client::KvsClient;
-pub use engine::{kvs::KvStore, sled::SledKvsEngine, KvsEngine};
-pub use error::{Error, Result};
-pub use protocol::{Request, Response};
-pub use server::KvsServer;
+pub use auth::{Auth, AuthConfig, Credentials, Permission, Rule, DEFAULT_USER};
+pub use client::{KvsClient, Pipeline, Subscription, Timeouts, Watch};
+pub use metrics::Metrics;
+pub use pool::{KvsClientPool, PoolConfig};
+pub use engine::{kvs::KvStore, sled::SledKvsEngine, EngineStats, KvsEngine};
+pub use error::{Error, ErrorKind, Result};
+pub use logging::{LogConfig, LogFormat, LogOutput, RequestLogState};
+pub use protocol::{ErrorCode, ProtocolError, Request, RequestFrame, Response, ResponseFrame};
+pub use pubsub::{Message, SubscribeEvent};
+pub use raft::{ClusterInfo, Entry, Payload, Raft, RaftConfig, RaftNode, RaftRequest, RaftResponse, RaftRole};
+pub use replication::{Command, Follower, Leader, Record, Replication, ReplicationInfo, Role, SyncEvent};
+pub use server::{Compaction, KvsServer, ServerInfo};
+pub use shard::{ShardMap, ShardNode, ShardedClient, Sharding};
+pub use slowlog::SlowRequest;
+pub use tls::{ClientTls, ServerTls, TlsConfig};
+pub use watch::{Change, ChangeFeed, WatchEvent};
 
+mod auth;
 mod client;
 mod engine;
 mod error;
+mod http;
+mod logging;
+mod metrics;
+mod pool;
 mod protocol;
+mod pubsub;
+mod raft;
+mod replication;
+mod resp;
 mod server;
+mod shard;
+mod slowlog;
+mod stream;
+mod tls;
+mod watch;
 
 /// The thread_pool modular.
 pub mod thread_pool;
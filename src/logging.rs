@@ -0,0 +1,240 @@
+use crate::slowlog;
+use crate::{Request, Response, Result};
+use serde::{Deserialize, Serialize};
+use slog::{o, Drain, Level, LevelFilter, Logger};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The format of the lines logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Lines for people to read, colored when written to a terminal.
+    Terminal,
+    /// A JSON object per line, for log collectors.
+    Json,
+}
+
+/// Where the lines are logged.
+#[derive(Debug, Clone)]
+pub enum LogOutput {
+    /// Write to the standard error.
+    Stderr,
+    /// Append to a file.
+    File(PathBuf),
+    /// Append to a file, which is renamed to `<path>.1` once it grows past a size, shifting the
+    /// older files up to a number of them kept.
+    Rotating {
+        /// The file appended to.
+        path: PathBuf,
+        /// The size past which the file is rotated.
+        max_bytes: u64,
+        /// The number of rotated files kept, the oldest being removed.
+        keep: usize,
+    },
+}
+
+/// The settings of the logger of a server.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Lines less severe than this level are dropped.
+    pub level: Level,
+    /// The format of the lines.
+    pub format: LogFormat,
+    /// Where the lines are written.
+    pub output: LogOutput,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: Level::Info,
+            format: LogFormat::Terminal,
+            output: LogOutput::Stderr,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Build a logger writing lines from a thread of its own.
+    pub fn logger(&self) -> Result<Logger> {
+        if let (LogFormat::Terminal, LogOutput::Stderr) = (self.format, &self.output) {
+            let decorator = slog_term::TermDecorator::new().build();
+            return Ok(self.root(slog_term::FullFormat::new(decorator).build().fuse()));
+        }
+
+        let writer: Box<dyn Write + Send> = match &self.output {
+            LogOutput::Stderr => Box::new(io::stderr()),
+            LogOutput::File(path) => Box::new(append(path)?),
+            LogOutput::Rotating { path, max_bytes, keep } => Box::new(RotatingFile::open(path, *max_bytes, *keep)?),
+        };
+        Ok(match self.format {
+            LogFormat::Terminal => {
+                let decorator = slog_term::PlainDecorator::new(writer);
+                self.root(slog_term::FullFormat::new(decorator).build().fuse())
+            }
+            LogFormat::Json => self.root(slog_json::Json::default(writer).fuse()),
+        })
+    }
+
+    fn root<D>(&self, drain: D) -> Logger
+    where
+        D: Drain<Ok = (), Err = slog::Never> + Send + 'static,
+    {
+        let drain = slog_async::Async::new(drain).build().fuse();
+        Logger::root(LevelFilter::new(drain, self.level).fuse(), o!())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// A log file which is rotated once it grows past a size, only between lines so that no line is
+/// split across files.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    len: u64,
+    line_start: bool,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = append(path)?;
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            len: file.metadata()?.len(),
+            file,
+            line_start: true,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                if self.rotated(n).exists() {
+                    fs::rename(self.rotated(n), self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = append(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start && self.len >= self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.len += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Describe a request for the log, where the values it carries are left out unless asked for.
+pub(crate) fn request(request: &Request, values: bool) -> String {
+    if values {
+        format!("{:?}", request)
+    } else {
+        slowlog::summary(request)
+    }
+}
+
+/// Describe a response for the log, where the values it carries are left out unless asked for.
+pub(crate) fn response(response: &Response, values: bool) -> String {
+    if values {
+        return format!("{:?}", response);
+    }
+    match response {
+        Response::Get(Ok(Some(value))) => format!("Get(Ok(Some(<{} bytes>)))", value.len()),
+        Response::GetMany(Ok(values)) => format!(
+            "GetMany(Ok(<{} of {} found>))",
+            values.iter().filter(|value| value.is_some()).count(),
+            values.len()
+        ),
+        Response::Sync(_) => "Sync(..)".to_string(),
+        Response::Raft(_) => "Raft(..)".to_string(),
+        Response::Watch(_) => "Watch(..)".to_string(),
+        Response::Subscribe(_) => "Subscribe(..)".to_string(),
+        response => format!("{:?}", response),
+    }
+}
+
+/// The connections whose requests are logged, as replied to `Request::RequestLog`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestLogState {
+    /// The requests of every connection are logged.
+    pub all: bool,
+    /// The clients whose connections are logged, by address with or without a port, or `unix`
+    /// for the clients of a Unix socket.
+    pub clients: Vec<String>,
+}
+
+/// The connections whose requests and responses are logged at the info level rather than the
+/// debug level, which can be switched while the server runs.
+#[derive(Clone, Default)]
+pub(crate) struct RequestLog(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    // Set when any connection is logged, so that requests are rarely checked under the lock.
+    any: AtomicBool,
+    state: Mutex<RequestLogState>,
+}
+
+impl RequestLog {
+    /// Switch the log of every connection, or of the connections of a client.
+    pub(crate) fn switch(&self, enabled: bool, client: Option<String>) -> RequestLogState {
+        let mut state = self.0.state.lock().unwrap();
+        match client {
+            None if enabled => state.all = true,
+            None => *state = RequestLogState::default(),
+            Some(client) => {
+                state.clients.retain(|logged| *logged != client);
+                if enabled {
+                    state.clients.push(client);
+                }
+            }
+        }
+        self.0.any.store(state.all || !state.clients.is_empty(), Ordering::Relaxed);
+        state.clone()
+    }
+
+    /// Check whether the requests of a connection are logged, by its address or `unix`.
+    pub(crate) fn logs(&self, client: &str) -> bool {
+        if !self.0.any.load(Ordering::Relaxed) {
+            return false;
+        }
+        let ip = client.parse::<SocketAddr>().map(|addr| addr.ip().to_string()).ok();
+        let state = self.0.state.lock().unwrap();
+        state.all || state.clients.iter().any(|logged| logged == client || Some(logged) == ip.as_ref())
+    }
+}
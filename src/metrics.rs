@@ -0,0 +1,185 @@
+use crate::engine::EngineStats;
+use crate::protocol::ErrorCode;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// The upper bounds in seconds of the buckets of the latency histograms, besides +Inf.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// The metrics of a server, shared by its listeners and exported in the Prometheus text format
+/// by `KvsServer::run_metrics`.
+///
+/// Requests are counted once they are replied, so streaming requests such as `Watch` are not.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    requests: Mutex<BTreeMap<&'static str, Histogram>>,
+    errors: Mutex<BTreeMap<String, u64>>,
+    connections: AtomicI64,
+    queued: AtomicI64,
+}
+
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: [0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// A gauge of the metrics which is incremented while the guard lives.
+pub(crate) struct GaugeGuard {
+    metrics: Metrics,
+    gauge: fn(&Inner) -> &AtomicI64,
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        (self.gauge)(&self.metrics.0).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Create metrics with nothing recorded yet.
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Record a request replied after a given time, with the code of the error it failed with.
+    /// Requests which can not be parsed have no type and only count as errors.
+    pub(crate) fn observe(&self, request: Option<&'static str>, elapsed: Duration, error: Option<ErrorCode>) {
+        if let Some(request) = request {
+            let mut requests = self.0.requests.lock().unwrap();
+            requests.entry(request).or_insert_with(Histogram::new).observe(elapsed.as_secs_f64());
+        }
+        if let Some(code) = error {
+            *self.0.errors.lock().unwrap().entry(format!("{:?}", code)).or_insert(0) += 1;
+        }
+    }
+
+    /// Count a connection as open until the guard is dropped.
+    pub(crate) fn connection(&self) -> GaugeGuard {
+        self.track(|inner| &inner.connections)
+    }
+
+    /// Count a connection as waiting for a thread of the pool until the guard is dropped.
+    pub(crate) fn queued(&self) -> GaugeGuard {
+        self.track(|inner| &inner.queued)
+    }
+
+    fn track(&self, gauge: fn(&Inner) -> &AtomicI64) -> GaugeGuard {
+        gauge(&self.0).fetch_add(1, Ordering::Relaxed);
+        GaugeGuard {
+            metrics: self.clone(),
+            gauge,
+        }
+    }
+
+    /// Get the number of connections of clients open.
+    pub(crate) fn connections(&self) -> u64 {
+        self.0.connections.load(Ordering::Relaxed).max(0) as u64
+    }
+
+    /// Get the number of requests replied, by type.
+    pub(crate) fn requests(&self) -> BTreeMap<String, u64> {
+        let requests = self.0.requests.lock().unwrap();
+        requests.iter().map(|(request, histogram)| (request.to_string(), histogram.count)).collect()
+    }
+
+    /// Render the metrics, with the statistics of the engine if it keeps any.
+    pub(crate) fn render(&self, stats: Option<EngineStats>) -> String {
+        let mut out = String::new();
+        let requests = self.0.requests.lock().unwrap();
+
+        header(&mut out, "kvs_requests_total", "counter", "Requests replied, by type.");
+        for (request, histogram) in requests.iter() {
+            writeln!(out, "kvs_requests_total{{request=\"{}\"}} {}", request, histogram.count).unwrap();
+        }
+
+        header(&mut out, "kvs_request_duration_seconds", "histogram", "Time taken to reply requests, by type.");
+        for (request, histogram) in requests.iter() {
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+                writeln!(
+                    out,
+                    "kvs_request_duration_seconds_bucket{{request=\"{}\",le=\"{}\"}} {}",
+                    request, bound, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "kvs_request_duration_seconds_bucket{{request=\"{}\",le=\"+Inf\"}} {}",
+                request, histogram.count
+            )
+            .unwrap();
+            writeln!(out, "kvs_request_duration_seconds_sum{{request=\"{}\"}} {}", request, histogram.sum).unwrap();
+            writeln!(out, "kvs_request_duration_seconds_count{{request=\"{}\"}} {}", request, histogram.count).unwrap();
+        }
+        drop(requests);
+
+        header(&mut out, "kvs_errors_total", "counter", "Requests replied with an error, by error code.");
+        for (code, count) in self.0.errors.lock().unwrap().iter() {
+            writeln!(out, "kvs_errors_total{{code=\"{}\"}} {}", code, count).unwrap();
+        }
+
+        let connections = self.0.connections.load(Ordering::Relaxed);
+        gauge(&mut out, "kvs_open_connections", "Connections of clients open.", connections);
+        gauge(
+            &mut out,
+            "kvs_thread_pool_queued_jobs",
+            "Connections accepted and waiting for a thread of the pool.",
+            self.0.queued.load(Ordering::Relaxed),
+        );
+
+        if let Some(stats) = stats {
+            gauge(&mut out, "kvs_store_keys", "Keys stored.", stats.keys);
+            gauge(&mut out, "kvs_store_disk_bytes", "Bytes the store takes on disk.", stats.disk_bytes);
+            gauge(
+                &mut out,
+                "kvs_store_uncompacted_bytes",
+                "Bytes of the log which compaction would reclaim.",
+                stats.uncompacted_bytes,
+            );
+            gauge(&mut out, "kvs_store_generations", "Generations of the log on disk.", stats.generations);
+            header(&mut out, "kvs_store_compactions_total", "counter", "Compactions run since the store was opened.");
+            writeln!(out, "kvs_store_compactions_total {}", stats.compactions).unwrap();
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, "gauge", help);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
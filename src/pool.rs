@@ -0,0 +1,255 @@
+use crate::error::{Error, ErrorKind};
+use crate::{ClientTls, Credentials, KvsClient, Request, Response, Result, Timeouts};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits of a [`KvsClientPool`].
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// The number of connections kept by the pool.
+    pub size: usize,
+    /// How many times an idempotent request is retried after its connection failed.
+    pub max_retries: u32,
+    /// The backoff before the first retry, doubled before each following retry.
+    pub backoff: Duration,
+    /// The upper bound of the backoff.
+    pub max_backoff: Duration,
+    /// Connections idle for longer than this are health-checked before being used again.
+    pub health_check_interval: Duration,
+    /// How long to wait for a connection when all of them are in use.
+    pub checkout_timeout: Duration,
+    /// Timeouts of every connection.
+    pub timeouts: Timeouts,
+    /// The credentials every connection authenticates with, if the server requires them.
+    pub credentials: Option<Credentials>,
+    /// The TLS settings every connection uses, if the server requires TLS.
+    pub tls: Option<ClientTls>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            size: 8,
+            max_retries: 3,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            health_check_interval: Duration::from_secs(30),
+            checkout_timeout: Duration::from_secs(5),
+            timeouts: Timeouts::all(Duration::from_secs(5)),
+            credentials: None,
+            tls: None,
+        }
+    }
+}
+
+/// A thread-safe pool of connections to a key-value store server.
+///
+/// Broken connections are dropped and reconnected transparently, and idempotent requests
+/// (gets) are retried with an exponential backoff when their connection fails.
+///
+/// # Example
+///
+/// ```no_run
+/// # use kvs::{KvsClientPool, PoolConfig, Result};
+/// # fn main() -> Result<()> {
+/// let pool = KvsClientPool::connect("127.0.0.1:4000", PoolConfig::default())?;
+/// let shared = pool.clone();
+/// std::thread::spawn(move || shared.set("key".to_string(), "value".to_string()));
+///
+/// let value = pool.get("key".to_string())?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct KvsClientPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    addr: String,
+    config: PoolConfig,
+    state: Mutex<PoolState>,
+    released: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<IdleClient>,
+    open: usize,
+}
+
+struct IdleClient {
+    client: KvsClient,
+    since: Instant,
+}
+
+impl KvsClientPool {
+    /// Connect the remote server with all connections of the pool.
+    pub fn connect(addr: &str, config: PoolConfig) -> Result<KvsClientPool> {
+        if config.size == 0 {
+            return Err(Error::from(ErrorKind::UnexpectedError(
+                "The pool size must be positive",
+            )));
+        }
+
+        let mut idle = Vec::with_capacity(config.size);
+        for _ in 0..config.size {
+            idle.push(IdleClient {
+                client: KvsClient::connect_as(addr, config.timeouts, config.credentials.as_ref(), config.tls.as_ref())?,
+                since: Instant::now(),
+            });
+        }
+
+        let state = PoolState {
+            open: idle.len(),
+            idle,
+        };
+
+        Ok(KvsClientPool {
+            inner: Arc::new(PoolInner {
+                addr: addr.to_string(),
+                config,
+                state: Mutex::new(state),
+                released: Condvar::new(),
+            }),
+        })
+    }
+
+    /// Sets the value of a string key to a string.
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        self.call(Request::Set { key, value }, false)?.into_set()
+    }
+
+    /// Gets the string value of the a string key, retrying if the connection fails.
+    /// If the key does not exist, return None.
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        self.call(Request::Get { key }, true)?.into_get()
+    }
+
+    /// Removes a given key.
+    pub fn remove(&self, key: String) -> Result<()> {
+        self.call(Request::Remove { key }, false)?.into_remove()
+    }
+
+    /// Gets the string values of many string keys, retrying if the connection fails.
+    pub fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.call(Request::GetMany { keys }, true)?.into_get_many()
+    }
+
+    /// Sets the values of many string keys.
+    pub fn set_many(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.call(Request::SetMany { pairs }, false)?.into_set_many()
+    }
+
+    /// Removes many keys, and tells whether each key existed.
+    pub fn remove_many(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        self.call(Request::RemoveMany { keys }, false)?.into_remove_many()
+    }
+
+    /// Gets all keys starting with a given prefix, retrying if the connection fails.
+    pub fn scan(&self, prefix: String) -> Result<Vec<String>> {
+        self.call(Request::Scan { prefix }, true)?.into_scan()
+    }
+
+    /// Checks that the server is alive, retrying if the connection fails.
+    pub fn ping(&self) -> Result<()> {
+        self.call(Request::Ping, true)?.into_pong()
+    }
+
+    /// Get the number of open connections, idle or in use.
+    pub fn open_connections(&self) -> usize {
+        self.inner.state.lock().unwrap().open
+    }
+
+    fn call(&self, request: Request, idempotent: bool) -> Result<Response> {
+        if !idempotent {
+            return self.checkout().and_then(|client| self.send(client, request));
+        }
+
+        let config = &self.inner.config;
+        let mut backoff = config.backoff;
+        let mut attempt = 0;
+        loop {
+            // Requests are only cloned when they may be sent again.
+            match self.checkout().and_then(|client| self.send(client, request.clone())) {
+                Err(ref err) if attempt < config.max_retries && !matches!(err.kind(), ErrorKind::Busy(_)) => {
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(config.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn send(&self, mut client: KvsClient, request: Request) -> Result<Response> {
+        match client.call(request) {
+            Ok(response) => {
+                self.checkin(client);
+                Ok(response)
+            }
+            Err(err) => {
+                self.discard();
+                Err(err)
+            }
+        }
+    }
+
+    /// Take a healthy connection out of the pool, opening a new one in place of broken ones.
+    fn checkout(&self) -> Result<KvsClient> {
+        let inner = &self.inner;
+        let deadline = Instant::now() + inner.config.checkout_timeout;
+        let mut state = inner.state.lock().unwrap();
+
+        loop {
+            if let Some(IdleClient { mut client, since }) = state.idle.pop() {
+                drop(state);
+                let fresh = since.elapsed() < inner.config.health_check_interval;
+                if !client.is_closed() && (fresh || client.ping().is_ok()) {
+                    return Ok(client);
+                }
+
+                state = inner.state.lock().unwrap();
+                state.open -= 1;
+                continue;
+            }
+
+            if state.open < inner.config.size {
+                state.open += 1;
+                drop(state);
+
+                return KvsClient::connect_as(
+                    &inner.addr,
+                    inner.config.timeouts,
+                    inner.config.credentials.as_ref(),
+                    inner.config.tls.as_ref(),
+                ).inspect_err(|_| self.discard());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::from(ErrorKind::Busy(
+                    "no connection of the pool is available".to_string(),
+                )));
+            }
+
+            state = inner.released.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    fn checkin(&self, client: KvsClient) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.idle.push(IdleClient {
+            client,
+            since: Instant::now(),
+        });
+        self.inner.released.notify_one();
+    }
+
+    /// Forget a connection which is broken, so that another one can be opened.
+    fn discard(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.open -= 1;
+        self.inner.released.notify_one();
+    }
+}
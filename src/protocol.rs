@@ -1,34 +1,362 @@
 #![allow(missing_docs)]
-use failure::_core::fmt::Display;
+use crate::auth::Credentials;
+use crate::error::{Error, ErrorKind};
+use crate::logging::RequestLogState;
+use crate::pubsub::SubscribeEvent;
+use crate::raft::{ClusterInfo, RaftRequest, RaftResponse};
+use crate::replication::{ReplicationInfo, SyncEvent};
+use crate::server::{Compaction, ServerInfo};
+use crate::shard::ShardMap;
+use crate::slowlog::SlowRequest;
+use crate::watch::WatchEvent;
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::io;
 
 /// Used to communicate between clients and server.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
+    /// Authenticate the connection, which is the only request a server requiring authentication
+    /// serves before it succeeds.
+    Auth { credentials: Credentials },
     Set { key: String, value: String },
     Get { key: String },
     Remove { key: String },
+    GetMany { keys: Vec<String> },
+    SetMany { pairs: Vec<(String, String)> },
+    RemoveMany { keys: Vec<String> },
+    Scan { prefix: String },
+    Ping,
+    /// Report the state of the server, such as its engine, its uptime and how many requests of
+    /// each type it replied.
+    Info,
+    /// Compact the storage of the engine, reclaiming the space taken by stale data.
+    Compact,
+    /// Get the requests which took longer than the threshold of the slow log, newest first, and
+    /// clear it if `reset` is set.
+    SlowLog { reset: bool },
+    /// Log the requests and responses of every connection, or of the connections of a client
+    /// given by its address with or without a port, at the info level rather than the debug level.
+    RequestLog { enabled: bool, client: Option<String> },
+    /// Sent by a follower to stream the writes of a leader after a given position,
+    /// replied by many `Response::Sync` until the connection breaks.
+    Sync { replid: Option<String>, offset: u64 },
+    ReplicationInfo,
+    /// Sent between the nodes of a Raft cluster.
+    Raft(RaftRequest),
+    ClusterInfo,
+    /// Add a member to a Raft cluster, which is served by the leader.
+    AddMember { addr: String },
+    /// Remove a member from a Raft cluster, which is served by the leader.
+    RemoveMember { addr: String },
+    ShardMap,
+    /// Replace the shard map of a node by a newer version.
+    SetShardMap { map: ShardMap },
+    /// Move a shard to another node, which is served by the node serving the shard.
+    MoveShard { shard: usize, to: String },
+    /// Sent by a node moving a shard, in chunks, to write the keys of the shard to the node it is
+    /// moved to. The first chunk clears whatever the node had left of the shard.
+    ImportShard {
+        shard: usize,
+        first: bool,
+        pairs: Vec<(String, String)>,
+        removed: Vec<String>,
+    },
+    /// Stream the changes of keys starting with a prefix, replied by many `Response::Watch`
+    /// until the connection breaks. A watcher resumes after the last change it was sent by
+    /// sending the stream and the seq of the change.
+    Watch {
+        prefix: String,
+        stream: Option<String>,
+        seq: u64,
+    },
+    /// Publish a message to the subscribers of a channel connected to the server.
+    Publish { channel: String, message: String },
+    /// Subscribe channels, and the channels matching glob-style patterns, replied by many
+    /// `Response::Subscribe` until the connection breaks.
+    Subscribe {
+        channels: Vec<String>,
+        patterns: Vec<String>,
+    },
 }
 
 /// Used to communicate between clients and server.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
-    // impl Trait can not be written here.
-    Set(Result<(), String>),
-    Get(Result<Option<String>, String>),
-    Remove(Result<(), String>),
+    /// Reply to `Request::Auth` with the user the connection is authenticated as.
+    Auth(Result<String, ProtocolError>),
+    Set(Result<(), ProtocolError>),
+    Get(Result<Option<String>, ProtocolError>),
+    Remove(Result<(), ProtocolError>),
+    GetMany(Result<Vec<Option<String>>, ProtocolError>),
+    SetMany(Result<(), ProtocolError>),
+    RemoveMany(Result<Vec<bool>, ProtocolError>),
+    Scan(Result<Vec<String>, ProtocolError>),
+    Pong,
+    Info(ServerInfo),
+    Compact(Result<Compaction, ProtocolError>),
+    SlowLog(Vec<SlowRequest>),
+    /// Reply to `Request::RequestLog` with the connections logged after the change.
+    RequestLog(RequestLogState),
+    Sync(SyncEvent),
+    ReplicationInfo(ReplicationInfo),
+    Raft(RaftResponse),
+    ClusterInfo(ClusterInfo),
+    /// Reply to `Request::AddMember` and `Request::RemoveMember` with the members after the change.
+    Members(Result<Vec<String>, ProtocolError>),
+    /// Reply to `Request::ShardMap`, `Request::SetShardMap` and `Request::MoveShard` with the shard
+    /// map of the node after the request.
+    ShardMap(Result<ShardMap, ProtocolError>),
+    ImportShard(Result<(), ProtocolError>),
+    Watch(WatchEvent),
+    /// Reply to `Request::Publish` with the number of subscribers the message is delivered to.
+    Publish(Result<usize, ProtocolError>),
+    Subscribe(SubscribeEvent),
+    /// Reply to a request which can not be served at all, such as one which can not be parsed.
+    Error(ProtocolError),
+}
+
+/// A request tagged with an id, which the server echoes in the response replying to it.
+///
+/// Frames are written as JSON, one per line, so that clients can pipeline many requests
+/// before reading any response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequestFrame {
+    pub id: u64,
+    pub request: Request,
+}
+
+/// A response tagged with the id of the request it replies to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseFrame {
+    pub id: u64,
+    pub response: Response,
+}
+
+/// The kind of an error which the server replies with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    NotFound,
+    Io,
+    Corruption,
+    Busy,
+    Unauthorized,
+    InvalidRequest,
+    ReadOnly,
+    NotLeader,
+    Moved,
+    Internal,
+}
+
+/// An error which the server replies with, typed by its code so that clients never need to
+/// look into the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// The address of the server to send the request to instead, such as the leader of a cluster
+    /// or the node serving the shard of a key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ProtocolError {
+            code,
+            message: message.into(),
+            redirect: None,
+        }
+    }
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.message, f)
+    }
+}
+
+impl From<&ErrorKind> for ErrorCode {
+    fn from(kind: &ErrorKind) -> Self {
+        match kind {
+            ErrorKind::KeyNotFound => ErrorCode::NotFound,
+            ErrorKind::Io(_) | ErrorKind::Timeout(_) => ErrorCode::Io,
+            ErrorKind::Sled(sled::Error::Io(_)) => ErrorCode::Io,
+            ErrorKind::Sled(sled::Error::Corruption { .. }) => ErrorCode::Corruption,
+            ErrorKind::Sled(_) => ErrorCode::Internal,
+            ErrorKind::Serde(_) | ErrorKind::FromUtf8Error | ErrorKind::Corruption(_) => ErrorCode::Corruption,
+            ErrorKind::Busy(_) => ErrorCode::Busy,
+            ErrorKind::Unauthorized(_) => ErrorCode::Unauthorized,
+            ErrorKind::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            ErrorKind::ReadOnly(_) => ErrorCode::ReadOnly,
+            ErrorKind::NotLeader { .. } => ErrorCode::NotLeader,
+            ErrorKind::Moved { .. } => ErrorCode::Moved,
+            ErrorKind::StringError(_) | ErrorKind::Tls(_) | ErrorKind::UnexpectedError(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+impl From<Error> for ProtocolError {
+    fn from(err: Error) -> Self {
+        let code = ErrorCode::from(err.kind());
+
+        // Errors carrying a message are sent without the prefix of their display.
+        let message = match err.kind() {
+            ErrorKind::StringError(message)
+            | ErrorKind::Corruption(message)
+            | ErrorKind::Busy(message)
+            | ErrorKind::Unauthorized(message)
+            | ErrorKind::InvalidRequest(message)
+            | ErrorKind::ReadOnly(message)
+            | ErrorKind::NotLeader { message, .. }
+            | ErrorKind::Moved { message, .. } => message.clone(),
+            _ => err.to_string(),
+        };
+        let redirect = match err.kind() {
+            ErrorKind::NotLeader { leader, .. } => leader.clone(),
+            ErrorKind::Moved { node, .. } => Some(node.clone()),
+            _ => None,
+        };
+
+        ProtocolError {
+            code,
+            message,
+            redirect,
+        }
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(err: ProtocolError) -> Self {
+        let ProtocolError {
+            code,
+            message,
+            redirect,
+        } = err;
+        let kind = match code {
+            ErrorCode::NotFound => ErrorKind::KeyNotFound,
+            ErrorCode::Io => ErrorKind::Io(io::Error::other(message)),
+            ErrorCode::Corruption => ErrorKind::Corruption(message),
+            ErrorCode::Busy => ErrorKind::Busy(message),
+            ErrorCode::Unauthorized => ErrorKind::Unauthorized(message),
+            ErrorCode::InvalidRequest => ErrorKind::InvalidRequest(message),
+            ErrorCode::ReadOnly => ErrorKind::ReadOnly(message),
+            ErrorCode::NotLeader => ErrorKind::NotLeader {
+                message,
+                leader: redirect,
+            },
+            ErrorCode::Moved => ErrorKind::Moved {
+                message,
+                node: redirect.unwrap_or_default(),
+            },
+            ErrorCode::Internal => ErrorKind::StringError(message),
+        };
+
+        Error::from(kind)
+    }
+}
+
+impl Request {
+    /// Get the name of the type of the request, such as `Get`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Auth { .. } => "Auth",
+            Request::Set { .. } => "Set",
+            Request::Get { .. } => "Get",
+            Request::Remove { .. } => "Remove",
+            Request::GetMany { .. } => "GetMany",
+            Request::SetMany { .. } => "SetMany",
+            Request::RemoveMany { .. } => "RemoveMany",
+            Request::Scan { .. } => "Scan",
+            Request::Ping => "Ping",
+            Request::Info => "Info",
+            Request::Compact => "Compact",
+            Request::SlowLog { .. } => "SlowLog",
+            Request::RequestLog { .. } => "RequestLog",
+            Request::Sync { .. } => "Sync",
+            Request::ReplicationInfo => "ReplicationInfo",
+            Request::Raft(_) => "Raft",
+            Request::ClusterInfo => "ClusterInfo",
+            Request::AddMember { .. } => "AddMember",
+            Request::RemoveMember { .. } => "RemoveMember",
+            Request::ShardMap => "ShardMap",
+            Request::SetShardMap { .. } => "SetShardMap",
+            Request::MoveShard { .. } => "MoveShard",
+            Request::ImportShard { .. } => "ImportShard",
+            Request::Watch { .. } => "Watch",
+            Request::Publish { .. } => "Publish",
+            Request::Subscribe { .. } => "Subscribe",
+        }
+    }
 }
 
 impl Response {
-    pub fn set(result: Result<(), impl Display>) -> Self {
-        Response::Set(result.map_err(|e| e.to_string()))
+    pub fn auth(result: Result<String, Error>) -> Self {
+        Response::Auth(result.map_err(ProtocolError::from))
+    }
+
+    pub fn set(result: Result<(), Error>) -> Self {
+        Response::Set(result.map_err(ProtocolError::from))
+    }
+
+    pub fn get(result: Result<Option<String>, Error>) -> Self {
+        Response::Get(result.map_err(ProtocolError::from))
+    }
+
+    pub fn remove(result: Result<(), Error>) -> Self {
+        Response::Remove(result.map_err(ProtocolError::from))
+    }
+
+    pub fn get_many(result: Result<Vec<Option<String>>, Error>) -> Self {
+        Response::GetMany(result.map_err(ProtocolError::from))
+    }
+
+    pub fn set_many(result: Result<(), Error>) -> Self {
+        Response::SetMany(result.map_err(ProtocolError::from))
+    }
+
+    pub fn remove_many(result: Result<Vec<bool>, Error>) -> Self {
+        Response::RemoveMany(result.map_err(ProtocolError::from))
+    }
+
+    pub fn scan(result: Result<Vec<String>, Error>) -> Self {
+        Response::Scan(result.map_err(ProtocolError::from))
+    }
+
+    pub fn members(result: Result<Vec<String>, Error>) -> Self {
+        Response::Members(result.map_err(ProtocolError::from))
+    }
+
+    pub fn shard_map(result: Result<ShardMap, Error>) -> Self {
+        Response::ShardMap(result.map_err(ProtocolError::from))
+    }
+
+    pub fn compact(result: Result<Compaction, Error>) -> Self {
+        Response::Compact(result.map_err(ProtocolError::from))
     }
 
-    pub fn get(result: Result<Option<String>, impl Display>) -> Self {
-        Response::Get(result.map_err(|e| e.to_string()))
+    pub fn import_shard(result: Result<(), Error>) -> Self {
+        Response::ImportShard(result.map_err(ProtocolError::from))
     }
 
-    pub fn remove(result: Result<(), impl Display>) -> Self {
-        Response::Remove(result.map_err(|e| e.to_string()))
+    /// Get the error of a response which failed, whatever the request was.
+    pub fn error(&self) -> Option<&ProtocolError> {
+        match self {
+            Response::Auth(Err(err))
+            | Response::Set(Err(err))
+            | Response::Get(Err(err))
+            | Response::Remove(Err(err))
+            | Response::GetMany(Err(err))
+            | Response::SetMany(Err(err))
+            | Response::RemoveMany(Err(err))
+            | Response::Scan(Err(err))
+            | Response::Members(Err(err))
+            | Response::ShardMap(Err(err))
+            | Response::ImportShard(Err(err))
+            | Response::Publish(Err(err))
+            | Response::Compact(Err(err))
+            | Response::Error(err) => Some(err),
+            _ => None,
+        }
     }
 }
@@ -0,0 +1,209 @@
+use crate::error::Error;
+use crate::protocol::{ErrorCode, ProtocolError, ResponseFrame};
+use crate::resp::glob_match;
+use crate::{Response, Result};
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// A subscriber is sent messages, or a heartbeat without messages, at least this often.
+const HEARTBEAT: Duration = Duration::from_secs(1);
+// A subscriber which has this many messages not sent yet is dropped.
+const QUEUE_LEN: usize = 4096;
+// Messages are sent in chunks of at most this many messages.
+const CHUNK_LEN: usize = 1024;
+// Patterns are at most this long and have at most this many stars, which bounds the time taken
+// to match them against every channel published to.
+const MAX_PATTERN_LEN: usize = 256;
+const MAX_PATTERN_STARS: usize = 16;
+
+/// A message published to a channel, as delivered to a subscriber.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    /// The channel the message was published to.
+    pub channel: String,
+    /// The pattern the channel matched, if the message is delivered for a pattern subscription.
+    pub pattern: Option<String>,
+    /// The message.
+    pub message: String,
+}
+
+/// The replies streamed by a server to a subscriber which sent `Request::Subscribe`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SubscribeEvent {
+    /// The subscriber is subscribed, and the messages published from now on follow.
+    Subscribed {
+        /// The channels subscribed by name.
+        channels: Vec<String>,
+        /// The glob-style patterns of the channels subscribed.
+        patterns: Vec<String>,
+    },
+    /// Messages published since the previous ones, in order, which are sent without messages
+    /// as heartbeats.
+    Messages(Vec<Message>),
+}
+
+/// The subscribers of the channels of a server, which messages published to the server are
+/// fanned out to.
+///
+/// Messages are neither stored nor replicated, so only the subscribers connected to the server
+/// when a message is published receive it.
+#[derive(Clone, Default)]
+pub(crate) struct PubSub(Arc<Mutex<Subscribers>>);
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    subscribers: HashMap<u64, Subscriber>,
+}
+
+struct Subscriber {
+    channels: HashSet<String>,
+    patterns: Arc<[String]>,
+    sender: SyncSender<Message>,
+}
+
+impl PubSub {
+    /// Publish a message to a channel, and return the number of subscribers it is delivered to.
+    /// A subscriber receives the message once if it subscribed the channel, however many times it
+    /// did, and once more for each of its patterns matching the channel, as redis does.
+    pub(crate) fn publish(&self, channel: String, message: String) -> usize {
+        // Patterns are matched once the lock is released, so that publishers and subscribers do
+        // not wait on each other.
+        let subscribers = {
+            let subscribers = self.0.lock().unwrap();
+            subscribers
+                .subscribers
+                .iter()
+                .filter(|(_, subscriber)| subscriber.channels.contains(&channel) || !subscriber.patterns.is_empty())
+                .map(|(id, subscriber)| {
+                    let subscribed = subscriber.channels.contains(&channel);
+                    (*id, subscribed, Arc::clone(&subscriber.patterns), subscriber.sender.clone())
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut behind = Vec::new();
+        let mut receivers = 0;
+
+        for (id, subscribed, patterns, sender) in subscribers {
+            let mut messages = Vec::new();
+            if subscribed {
+                messages.push(None);
+            }
+            messages.extend(
+                patterns
+                    .iter()
+                    .filter(|pattern| glob_match(pattern.as_bytes(), channel.as_bytes()))
+                    .map(|pattern| Some(pattern.clone())),
+            );
+            if messages.is_empty() {
+                continue;
+            }
+
+            receivers += 1;
+            for pattern in messages {
+                let message = Message {
+                    channel: channel.clone(),
+                    pattern,
+                    message: message.clone(),
+                };
+                if let Err(TrySendError::Full(_)) = sender.try_send(message) {
+                    behind.push(id);
+                    break;
+                }
+            }
+        }
+
+        // Dropping the sender tells the subscriber it fell behind, once it took the messages queued.
+        if !behind.is_empty() {
+            let mut subscribers = self.0.lock().unwrap();
+            for id in behind {
+                subscribers.subscribers.remove(&id);
+            }
+        }
+        receivers
+    }
+
+    fn subscribe(&self, channels: &[String], patterns: &[String]) -> (u64, Receiver<Message>) {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        let mut subscribers = self.0.lock().unwrap();
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        let mut seen = HashSet::new();
+        subscribers.subscribers.insert(id, Subscriber {
+            channels: channels.iter().cloned().collect(),
+            patterns: patterns.iter().filter(|pattern| seen.insert(*pattern)).cloned().collect(),
+            sender,
+        });
+
+        (id, receiver)
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.0.lock().unwrap().subscribers.remove(&id);
+    }
+
+    /// Stream the messages published to given channels, and to the channels matching given
+    /// glob-style patterns, to a subscriber which sent `Request::Subscribe` with a given id,
+    /// until the connection breaks.
+    pub(crate) fn serve_subscriber(
+        &self,
+        id: u64,
+        channels: Vec<String>,
+        patterns: Vec<String>,
+        writer: &mut impl Write,
+        logger: &Logger,
+    ) -> Result<()> {
+        let mut reply = |response: Response| -> Result<()> {
+            serde_json::to_writer(&mut *writer, &ResponseFrame { id, response })?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+            Ok(())
+        };
+
+        if patterns
+            .iter()
+            .any(|pattern| pattern.len() > MAX_PATTERN_LEN || pattern.matches('*').count() > MAX_PATTERN_STARS)
+        {
+            let err = ProtocolError::new(
+                ErrorCode::InvalidRequest,
+                format!("patterns are limited to {} bytes and {} stars", MAX_PATTERN_LEN, MAX_PATTERN_STARS),
+            );
+            reply(Response::Error(err.clone()))?;
+            return Err(Error::from(err));
+        }
+
+        let (subscriber, receiver) = self.subscribe(&channels, &patterns);
+        let result: Result<()> = (|| {
+            reply(Response::Subscribe(SubscribeEvent::Subscribed { channels, patterns }))?;
+            loop {
+                let messages = match receiver.recv_timeout(HEARTBEAT) {
+                    Ok(message) => {
+                        let mut messages = vec![message];
+                        messages.extend(receiver.try_iter().take(CHUNK_LEN - 1));
+                        messages
+                    }
+                    Err(RecvTimeoutError::Timeout) => Vec::new(),
+                    // The subscriber is told why it is dropped.
+                    Err(RecvTimeoutError::Disconnected) => {
+                        info!(logger, "dropping a subscriber which fell behind");
+                        let err = ProtocolError::new(
+                            ErrorCode::Busy,
+                            format!("the subscriber fell behind by {} messages", QUEUE_LEN),
+                        );
+                        reply(Response::Error(err.clone()))?;
+                        return Err(Error::from(err));
+                    }
+                };
+                reply(Response::Subscribe(SubscribeEvent::Messages(messages)))?;
+            }
+        })();
+
+        self.unsubscribe(subscriber);
+        result
+    }
+}
@@ -0,0 +1,1260 @@
+use crate::client::{KvsClient, Timeouts};
+use crate::engine::{EngineStats, KvsEngine};
+use crate::watch::ChangeFeed;
+use crate::error::{Error, ErrorKind};
+use crate::replication::Command;
+use crate::{ClientTls, Credentials, Request, Response, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use slog::{error, info, warn, Logger};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use storage::{SnapshotMeta, Storage};
+
+pub use storage::{Entry, Payload};
+
+mod storage;
+
+// A leader sends entries at least this often, so that followers can tell a dead leader from an idle one.
+const HEARTBEAT: Duration = Duration::from_millis(100);
+// A follower starts an election when it hears nothing from a leader for a random timeout
+// between this and twice this.
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+const RPC_TIMEOUT: Duration = Duration::from_millis(500);
+// How long a request waits for a quorum of the cluster before giving up.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Entries and snapshots are sent in chunks of at most this many entries or keys.
+const CHUNK_LEN: usize = 1024;
+// The directory of the Raft state in the data directory of a node.
+const RAFT_DIR: &str = "raft";
+
+/// The messages between the nodes of a Raft cluster, replied by `RaftResponse`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftRequest {
+    /// Sent by a candidate to get the vote of a node.
+    RequestVote {
+        /// The term of the candidate.
+        term: u64,
+        /// The address of the candidate.
+        candidate: String,
+        /// The index of the last entry of the candidate.
+        last_log_index: u64,
+        /// The term of the last entry of the candidate.
+        last_log_term: u64,
+    },
+    /// Sent by the leader to replicate entries, and as a heartbeat without entries.
+    AppendEntries {
+        /// The term of the leader.
+        term: u64,
+        /// The address of the leader.
+        leader: String,
+        /// The index of the entry before the first one sent.
+        prev_log_index: u64,
+        /// The term of the entry before the first one sent.
+        prev_log_term: u64,
+        /// The entries, in order.
+        entries: Vec<Entry>,
+        /// The commit index of the leader.
+        leader_commit: u64,
+    },
+    /// Sent by the leader, in chunks, to a node which is behind the compacted log of the leader.
+    InstallSnapshot {
+        /// The term of the leader.
+        term: u64,
+        /// The address of the leader.
+        leader: String,
+        /// The index of the last entry covered by the snapshot.
+        last_index: u64,
+        /// The term of the last entry covered by the snapshot.
+        last_term: u64,
+        /// The members as of the last entry covered by the snapshot.
+        members: Vec<String>,
+        /// The keys and values of the chunk.
+        pairs: Vec<(String, String)>,
+        /// Whether it is the first chunk.
+        first: bool,
+        /// Whether it is the last chunk.
+        done: bool,
+    },
+}
+
+/// The replies to `RaftRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftResponse {
+    /// Reply to `RaftRequest::RequestVote`.
+    Vote {
+        /// The term of the node, for the candidate to update itself.
+        term: u64,
+        /// Whether the node voted for the candidate.
+        granted: bool,
+    },
+    /// Reply to `RaftRequest::AppendEntries`.
+    Append {
+        /// The term of the node, for the leader to update itself.
+        term: u64,
+        /// Whether the log of the node matched the entry before the ones sent.
+        success: bool,
+        /// The index of the next entry to send, which is a guess when the log did not match.
+        next_index: u64,
+    },
+    /// Reply to `RaftRequest::InstallSnapshot`.
+    Snapshot {
+        /// The term of the node, for the leader to update itself.
+        term: u64,
+        /// Whether the chunk was applied, which fails when the previous chunks are missing.
+        success: bool,
+    },
+}
+
+/// The role of a node in a Raft cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaftRole {
+    /// A node replicating the log of the leader.
+    Follower,
+    /// A node asking for votes to become the leader.
+    Candidate,
+    /// The node serving requests and replicating its log to the others.
+    Leader,
+}
+
+/// The Raft state of a node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterInfo {
+    /// The address of the node.
+    pub id: String,
+    /// The role of the node.
+    pub role: RaftRole,
+    /// The current term of the node.
+    pub term: u64,
+    /// The address of the leader, if it is known.
+    pub leader: Option<String>,
+    /// The addresses of the members of the cluster.
+    pub members: Vec<String>,
+    /// The index of the last entry known to be committed.
+    pub commit_index: u64,
+    /// The index of the last entry applied to the engine.
+    pub last_applied: u64,
+    /// The index of the last entry of the log.
+    pub last_index: u64,
+    /// The index of the last entry compacted into a snapshot.
+    pub snapshot_index: u64,
+}
+
+/// The configuration of a node of a Raft cluster.
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    /// The address of the node, by which the other members reach it.
+    pub id: String,
+    /// The addresses of the members when the cluster starts, which include the node itself,
+    /// or none for a node waiting to be added to a running cluster.
+    /// They are ignored when the node restarts, since its log knows the latest members.
+    pub members: Vec<String>,
+    /// The log is compacted once it holds this many applied entries.
+    pub snapshot_threshold: u64,
+    /// The credentials the node authenticates to the other members with, if they require them.
+    pub credentials: Option<Credentials>,
+    /// The TLS settings the node connects to the other members with, if they require TLS.
+    pub tls: Option<ClientTls>,
+}
+
+impl RaftConfig {
+    /// Configure a node starting a cluster of given members, or joining one if there are none.
+    pub fn new(id: impl Into<String>, members: Vec<String>) -> Self {
+        RaftConfig {
+            id: id.into(),
+            members,
+            snapshot_threshold: 10_000,
+            credentials: None,
+            tls: None,
+        }
+    }
+}
+
+/// The Raft state of a server, given to `KvsServer::raft` so that it can serve the messages of
+/// the other nodes, report the state and change the members.
+#[derive(Clone)]
+pub struct Raft(Arc<dyn Consensus>);
+
+impl Raft {
+    /// Get the Raft state of the node.
+    pub fn info(&self) -> ClusterInfo {
+        self.0.info()
+    }
+
+    pub(crate) fn handle(&self, request: RaftRequest) -> Result<RaftResponse> {
+        self.0.handle(request)
+    }
+
+    /// Add or remove a member through the leader, and return the members once the change is
+    /// committed.
+    pub(crate) fn change_members(&self, add: Option<String>, remove: Option<String>) -> Result<Vec<String>> {
+        self.0.change_members(add, remove)
+    }
+}
+
+trait Consensus: Send + Sync {
+    fn info(&self) -> ClusterInfo;
+    fn handle(&self, request: RaftRequest) -> Result<RaftResponse>;
+    fn change_members(&self, add: Option<String>, remove: Option<String>) -> Result<Vec<String>>;
+}
+
+/// An engine replicated by a Raft cluster.
+///
+/// Writes are appended to the log of the leader and applied to the engine of every node once a
+/// majority of the members has them. Reads are served by the leader after it checks that it is
+/// still the leader, so that they see every write committed before. Other nodes refuse requests
+/// with `ErrorKind::NotLeader`, which tells clients where the leader is.
+///
+/// Members are added and removed one at a time through the leader, and the log is compacted
+/// once it holds `RaftConfig::snapshot_threshold` applied entries, since the engine itself is
+/// the snapshot. A node which is behind the compacted log gets the keys and values of the
+/// engine of the leader.
+#[derive(Clone)]
+pub struct RaftNode<E: KvsEngine> {
+    engine: E,
+    shared: Arc<Shared>,
+}
+
+impl<E: KvsEngine> RaftNode<E> {
+    /// Start a node in the background, where `path` is the data directory of the engine.
+    pub fn start(engine: E, config: RaftConfig, path: impl Into<PathBuf>, logger: Logger) -> Result<RaftNode<E>> {
+        if !config.members.is_empty() && !config.members.contains(&config.id) {
+            return Err(Error::from(ErrorKind::UnexpectedError(
+                "The members of a cluster must include the node",
+            )));
+        }
+
+        let storage = Storage::open(path.into().join(RAFT_DIR), config.members)?;
+        let snapshot_index = storage.snapshot().last_index;
+        let mut state = State {
+            storage,
+            role: RaftRole::Follower,
+            leader: None,
+            commit_index: snapshot_index,
+            last_applied: snapshot_index,
+            election_deadline: Instant::now(),
+            leader_contact: None,
+            votes: HashSet::new(),
+            progress: HashMap::new(),
+            replicators: HashMap::new(),
+            term_start: 0,
+            heartbeats: 0,
+            waiting: HashMap::new(),
+            installing: None,
+        };
+        state.reset_election_timer();
+        info!(logger, "raft node started";
+            "term" => state.storage.term(),
+            "members" => format!("{:?}", state.storage.members()),
+            "last_index" => state.storage.last_index(),
+            "snapshot_index" => snapshot_index
+        );
+
+        let shared = Arc::new(Shared {
+            id: config.id,
+            snapshot_threshold: config.snapshot_threshold,
+            credentials: config.credentials,
+            tls: config.tls,
+            state: Mutex::new(state),
+            changed: Condvar::new(),
+            applying: Mutex::new(()),
+            peers: Mutex::new(HashMap::new()),
+            logger,
+        });
+
+        let (ticker, ticker_engine) = (Arc::clone(&shared), engine.clone());
+        thread::spawn(move || tick(ticker, ticker_engine));
+        let (applier, applier_engine) = (Arc::clone(&shared), engine.clone());
+        thread::spawn(move || apply_committed(applier, applier_engine));
+
+        Ok(RaftNode { engine, shared })
+    }
+
+    /// Get the Raft state, to be given to `KvsServer::raft`.
+    pub fn raft(&self) -> Raft {
+        Raft(Arc::new(Handle {
+            engine: Mutex::new(self.engine.clone()),
+            shared: Arc::clone(&self.shared),
+        }))
+    }
+}
+
+impl<E: KvsEngine> KvsEngine for RaftNode<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.shared.propose(vec![Command::Set { key, value }]).map(|_| ())
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.shared.read_barrier()?;
+        self.engine.get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        match self.shared.propose(vec![Command::Remove { key }])?.first() {
+            Some(true) => Ok(()),
+            _ => Err(Error::from(ErrorKind::KeyNotFound)),
+        }
+    }
+
+    fn scan(&self, prefix: String) -> Result<Vec<String>> {
+        self.shared.read_barrier()?;
+        self.engine.scan(prefix)
+    }
+
+    fn scan_after(&self, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        self.shared.read_barrier()?;
+        self.engine.scan_after(after, limit)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.shared.read_barrier()?;
+        self.engine.get_many(keys)
+    }
+
+    fn set_many(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        let commands = pairs
+            .into_iter()
+            .map(|(key, value)| Command::Set { key, value })
+            .collect();
+        self.shared.propose(commands).map(|_| ())
+    }
+
+    fn remove_many(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        let commands = keys.into_iter().map(|key| Command::Remove { key }).collect();
+        self.shared.propose(commands)
+    }
+
+    fn changes(&self) -> Option<ChangeFeed> {
+        self.engine.changes()
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.engine.compact()
+    }
+
+    fn stats(&self) -> Option<EngineStats> {
+        self.engine.stats()
+    }
+}
+
+struct Handle<E: KvsEngine> {
+    engine: Mutex<E>,
+    shared: Arc<Shared>,
+}
+
+impl<E: KvsEngine> Consensus for Handle<E> {
+    fn info(&self) -> ClusterInfo {
+        let state = self.shared.state.lock().unwrap();
+        ClusterInfo {
+            id: self.shared.id.clone(),
+            role: state.role,
+            term: state.storage.term(),
+            leader: state.leader.clone(),
+            members: state.storage.members().to_vec(),
+            commit_index: state.commit_index,
+            last_applied: state.last_applied,
+            last_index: state.storage.last_index(),
+            snapshot_index: state.storage.snapshot().last_index,
+        }
+    }
+
+    fn handle(&self, request: RaftRequest) -> Result<RaftResponse> {
+        match request {
+            RaftRequest::RequestVote {
+                term,
+                candidate,
+                last_log_index,
+                last_log_term,
+            } => self.shared.on_request_vote(term, candidate, (last_log_term, last_log_index)),
+            RaftRequest::AppendEntries {
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => self
+                .shared
+                .on_append_entries(term, leader, (prev_log_index, prev_log_term), entries, leader_commit),
+            RaftRequest::InstallSnapshot {
+                term,
+                leader,
+                last_index,
+                last_term,
+                members,
+                pairs,
+                first,
+                done,
+            } => {
+                let engine = self.engine.lock().unwrap().clone();
+                let snapshot = SnapshotMeta {
+                    last_index,
+                    last_term,
+                    members,
+                };
+                self.shared
+                    .on_install_snapshot(&engine, term, leader, snapshot, pairs, (first, done))
+            }
+        }
+    }
+
+    fn change_members(&self, add: Option<String>, remove: Option<String>) -> Result<Vec<String>> {
+        self.shared.change_members(add, remove)
+    }
+}
+
+struct Shared {
+    id: String,
+    snapshot_threshold: u64,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
+    state: Mutex<State>,
+    // Notified whenever the state changes in a way someone may wait for.
+    changed: Condvar,
+    // Held while entries or a snapshot are applied to the engine, so that they never interleave.
+    applying: Mutex<()>,
+    peers: Mutex<HashMap<String, Arc<Peer>>>,
+    logger: Logger,
+}
+
+struct State {
+    storage: Storage,
+    role: RaftRole,
+    leader: Option<String>,
+    commit_index: u64,
+    last_applied: u64,
+    election_deadline: Instant,
+    leader_contact: Option<Instant>,
+    votes: HashSet<String>,
+    // The replication state of each member, kept by the leader.
+    progress: HashMap<String, Progress>,
+    // The term of the replicator thread running for each member.
+    replicators: HashMap<String, u64>,
+    // The index of the first entry appended by the leader in its term.
+    term_start: u64,
+    // Bumped when a read asks replicators for a heartbeat right away.
+    heartbeats: u64,
+    // Writes proposed by this node, with the term of the entry and the keys removed once applied.
+    waiting: HashMap<u64, Option<(u64, Vec<bool>)>>,
+    installing: Option<Installing>,
+}
+
+impl State {
+    fn reset_election_timer(&mut self) {
+        let min = ELECTION_TIMEOUT.as_millis() as u64;
+        let timeout = rand::thread_rng().gen_range(min, 2 * min);
+        self.election_deadline = Instant::now() + Duration::from_millis(timeout);
+    }
+
+    fn is_leader_of(&self, term: u64) -> bool {
+        self.role == RaftRole::Leader && self.storage.term() == term
+    }
+
+    fn not_leader(&self) -> Error {
+        let message = match &self.leader {
+            Some(leader) => format!("the leader is {}", leader),
+            None => "the leader is unknown".to_string(),
+        };
+        Error::from(ErrorKind::NotLeader {
+            message,
+            leader: self.leader.clone(),
+        })
+    }
+}
+
+struct Progress {
+    next_index: u64,
+    match_index: u64,
+    // When the latest request which the member answered was sent.
+    acked: Option<Instant>,
+}
+
+/// A snapshot being installed from the leader, with the keys which are not in it yet.
+struct Installing {
+    snapshot: SnapshotMeta,
+    stale: HashSet<String>,
+}
+
+fn quorum(members: usize) -> usize {
+    members / 2 + 1
+}
+
+impl Shared {
+    fn peer(&self, addr: &str) -> Arc<Peer> {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = peers.entry(addr.to_string()).or_insert_with(|| {
+            Arc::new(Peer {
+                addr: addr.to_string(),
+                credentials: self.credentials.clone(),
+                tls: self.tls.clone(),
+                client: Mutex::new(None),
+            })
+        });
+        Arc::clone(peer)
+    }
+
+    /// Follow a newer term, stepping down if this node is a candidate or the leader.
+    fn observe_term(&self, state: &mut State, term: u64) -> Result<()> {
+        if term > state.storage.term() {
+            state.storage.set_state(term, None)?;
+            if state.role != RaftRole::Follower {
+                info!(self.logger, "stepped down"; "term" => term);
+            }
+            state.role = RaftRole::Follower;
+            state.leader = None;
+            state.votes.clear();
+            self.changed.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Follow the leader of the current term, which just sent a message.
+    fn follow(&self, state: &mut State, leader: String) {
+        if state.role != RaftRole::Follower {
+            state.role = RaftRole::Follower;
+            self.changed.notify_all();
+        }
+        if state.leader.as_ref() != Some(&leader) {
+            info!(self.logger, "following a new leader"; "leader" => &leader, "term" => state.storage.term());
+            state.leader = Some(leader);
+        }
+        state.leader_contact = Some(Instant::now());
+        state.reset_election_timer();
+    }
+
+    fn start_election(self: &Arc<Self>, state: &mut State) -> Result<()> {
+        let term = state.storage.term() + 1;
+        state.storage.set_state(term, Some(self.id.clone()))?;
+        state.role = RaftRole::Candidate;
+        state.leader = None;
+        state.votes = std::iter::once(self.id.clone()).collect();
+        state.reset_election_timer();
+        info!(self.logger, "election started"; "term" => term);
+
+        let request = RaftRequest::RequestVote {
+            term,
+            candidate: self.id.clone(),
+            last_log_index: state.storage.last_index(),
+            last_log_term: state.storage.last_term(),
+        };
+        for member in state.storage.members().iter().filter(|member| **member != self.id) {
+            let (shared, member, request) = (Arc::clone(self), member.clone(), request.clone());
+            thread::spawn(move || {
+                if let Ok(RaftResponse::Vote { term: voter_term, granted }) = shared.peer(&member).call(request) {
+                    let mut state = shared.state.lock().unwrap();
+                    if let Err(err) = shared.on_vote(&mut state, term, member, voter_term, granted) {
+                        error!(shared.logger, "can not count a vote"; "error" => format!("{}", err));
+                    }
+                }
+            });
+        }
+
+        self.count_votes(state)
+    }
+
+    fn on_vote(&self, state: &mut State, term: u64, member: String, voter_term: u64, granted: bool) -> Result<()> {
+        self.observe_term(state, voter_term)?;
+        if granted && state.role == RaftRole::Candidate && state.storage.term() == term {
+            state.votes.insert(member);
+            self.count_votes(state)?;
+        }
+        Ok(())
+    }
+
+    fn count_votes(&self, state: &mut State) -> Result<()> {
+        let members = state.storage.members();
+        let votes = members.iter().filter(|member| state.votes.contains(*member)).count();
+        if state.role == RaftRole::Candidate && votes >= quorum(members.len()) {
+            self.become_leader(state)?;
+        }
+        Ok(())
+    }
+
+    fn become_leader(&self, state: &mut State) -> Result<()> {
+        let term = state.storage.term();
+        let index = state.storage.last_index() + 1;
+        state.storage.append(vec![Entry {
+            index,
+            term,
+            payload: Payload::Noop,
+        }])?;
+
+        info!(self.logger, "became the leader"; "term" => term);
+        state.role = RaftRole::Leader;
+        state.leader = Some(self.id.clone());
+        state.progress.clear();
+        state.term_start = index;
+        self.advance_commit(state);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Commit the latest entry of the current term which a majority of the members has.
+    fn advance_commit(&self, state: &mut State) {
+        let term = state.storage.term();
+        let members = state.storage.members();
+        for index in (state.commit_index + 1..=state.storage.last_index()).rev() {
+            // Entries of previous terms are only committed by committing one of the current term.
+            if state.storage.term_at(index) != Some(term) {
+                break;
+            }
+
+            let replicated = members
+                .iter()
+                .filter(|member| {
+                    **member == self.id || state.progress.get(*member).is_some_and(|p| p.match_index >= index)
+                })
+                .count();
+            if replicated >= quorum(members.len()) {
+                state.commit_index = index;
+                self.changed.notify_all();
+                break;
+            }
+        }
+
+        // A leader removed from the cluster leads until its removal is committed.
+        let removed = !state.storage.members().contains(&self.id);
+        if removed && state.role == RaftRole::Leader && state.storage.members_index() <= state.commit_index {
+            info!(self.logger, "stepped down after leaving the cluster");
+            state.role = RaftRole::Follower;
+            state.leader = None;
+            self.changed.notify_all();
+        }
+    }
+
+    fn on_request_vote(&self, term: u64, candidate: String, last_log: (u64, u64)) -> Result<RaftResponse> {
+        let mut state = self.state.lock().unwrap();
+
+        // Candidates are ignored while the leader is alive, so that members removed from the
+        // cluster can not disrupt it with elections.
+        let leader_alive = state.role == RaftRole::Leader
+            || state.leader_contact.is_some_and(|contact| contact.elapsed() < ELECTION_TIMEOUT);
+        if term > state.storage.term() && leader_alive {
+            return Ok(RaftResponse::Vote {
+                term: state.storage.term(),
+                granted: false,
+            });
+        }
+
+        self.observe_term(&mut state, term)?;
+        let up_to_date = last_log >= (state.storage.last_term(), state.storage.last_index());
+        let free = state.storage.voted_for().is_none_or(|voted_for| voted_for == candidate);
+        let granted = term == state.storage.term() && up_to_date && free;
+        if granted {
+            state.storage.set_state(term, Some(candidate))?;
+            state.reset_election_timer();
+        }
+
+        Ok(RaftResponse::Vote {
+            term: state.storage.term(),
+            granted,
+        })
+    }
+
+    fn on_append_entries(
+        &self,
+        term: u64,
+        leader: String,
+        (prev_log_index, prev_log_term): (u64, u64),
+        entries: Vec<Entry>,
+        leader_commit: u64,
+    ) -> Result<RaftResponse> {
+        let mut state = self.state.lock().unwrap();
+        if term < state.storage.term() {
+            return Ok(RaftResponse::Append {
+                term: state.storage.term(),
+                success: false,
+                next_index: 0,
+            });
+        }
+
+        self.observe_term(&mut state, term)?;
+        self.follow(&mut state, leader);
+
+        let storage = &mut state.storage;
+        if prev_log_index > storage.last_index() {
+            return Ok(RaftResponse::Append {
+                term,
+                success: false,
+                next_index: storage.last_index() + 1,
+            });
+        }
+
+        // Entries up to the snapshot are committed, so they match the log of the leader.
+        let snapshot_index = storage.snapshot().last_index;
+        if prev_log_index >= snapshot_index && storage.term_at(prev_log_index) != Some(prev_log_term) {
+            // The leader skips the whole term of the conflicting entry.
+            let conflicting = storage.term_at(prev_log_index);
+            let mut next_index = prev_log_index;
+            while next_index > snapshot_index + 1 && storage.term_at(next_index - 1) == conflicting {
+                next_index -= 1;
+            }
+            return Ok(RaftResponse::Append {
+                term,
+                success: false,
+                next_index,
+            });
+        }
+
+        let matched = (prev_log_index + entries.len() as u64).max(snapshot_index);
+        let mut new = Vec::new();
+        for entry in entries.into_iter().filter(|entry| entry.index > snapshot_index) {
+            match storage.term_at(entry.index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => storage.truncate(entry.index)?,
+                None => (),
+            }
+            new.push(entry);
+        }
+        if !new.is_empty() {
+            storage.append(new)?;
+        }
+
+        // A snapshot from a previous leader is given up, since the log matches the new one.
+        state.installing = None;
+        if leader_commit > state.commit_index {
+            state.commit_index = leader_commit.min(matched).max(state.commit_index);
+            self.changed.notify_all();
+        }
+
+        Ok(RaftResponse::Append {
+            term,
+            success: true,
+            next_index: matched + 1,
+        })
+    }
+
+    fn on_install_snapshot<E: KvsEngine>(
+        &self,
+        engine: &E,
+        term: u64,
+        leader: String,
+        snapshot: SnapshotMeta,
+        pairs: Vec<(String, String)>,
+        (first, done): (bool, bool),
+    ) -> Result<RaftResponse> {
+        let _applying = self.applying.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        if term < state.storage.term() {
+            return Ok(RaftResponse::Snapshot {
+                term: state.storage.term(),
+                success: false,
+            });
+        }
+
+        self.observe_term(&mut state, term)?;
+        self.follow(&mut state, leader);
+
+        // The engine already has a snapshot which is not newer.
+        if snapshot.last_index <= state.commit_index {
+            return Ok(RaftResponse::Snapshot { term, success: true });
+        }
+
+        let installing = match state.installing.take() {
+            _ if first => None,
+            Some(installing) if installing.snapshot.last_index == snapshot.last_index => Some(installing),
+            _ => return Ok(RaftResponse::Snapshot { term, success: false }),
+        };
+        drop(state);
+
+        // The applier waits while `applying` is held, so the engine is only written here.
+        let mut installing = match installing {
+            Some(installing) => installing,
+            None => {
+                info!(self.logger, "installing a snapshot"; "last_index" => snapshot.last_index);
+                Installing {
+                    stale: engine.scan(String::new())?.into_iter().collect(),
+                    snapshot,
+                }
+            }
+        };
+        for (key, _) in &pairs {
+            installing.stale.remove(key);
+        }
+        engine.set_many(pairs)?;
+
+        let mut state = self.state.lock().unwrap();
+        if !done {
+            state.installing = Some(installing);
+            return Ok(RaftResponse::Snapshot { term, success: true });
+        }
+
+        engine.remove_many(installing.stale.into_iter().collect())?;
+        let last_index = installing.snapshot.last_index;
+        state.storage.install(installing.snapshot)?;
+        state.commit_index = state.commit_index.max(last_index);
+        state.last_applied = last_index;
+        info!(self.logger, "snapshot installed"; "last_index" => last_index);
+        self.changed.notify_all();
+
+        Ok(RaftResponse::Snapshot { term, success: true })
+    }
+
+    /// Append writes to the log of the leader, and wait until they are applied.
+    fn propose(&self, commands: Vec<Command>) -> Result<Vec<bool>> {
+        let mut state = self.state.lock().unwrap();
+        if state.role != RaftRole::Leader {
+            return Err(state.not_leader());
+        }
+
+        let term = state.storage.term();
+        let index = state.storage.last_index() + 1;
+        state.storage.append(vec![Entry {
+            index,
+            term,
+            payload: Payload::Write(commands),
+        }])?;
+        state.waiting.insert(index, None);
+        self.advance_commit(&mut state);
+        self.changed.notify_all();
+
+        let result = self.wait_as_leader(state, term, |state| matches!(state.waiting.get(&index), Some(Some(_))));
+        let mut state = match result {
+            Ok(state) => state,
+            Err(err) => {
+                self.state.lock().unwrap().waiting.remove(&index);
+                return Err(err);
+            }
+        };
+
+        match state.waiting.remove(&index) {
+            Some(Some((entry_term, removed))) if entry_term == term => Ok(removed),
+            _ => Err(Error::from(ErrorKind::Busy(
+                "the write was replaced by the log of another leader".to_string(),
+            ))),
+        }
+    }
+
+    /// Wait until the engine of the leader has every write committed before, and the node is
+    /// known to be the leader still, so that a read sees them.
+    fn read_barrier(&self) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        if state.role != RaftRole::Leader {
+            return Err(state.not_leader());
+        }
+
+        // The commit index is up to date once the first entry of the term is committed.
+        let term = state.storage.term();
+        let mut state = self.wait_as_leader(state, term, |state| state.commit_index >= state.term_start)?;
+        let read_index = state.commit_index;
+
+        let started = Instant::now();
+        state.heartbeats += 1;
+        self.changed.notify_all();
+        let state = self.wait_as_leader(state, term, |state| {
+            let members = state.storage.members();
+            let acks = members
+                .iter()
+                .filter(|member| {
+                    **member == self.id
+                        || state
+                            .progress
+                            .get(*member)
+                            .and_then(|progress| progress.acked)
+                            .is_some_and(|acked| acked >= started)
+                })
+                .count();
+            acks >= quorum(members.len())
+        })?;
+
+        self.wait_as_leader(state, term, |state| state.last_applied >= read_index)
+            .map(drop)
+    }
+
+    fn change_members(&self, add: Option<String>, remove: Option<String>) -> Result<Vec<String>> {
+        let mut state = self.state.lock().unwrap();
+        if state.role != RaftRole::Leader {
+            return Err(state.not_leader());
+        }
+
+        // Members change one at a time, so that the majorities of the old and new members overlap.
+        if state.storage.members_index() > state.commit_index || state.term_start > state.commit_index {
+            return Err(Error::from(ErrorKind::Busy(
+                "another membership change is in progress".to_string(),
+            )));
+        }
+
+        let mut members = state.storage.members().to_vec();
+        if let Some(addr) = add.filter(|addr| !members.contains(addr)) {
+            members.push(addr);
+        }
+        if let Some(addr) = remove {
+            members.retain(|member| *member != addr);
+        }
+        if members == state.storage.members() {
+            return Ok(members);
+        }
+        if members.is_empty() {
+            return Err(Error::from(ErrorKind::InvalidRequest(
+                "the last member of the cluster can not be removed".to_string(),
+            )));
+        }
+
+        let term = state.storage.term();
+        let index = state.storage.last_index() + 1;
+        state.storage.append(vec![Entry {
+            index,
+            term,
+            payload: Payload::Members(members.clone()),
+        }])?;
+        info!(self.logger, "changing members"; "members" => format!("{:?}", members));
+        self.advance_commit(&mut state);
+        self.changed.notify_all();
+
+        self.wait_as_leader(state, term, |state| state.commit_index >= index)
+            .map(|_| members)
+    }
+
+    /// Wait until a condition holds, as long as this node is the leader of a given term.
+    fn wait_as_leader<'a>(
+        &self,
+        mut state: MutexGuard<'a, State>,
+        term: u64,
+        done: impl Fn(&State) -> bool,
+    ) -> Result<MutexGuard<'a, State>> {
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        loop {
+            if done(&state) {
+                return Ok(state);
+            }
+            if !state.is_leader_of(term) {
+                return Err(Error::from(ErrorKind::Busy(
+                    "the leader stepped down, so the request may or may not be served".to_string(),
+                )));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::from(ErrorKind::Busy(
+                    "no majority of the cluster answered in time".to_string(),
+                )));
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Send entries to a member, and update its progress with the reply.
+    fn send_entries(&self, peer: &Peer, term: u64, request: RaftRequest) -> Result<()> {
+        let sent_at = Instant::now();
+        let response = peer.call(request)?;
+
+        let mut state = self.state.lock().unwrap();
+        let (peer_term, success, next_index) = match response {
+            RaftResponse::Append {
+                term,
+                success,
+                next_index,
+            } => (term, success, next_index),
+            _ => return Err(unexpected()),
+        };
+
+        self.observe_term(&mut state, peer_term)?;
+        if !state.is_leader_of(term) {
+            return Ok(());
+        }
+
+        let progress = state.progress.get_mut(&peer.addr).ok_or_else(unexpected)?;
+        progress.acked = Some(sent_at);
+        if success {
+            progress.match_index = progress.match_index.max(next_index - 1);
+            progress.next_index = next_index;
+            self.advance_commit(&mut state);
+        } else {
+            progress.next_index = next_index.min(progress.next_index.saturating_sub(1)).max(1);
+        }
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Send the engine of the leader to a member which is behind the compacted log.
+    fn send_snapshot<E: KvsEngine>(&self, peer: &Peer, engine: &E, term: u64, snapshot: SnapshotMeta) -> Result<()> {
+        info!(self.logger, "sending a snapshot"; "member" => &peer.addr, "last_index" => snapshot.last_index);
+
+        // The engine is read while entries are applied, which is fine since the entries after
+        // the snapshot are applied on it again in order.
+        let keys = engine.scan(String::new())?;
+        let mut chunks = keys.chunks(CHUNK_LEN).enumerate().peekable();
+        while let Some((index, chunk)) = chunks.next() {
+            let values = engine.get_many(chunk.to_vec())?;
+            let pairs = chunk
+                .iter()
+                .cloned()
+                .zip(values)
+                .filter_map(|(key, value)| value.map(|value| (key, value)))
+                .collect();
+
+            let done = chunks.peek().is_none();
+            let sent_at = Instant::now();
+            let response = peer.call(RaftRequest::InstallSnapshot {
+                term,
+                leader: self.id.clone(),
+                last_index: snapshot.last_index,
+                last_term: snapshot.last_term,
+                members: snapshot.members.clone(),
+                pairs,
+                first: index == 0,
+                done,
+            })?;
+
+            let mut state = self.state.lock().unwrap();
+            let (peer_term, success) = match response {
+                RaftResponse::Snapshot { term, success } => (term, success),
+                _ => return Err(unexpected()),
+            };
+            self.observe_term(&mut state, peer_term)?;
+            if !state.is_leader_of(term) {
+                return Ok(());
+            }
+            if !success {
+                return Err(Error::from(ErrorKind::Busy("the snapshot was interrupted".to_string())));
+            }
+
+            let progress = state.progress.get_mut(&peer.addr).ok_or_else(unexpected)?;
+            progress.acked = Some(sent_at);
+            if done {
+                progress.match_index = progress.match_index.max(snapshot.last_index);
+                progress.next_index = snapshot.last_index + 1;
+                self.advance_commit(&mut state);
+                self.changed.notify_all();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Start elections when the leader is silent, and start replicating to every member when this
+/// node is the leader.
+fn tick<E: KvsEngine>(shared: Arc<Shared>, engine: E) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if state.role == RaftRole::Leader {
+            let term = state.storage.term();
+            let members = state
+                .storage
+                .members()
+                .iter()
+                .filter(|member| **member != shared.id && state.replicators.get(*member) != Some(&term))
+                .cloned()
+                .collect::<Vec<String>>();
+
+            for member in members {
+                state.replicators.insert(member.clone(), term);
+                let (shared, engine) = (Arc::clone(&shared), engine.clone());
+                thread::spawn(move || replicate(shared, engine, member, term));
+            }
+
+            state = shared.changed.wait_timeout(state, HEARTBEAT).unwrap().0;
+            continue;
+        }
+
+        let now = Instant::now();
+        if now < state.election_deadline {
+            let timeout = state.election_deadline - now;
+            state = shared.changed.wait_timeout(state, timeout).unwrap().0;
+            continue;
+        }
+
+        // Nodes which are not members wait to be added by the leader.
+        if state.storage.members().contains(&shared.id) {
+            if let Err(err) = shared.start_election(&mut state) {
+                error!(shared.logger, "can not start an election"; "error" => format!("{}", err));
+            }
+        }
+        state.reset_election_timer();
+    }
+}
+
+/// Replicate the log of the leader to a member, until this node is no longer the leader of the
+/// given term or the member is removed.
+fn replicate<E: KvsEngine>(shared: Arc<Shared>, engine: E, member: String, term: u64) {
+    enum Next {
+        Entries(RaftRequest),
+        Snapshot(SnapshotMeta),
+    }
+
+    let peer = shared.peer(&member);
+    let mut reachable = true;
+    loop {
+        let (next, heartbeats) = {
+            let mut state = shared.state.lock().unwrap();
+            if !state.is_leader_of(term) || !state.storage.members().contains(&member) {
+                if state.replicators.get(&member) == Some(&term) {
+                    state.replicators.remove(&member);
+                }
+                return;
+            }
+
+            let last_index = state.storage.last_index();
+            let next_index = state
+                .progress
+                .entry(member.clone())
+                .or_insert(Progress {
+                    next_index: last_index + 1,
+                    match_index: 0,
+                    acked: None,
+                })
+                .next_index;
+
+            let next = if next_index <= state.storage.snapshot().last_index {
+                Next::Snapshot(state.storage.snapshot().clone())
+            } else {
+                let prev_log_index = next_index - 1;
+                Next::Entries(RaftRequest::AppendEntries {
+                    term,
+                    leader: shared.id.clone(),
+                    prev_log_index,
+                    prev_log_term: state.storage.term_at(prev_log_index).unwrap_or_default(),
+                    entries: state.storage.entries(next_index, last_index, CHUNK_LEN),
+                    leader_commit: state.commit_index,
+                })
+            };
+            (next, state.heartbeats)
+        };
+
+        let result = match next {
+            Next::Entries(request) => shared.send_entries(&peer, term, request),
+            Next::Snapshot(snapshot) => shared.send_snapshot(&peer, &engine, term, snapshot),
+        };
+        match result {
+            Ok(()) if !reachable => {
+                info!(shared.logger, "member is reachable again"; "member" => &member);
+                reachable = true;
+            }
+            Ok(()) => (),
+            Err(err) => {
+                if reachable {
+                    warn!(shared.logger, "can not replicate to a member";
+                        "member" => &member, "error" => format!("{}", err));
+                    reachable = false;
+                }
+                thread::sleep(HEARTBEAT);
+                continue;
+            }
+        }
+
+        // Wait for new entries, for a heartbeat to be due, or for a read asking for one.
+        let state = shared.state.lock().unwrap();
+        let _ = shared
+            .changed
+            .wait_timeout_while(state, HEARTBEAT, |state| {
+                let caught_up = state
+                    .progress
+                    .get(&member)
+                    .is_none_or(|progress| progress.next_index > state.storage.last_index());
+                state.is_leader_of(term) && state.heartbeats == heartbeats && caught_up
+            })
+            .unwrap();
+    }
+}
+
+/// Apply the committed entries to the engine in order, and compact the log.
+fn apply_committed<E: KvsEngine>(shared: Arc<Shared>, engine: E) {
+    loop {
+        drop(
+            shared
+                .changed
+                .wait_while(shared.state.lock().unwrap(), |state| {
+                    state.installing.is_some() || state.commit_index <= state.last_applied
+                })
+                .unwrap(),
+        );
+
+        let _applying = shared.applying.lock().unwrap();
+        let entries = {
+            let state = shared.state.lock().unwrap();
+            if state.installing.is_some() {
+                continue;
+            }
+            state.storage.entries(state.last_applied + 1, state.commit_index, CHUNK_LEN)
+        };
+        let last = match entries.last() {
+            Some(entry) => entry.index,
+            None => continue,
+        };
+
+        let mut results = Vec::new();
+        let applied: Result<()> = entries.into_iter().try_for_each(|entry| {
+            if let Payload::Write(commands) = entry.payload {
+                results.push((entry.index, entry.term, apply(&engine, commands)?));
+            }
+            Ok(())
+        });
+        if let Err(err) = applied {
+            // The entries are applied again, which is harmless.
+            error!(shared.logger, "can not apply entries"; "error" => format!("{}", err));
+            thread::sleep(HEARTBEAT);
+            continue;
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        state.last_applied = last;
+        for (index, term, removed) in results {
+            if let Some(result) = state.waiting.get_mut(&index) {
+                *result = Some((term, removed));
+            }
+        }
+
+        if last - state.storage.snapshot().last_index >= shared.snapshot_threshold {
+            match state.storage.compact(last) {
+                Ok(()) => info!(shared.logger, "log compacted"; "last_index" => last),
+                Err(err) => error!(shared.logger, "can not compact the log"; "error" => format!("{}", err)),
+            }
+        }
+        shared.changed.notify_all();
+    }
+}
+
+/// Apply writes to the engine, and tell whether each removed key existed.
+fn apply<E: KvsEngine>(engine: &E, commands: Vec<Command>) -> Result<Vec<bool>> {
+    let mut removed = Vec::new();
+    for command in commands {
+        match command {
+            Command::Set { key, value } => engine.set(key, value)?,
+            // Entries may be applied again after a restart, when the key is already removed.
+            Command::Remove { key } => removed.extend(engine.remove_many(vec![key])?),
+        }
+    }
+    Ok(removed)
+}
+
+/// Another member, reached over one connection which is opened again when it breaks.
+struct Peer {
+    addr: String,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
+    client: Mutex<Option<KvsClient>>,
+}
+
+impl Peer {
+    fn call(&self, request: RaftRequest) -> Result<RaftResponse> {
+        let mut client = self.client.lock().unwrap();
+        let connected = match client.take() {
+            Some(connected) => connected,
+            None => KvsClient::connect_as(
+                &self.addr,
+                Timeouts::all(RPC_TIMEOUT),
+                self.credentials.as_ref(),
+                self.tls.as_ref(),
+            )?,
+        };
+        let connected = client.insert(connected);
+
+        match connected.call(Request::Raft(request)) {
+            Ok(Response::Raft(response)) => Ok(response),
+            Ok(Response::Error(err)) => Err(err.into()),
+            Ok(_) => Err(unexpected()),
+            Err(err) => {
+                *client = None;
+                Err(err)
+            }
+        }
+    }
+}
+
+fn unexpected() -> Error {
+    Error::from(ErrorKind::UnexpectedError("Raft node received an unexpected message"))
+}
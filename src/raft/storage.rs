@@ -0,0 +1,295 @@
+use crate::replication::Command;
+use crate::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Files in the Raft directory of a node.
+const STATE_FILE: &str = "state";
+const SNAPSHOT_FILE: &str = "snapshot";
+const LOG_FILE: &str = "log";
+
+/// An entry of the Raft log, numbered from 1.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// The position of the entry in the log.
+    pub index: u64,
+    /// The term of the leader which appended the entry.
+    pub term: u64,
+    /// What the entry does once committed.
+    pub payload: Payload,
+}
+
+/// What an entry of the Raft log does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Payload {
+    /// Appended by a new leader, so that the entries of previous terms get committed.
+    Noop,
+    /// Writes applied to the engine together, in order.
+    Write(Vec<Command>),
+    /// The addresses of the members of the cluster, which take effect as soon as the entry is
+    /// appended.
+    Members(Vec<String>),
+}
+
+/// The position covered by a snapshot of the engine, which replaces the entries up to it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct SnapshotMeta {
+    pub last_index: u64,
+    pub last_term: u64,
+    pub members: Vec<String>,
+}
+
+/// The term and vote of a node, which are saved before it answers anyone.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HardState {
+    term: u64,
+    voted_for: Option<String>,
+}
+
+/// The durable state of a Raft node: its term and vote, its snapshot position and the entries
+/// after it.
+///
+/// The snapshot itself is the engine, which applies entries idempotently, so that the entries
+/// after the snapshot position can be applied again after a restart.
+pub(crate) struct Storage {
+    dir: PathBuf,
+    state: HardState,
+    snapshot: SnapshotMeta,
+    entries: Vec<Entry>,
+    writer: BufWriter<File>,
+    // The latest members, and the index of the entry setting them.
+    members: (u64, Vec<String>),
+}
+
+impl Storage {
+    /// Open the storage in a given directory, which starts from given members if it is new.
+    pub fn open(dir: impl Into<PathBuf>, members: Vec<String>) -> Result<Storage> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let state = read_json(&dir.join(STATE_FILE))?.unwrap_or_default();
+        let snapshot = match read_json(&dir.join(SNAPSHOT_FILE))? {
+            Some(snapshot) => snapshot,
+            None => {
+                let snapshot = SnapshotMeta {
+                    members,
+                    ..SnapshotMeta::default()
+                };
+                write_json(&dir.join(SNAPSHOT_FILE), &snapshot)?;
+                snapshot
+            }
+        };
+
+        // Entries already in the snapshot are left when a compaction is interrupted, and the
+        // last line is torn when a write is interrupted.
+        let mut entries: Vec<Entry> = Vec::new();
+        if let Ok(file) = File::open(dir.join(LOG_FILE)) {
+            for line in BufReader::new(file).lines() {
+                let entry = match serde_json::from_str::<Entry>(&line?) {
+                    Ok(entry) => entry,
+                    Err(_) => break,
+                };
+                let next = snapshot.last_index + entries.len() as u64 + 1;
+                if entry.index == next {
+                    entries.push(entry);
+                } else if entry.index > next {
+                    break;
+                }
+            }
+        }
+
+        let writer = rewrite(&dir, &entries)?;
+        let mut storage = Storage {
+            dir,
+            state,
+            snapshot,
+            entries,
+            writer,
+            members: (0, Vec::new()),
+        };
+        storage.refresh_members();
+        Ok(storage)
+    }
+
+    pub fn term(&self) -> u64 {
+        self.state.term
+    }
+
+    pub fn voted_for(&self) -> Option<&str> {
+        self.state.voted_for.as_deref()
+    }
+
+    /// Save the term and vote, replacing the file atomically.
+    pub fn set_state(&mut self, term: u64, voted_for: Option<String>) -> Result<()> {
+        let state = HardState { term, voted_for };
+        write_json(&self.dir.join(STATE_FILE), &state)?;
+        self.state = state;
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> &SnapshotMeta {
+        &self.snapshot
+    }
+
+    pub fn members(&self) -> &[String] {
+        &self.members.1
+    }
+
+    /// Get the index of the entry which set the latest members.
+    pub fn members_index(&self) -> u64 {
+        self.members.0
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot.last_index + self.entries.len() as u64
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries.last().map_or(self.snapshot.last_term, |entry| entry.term)
+    }
+
+    /// Get the term of the entry at a given index, or None if it is not in the log,
+    /// where the snapshot position has the term of the snapshot.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot.last_index {
+            return Some(self.snapshot.last_term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    pub fn entry(&self, index: u64) -> Option<&Entry> {
+        let offset = index.checked_sub(self.snapshot.last_index + 1)?;
+        self.entries.get(offset as usize)
+    }
+
+    /// Get at most `max` entries from a given index to a given index, both included.
+    pub fn entries(&self, from: u64, to: u64, max: usize) -> Vec<Entry> {
+        let first = self.snapshot.last_index + 1;
+        if from < first || from > to {
+            return Vec::new();
+        }
+
+        let start = (from - first) as usize;
+        let end = ((to - first + 1) as usize).min(self.entries.len()).min(start + max);
+        self.entries.get(start..end).map_or_else(Vec::new, <[Entry]>::to_vec)
+    }
+
+    /// Append entries following the last one, which are durable once this returns.
+    pub fn append(&mut self, entries: Vec<Entry>) -> Result<()> {
+        for entry in &entries {
+            serde_json::to_writer(&mut self.writer, entry)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+
+        self.entries.extend(entries);
+        self.refresh_members();
+        Ok(())
+    }
+
+    /// Drop the entries from a given index, which conflict with the log of the leader.
+    pub fn truncate(&mut self, from: u64) -> Result<()> {
+        let keep = from.saturating_sub(self.snapshot.last_index + 1) as usize;
+        self.entries.truncate(keep);
+        self.writer = rewrite(&self.dir, &self.entries)?;
+        self.refresh_members();
+        Ok(())
+    }
+
+    /// Drop the entries up to a given index, which the engine has applied.
+    pub fn compact(&mut self, last_index: u64) -> Result<()> {
+        let last_term = match self.term_at(last_index) {
+            Some(term) if last_index > self.snapshot.last_index => term,
+            _ => return Ok(()),
+        };
+        let members = self
+            .entries
+            .iter()
+            .take_while(|entry| entry.index <= last_index)
+            .filter_map(|entry| match &entry.payload {
+                Payload::Members(members) => Some(members),
+                _ => None,
+            })
+            .last()
+            .unwrap_or(&self.snapshot.members)
+            .clone();
+
+        let snapshot = SnapshotMeta {
+            last_index,
+            last_term,
+            members,
+        };
+        write_json(&self.dir.join(SNAPSHOT_FILE), &snapshot)?;
+
+        self.entries.drain(..(last_index - self.snapshot.last_index) as usize);
+        self.snapshot = snapshot;
+        self.writer = rewrite(&self.dir, &self.entries)?;
+        Ok(())
+    }
+
+    /// Replace the log by a snapshot installed from the leader, keeping the entries after it
+    /// if the log has the last entry of the snapshot.
+    pub fn install(&mut self, snapshot: SnapshotMeta) -> Result<()> {
+        let entries = if self.term_at(snapshot.last_index) == Some(snapshot.last_term) {
+            self.entries
+                .split_off((snapshot.last_index.saturating_sub(self.snapshot.last_index)) as usize)
+        } else {
+            Vec::new()
+        };
+
+        write_json(&self.dir.join(SNAPSHOT_FILE), &snapshot)?;
+        self.snapshot = snapshot;
+        self.entries = entries;
+        self.writer = rewrite(&self.dir, &self.entries)?;
+        self.refresh_members();
+        Ok(())
+    }
+
+    fn refresh_members(&mut self) {
+        let latest = self.entries.iter().rev().find_map(|entry| match &entry.payload {
+            Payload::Members(members) => Some((entry.index, members.clone())),
+            _ => None,
+        });
+        self.members = latest.unwrap_or_else(|| (self.snapshot.last_index, self.snapshot.members.clone()));
+    }
+}
+
+/// Write the log file anew with given entries, replacing it atomically,
+/// and open it for appending.
+fn rewrite(dir: &Path, entries: &[Entry]) -> Result<BufWriter<File>> {
+    let path = dir.join(LOG_FILE);
+    let temp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    for entry in entries {
+        serde_json::to_writer(&mut writer, entry)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&temp, &path)?;
+
+    let file = OpenOptions::new().append(true).open(&path)?;
+    Ok(BufWriter::new(file))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Write a file atomically and durably, through a temporary file.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(&serde_json::to_vec(value)?)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
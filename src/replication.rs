@@ -0,0 +1,668 @@
+use crate::client::{KvsClient, Timeouts};
+use crate::engine::{EngineStats, KvsEngine};
+use crate::watch::ChangeFeed;
+use crate::error::{Error, ErrorKind};
+use crate::protocol::{ErrorCode, ProtocolError, ResponseFrame};
+use crate::{ClientTls, Credentials, Request, Response, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use slog::{info, warn, Logger};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A leader sends records at least this often, so that followers can tell a dead leader from an idle one.
+const HEARTBEAT: Duration = Duration::from_secs(1);
+// A follower reconnects when it hears nothing from its leader for this long.
+const LEADER_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// The backlog keeps the latest records, up to this many bytes of keys and values.
+const BACKLOG_BYTES: usize = 16 * 1024 * 1024;
+// Snapshots and records are sent in chunks of at most this many entries.
+const CHUNK_LEN: usize = 1024;
+// The file in the data directory of a follower which keeps its position.
+const POSITION_FILE: &str = "replication";
+
+/// A write replicated from a leader to its followers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+    /// Set the value of a key.
+    Set {
+        /// The key.
+        key: String,
+        /// The value.
+        value: String,
+    },
+    /// Remove a key.
+    Remove {
+        /// The key.
+        key: String,
+    },
+}
+
+impl Command {
+    /// Get the key written by the command.
+    pub fn key(&self) -> &str {
+        match self {
+            Command::Set { key, .. } | Command::Remove { key } => key,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Command::Set { key, value } => key.len() + value.len(),
+            Command::Remove { key } => key.len(),
+        }
+    }
+}
+
+/// A write numbered by its position in the replication log of a leader, starting from 1.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// The position of the write.
+    pub offset: u64,
+    /// The write.
+    pub command: Command,
+}
+
+/// The replies streamed by a leader to a follower which sent `Request::Sync`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SyncEvent {
+    /// The follower can not be resumed, so it loads a snapshot followed by the records after
+    /// `offset`, and drops every key which is not in the snapshot.
+    FullSync {
+        /// The id of the replication log of the leader.
+        replid: String,
+        /// The position of the leader when the snapshot started.
+        offset: u64,
+    },
+    /// A chunk of the snapshot.
+    Snapshot {
+        /// The keys and values of the chunk.
+        pairs: Vec<(String, String)>,
+        /// Whether it is the last chunk.
+        last: bool,
+    },
+    /// The follower is resumed from the position it sent.
+    Continue,
+    /// Records appended since the previous ones, which are sent without records as heartbeats.
+    Records {
+        /// The records, in order.
+        records: Vec<Record>,
+        /// The position of the leader.
+        leader_offset: u64,
+    },
+}
+
+/// The role of a server in replication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    /// A server taking writes, from which followers sync.
+    Leader,
+    /// A read-only server applying the writes of a leader.
+    Follower,
+}
+
+/// The replication state of a server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationInfo {
+    /// The role of the server.
+    pub role: Role,
+    /// The id of the replication log, which changes whenever a leader starts.
+    pub replid: Option<String>,
+    /// The position of the last write appended by a leader, or applied by a follower.
+    pub offset: u64,
+    /// The number of followers streaming from a leader.
+    pub followers: usize,
+    /// The address of the leader of a follower.
+    pub leader: Option<String>,
+    /// Whether a follower is streaming from its leader.
+    pub connected: bool,
+    /// How many writes a follower is behind its leader, as of the last message from it.
+    pub lag: Option<u64>,
+    /// Milliseconds since a follower last heard from its leader.
+    pub last_contact_ms: Option<u64>,
+}
+
+/// The replication state of a server, given to `KvsServer::replication` so that it can serve
+/// followers and report it.
+#[derive(Clone)]
+pub struct Replication(Node);
+
+#[derive(Clone)]
+enum Node {
+    Leader(Arc<ReplicationLog>),
+    Follower(Arc<FollowerState>),
+}
+
+impl Replication {
+    /// Get the replication state.
+    pub fn info(&self) -> ReplicationInfo {
+        match &self.0 {
+            Node::Leader(log) => ReplicationInfo {
+                role: Role::Leader,
+                replid: Some(log.replid.clone()),
+                offset: log.backlog.lock().unwrap().offset,
+                followers: log.followers.load(Ordering::SeqCst),
+                leader: None,
+                connected: false,
+                lag: None,
+                last_contact_ms: None,
+            },
+            Node::Follower(state) => {
+                let status = state.status.lock().unwrap();
+                let offset = status.position.offset;
+                ReplicationInfo {
+                    role: Role::Follower,
+                    replid: status.position.replid.clone(),
+                    offset,
+                    followers: 0,
+                    leader: Some(state.leader.clone()),
+                    connected: status.connected,
+                    lag: status.leader_offset.map(|leader_offset| leader_offset.saturating_sub(offset)),
+                    last_contact_ms: status.last_contact.map(|instant| instant.elapsed().as_millis() as u64),
+                }
+            }
+        }
+    }
+
+    /// Stream the writes of a leader to a follower which sent `Request::Sync` with a given id,
+    /// until the connection breaks.
+    pub(crate) fn serve_follower<E: KvsEngine>(
+        &self,
+        store: &E,
+        id: u64,
+        position: (Option<String>, u64),
+        writer: &mut impl Write,
+        logger: &Logger,
+    ) -> Result<()> {
+        let mut reply = |response: Response| -> Result<()> {
+            serde_json::to_writer(&mut *writer, &ResponseFrame { id, response })?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+            Ok(())
+        };
+
+        let log = match &self.0 {
+            Node::Leader(log) => log,
+            Node::Follower(_) => {
+                let message = "followers can not be synced from";
+                return reply(Response::Error(ProtocolError::new(ErrorCode::InvalidRequest, message)));
+            }
+        };
+        let mut send = |event: SyncEvent| reply(Response::Sync(event));
+
+        log.followers.fetch_add(1, Ordering::SeqCst);
+        let _follower = FollowerGuard(log);
+
+        let mut offset = match position {
+            (Some(replid), offset) if replid == log.replid && log.has_records_after(offset) => {
+                info!(logger, "follower resumed"; "offset" => offset);
+                send(SyncEvent::Continue)?;
+                offset
+            }
+            _ => {
+                let offset = log.backlog.lock().unwrap().offset;
+                info!(logger, "full sync of follower"; "offset" => offset);
+                send(SyncEvent::FullSync {
+                    replid: log.replid.clone(),
+                    offset,
+                })?;
+
+                // The snapshot is read while writes go on, which is fine since the records
+                // after `offset` are applied on it in order.
+                let keys = store.scan(String::new())?;
+                let mut chunks = keys.chunks(CHUNK_LEN).peekable();
+                loop {
+                    let chunk = chunks.next().unwrap_or(&[]);
+                    let values = store.get_many(chunk.to_vec())?;
+                    let pairs = chunk
+                        .iter()
+                        .cloned()
+                        .zip(values)
+                        .filter_map(|(key, value)| value.map(|value| (key, value)))
+                        .collect();
+
+                    let last = chunks.peek().is_none();
+                    send(SyncEvent::Snapshot { pairs, last })?;
+                    if last {
+                        break;
+                    }
+                }
+                offset
+            }
+        };
+
+        loop {
+            let (records, leader_offset) = log.wait_records_after(offset).ok_or_else(|| {
+                Error::from(ErrorKind::Busy("the follower fell behind the backlog".to_string()))
+            })?;
+            if let Some(record) = records.last() {
+                offset = record.offset;
+            }
+            send(SyncEvent::Records { records, leader_offset })?;
+        }
+    }
+}
+
+struct ReplicationLog {
+    replid: String,
+    backlog: Mutex<Backlog>,
+    appended: Condvar,
+    followers: AtomicUsize,
+}
+
+impl ReplicationLog {
+    fn has_records_after(&self, offset: u64) -> bool {
+        self.backlog.lock().unwrap().records_after(offset).is_some()
+    }
+
+    /// Wait until records are appended after a given position, or a heartbeat is due.
+    /// Return None if the records were dropped from the backlog.
+    fn wait_records_after(&self, offset: u64) -> Option<(Vec<Record>, u64)> {
+        let backlog = self.backlog.lock().unwrap();
+        let (backlog, _) = self
+            .appended
+            .wait_timeout_while(backlog, HEARTBEAT, |backlog| backlog.offset == offset)
+            .unwrap();
+        backlog.records_after(offset).map(|records| (records, backlog.offset))
+    }
+}
+
+struct FollowerGuard<'a>(&'a ReplicationLog);
+
+impl Drop for FollowerGuard<'_> {
+    fn drop(&mut self) {
+        self.0.followers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The latest records of a leader, kept so that followers can be resumed after reconnecting.
+struct Backlog {
+    records: VecDeque<Record>,
+    offset: u64,
+    bytes: usize,
+}
+
+impl Backlog {
+    fn append(&mut self, commands: Vec<Command>) {
+        for command in commands {
+            self.offset += 1;
+            self.bytes += command.len();
+            self.records.push_back(Record {
+                offset: self.offset,
+                command,
+            });
+        }
+
+        while self.bytes > BACKLOG_BYTES && self.records.len() > 1 {
+            if let Some(record) = self.records.pop_front() {
+                self.bytes -= record.command.len();
+            }
+        }
+    }
+
+    /// Get the first records after a given position,
+    /// or None if the position is not in the backlog.
+    fn records_after(&self, offset: u64) -> Option<Vec<Record>> {
+        let first = self.records.front().map_or(self.offset + 1, |record| record.offset);
+        if offset + 1 < first || offset > self.offset {
+            return None;
+        }
+
+        let skip = (offset + 1 - first) as usize;
+        Some(self.records.iter().skip(skip).take(CHUNK_LEN).cloned().collect())
+    }
+}
+
+/// An engine recording its writes in a replication log, from which followers sync.
+///
+/// Writes are serialized, so that followers apply them in the order of the engine.
+#[derive(Clone)]
+pub struct Leader<E: KvsEngine> {
+    engine: E,
+    log: Arc<ReplicationLog>,
+}
+
+impl<E: KvsEngine> Leader<E> {
+    /// Record the writes of an engine in a new replication log.
+    pub fn new(engine: E) -> Self {
+        let mut rng = rand::thread_rng();
+        let replid = (0..20).map(|_| format!("{:02x}", rng.gen::<u8>())).collect();
+
+        Leader {
+            engine,
+            log: Arc::new(ReplicationLog {
+                replid,
+                backlog: Mutex::new(Backlog {
+                    records: VecDeque::new(),
+                    offset: 0,
+                    bytes: 0,
+                }),
+                appended: Condvar::new(),
+                followers: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Get the replication state, to be given to `KvsServer::replication`.
+    pub fn replication(&self) -> Replication {
+        Replication(Node::Leader(Arc::clone(&self.log)))
+    }
+
+    /// Write to the engine, and append the commands of the write to the backlog.
+    fn write<T>(&self, write: impl FnOnce(&E) -> Result<(T, Vec<Command>)>) -> Result<T> {
+        let mut backlog = self.log.backlog.lock().unwrap();
+        let (result, commands) = write(&self.engine)?;
+        if !commands.is_empty() {
+            backlog.append(commands);
+            self.log.appended.notify_all();
+        }
+
+        Ok(result)
+    }
+}
+
+impl<E: KvsEngine> KvsEngine for Leader<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.write(|engine| {
+            engine.set(key.clone(), value.clone())?;
+            Ok(((), vec![Command::Set { key, value }]))
+        })
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.engine.get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.write(|engine| {
+            engine.remove(key.clone())?;
+            Ok(((), vec![Command::Remove { key }]))
+        })
+    }
+
+    fn scan(&self, prefix: String) -> Result<Vec<String>> {
+        self.engine.scan(prefix)
+    }
+
+    fn scan_after(&self, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        self.engine.scan_after(after, limit)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.engine.get_many(keys)
+    }
+
+    fn set_many(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.write(|engine| {
+            engine.set_many(pairs.clone())?;
+            let commands = pairs
+                .into_iter()
+                .map(|(key, value)| Command::Set { key, value })
+                .collect();
+            Ok(((), commands))
+        })
+    }
+
+    fn remove_many(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        self.write(|engine| {
+            let removed = engine.remove_many(keys.clone())?;
+            let commands = keys
+                .into_iter()
+                .zip(&removed)
+                .filter(|(_, removed)| **removed)
+                .map(|(key, _)| Command::Remove { key })
+                .collect();
+            Ok((removed, commands))
+        })
+    }
+
+    fn changes(&self) -> Option<ChangeFeed> {
+        self.engine.changes()
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.engine.compact()
+    }
+
+    fn stats(&self) -> Option<EngineStats> {
+        self.engine.stats()
+    }
+}
+
+/// The position of a follower in the replication log of its leader.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Position {
+    replid: Option<String>,
+    offset: u64,
+}
+
+struct FollowerState {
+    leader: String,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
+    path: PathBuf,
+    status: Mutex<FollowerStatus>,
+}
+
+#[derive(Default)]
+struct FollowerStatus {
+    position: Position,
+    connected: bool,
+    leader_offset: Option<u64>,
+    last_contact: Option<Instant>,
+}
+
+/// A read-only engine applying the writes of a leader.
+///
+/// The follower keeps its position in its data directory, so that it is resumed from there
+/// after a restart as long as the leader still has the following writes in its backlog.
+#[derive(Clone)]
+pub struct Follower<E: KvsEngine> {
+    engine: E,
+    state: Arc<FollowerState>,
+}
+
+impl<E: KvsEngine> Follower<E> {
+    /// Start following the leader at a given address in the background, authenticating with
+    /// given credentials and connecting with given TLS settings if the leader requires them,
+    /// where `path` is the data directory of the engine.
+    pub fn start(
+        engine: E,
+        leader: &str,
+        credentials: Option<Credentials>,
+        tls: Option<ClientTls>,
+        path: impl Into<PathBuf>,
+        logger: Logger,
+    ) -> Result<Follower<E>> {
+        let path = path.into().join(POSITION_FILE);
+        let position = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Position::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let follower = Follower {
+            engine,
+            state: Arc::new(FollowerState {
+                leader: leader.to_string(),
+                credentials,
+                tls,
+                path,
+                status: Mutex::new(FollowerStatus {
+                    position,
+                    ..FollowerStatus::default()
+                }),
+            }),
+        };
+
+        let replica = follower.clone();
+        thread::spawn(move || replica.replicate(logger));
+        Ok(follower)
+    }
+
+    /// Get the replication state, to be given to `KvsServer::replication`.
+    pub fn replication(&self) -> Replication {
+        Replication(Node::Follower(Arc::clone(&self.state)))
+    }
+
+    fn replicate(self, logger: Logger) {
+        loop {
+            if let Err(err) = self.sync(&logger) {
+                warn!(logger, "replication broken"; "error" => format!("{}", err));
+            }
+
+            self.state.status.lock().unwrap().connected = false;
+            thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    fn sync(&self, logger: &Logger) -> Result<()> {
+        let mut client = KvsClient::connect_as(
+            &self.state.leader,
+            Timeouts::all(LEADER_TIMEOUT),
+            self.state.credentials.as_ref(),
+            self.state.tls.as_ref(),
+        )?;
+        let Position { replid, offset } = self.state.status.lock().unwrap().position.clone();
+        let id = client.send(&Request::Sync { replid, offset })?;
+        client.flush()?;
+
+        // The position after the snapshot being loaded, and the keys which are not in it yet.
+        let mut full_sync: Option<(Position, HashSet<String>)> = None;
+        loop {
+            let frame = client.receive()?;
+            let event = match frame.response {
+                Response::Sync(event) if frame.id == id => event,
+                Response::Error(err) => return Err(err.into()),
+                _ => return Err(unexpected()),
+            };
+
+            match event {
+                SyncEvent::FullSync { replid, offset } => {
+                    info!(logger, "full sync from the leader"; "replid" => &replid, "offset" => offset);
+                    let stale = self.engine.scan(String::new())?.into_iter().collect();
+                    let position = Position {
+                        replid: Some(replid),
+                        offset,
+                    };
+                    full_sync = Some((position, stale));
+                }
+                SyncEvent::Snapshot { pairs, last } => {
+                    let (_, stale) = full_sync.as_mut().ok_or_else(unexpected)?;
+                    for (key, _) in &pairs {
+                        stale.remove(key);
+                    }
+                    self.engine.set_many(pairs)?;
+
+                    if last {
+                        let (position, stale) = full_sync.take().ok_or_else(unexpected)?;
+                        self.engine.remove_many(stale.into_iter().collect())?;
+                        info!(logger, "snapshot loaded"; "offset" => position.offset);
+                        self.save(position)?;
+                    }
+                }
+                SyncEvent::Continue => info!(logger, "resumed from the leader"; "offset" => offset),
+                SyncEvent::Records { records, leader_offset } => {
+                    if full_sync.is_some() {
+                        return Err(unexpected());
+                    }
+
+                    if let Some(last) = records.last().map(|record| record.offset) {
+                        for record in records {
+                            self.apply(record.command)?;
+                        }
+                        let replid = self.state.status.lock().unwrap().position.replid.clone();
+                        self.save(Position { replid, offset: last })?;
+                    }
+
+                    let mut status = self.state.status.lock().unwrap();
+                    status.connected = true;
+                    status.leader_offset = Some(leader_offset);
+                    status.last_contact = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    fn apply(&self, command: Command) -> Result<()> {
+        match command {
+            Command::Set { key, value } => self.engine.set(key, value),
+            // Records may be applied again after a restart, when the key is already removed.
+            Command::Remove { key } => self.engine.remove_many(vec![key]).map(|_| ()),
+        }
+    }
+
+    /// Save the position after the writes are applied, replacing the file atomically.
+    fn save(&self, position: Position) -> Result<()> {
+        let temp = self.state.path.with_extension("tmp");
+        fs::write(&temp, serde_json::to_vec(&position)?)?;
+        fs::rename(&temp, &self.state.path)?;
+
+        self.state.status.lock().unwrap().position = position;
+        Ok(())
+    }
+
+    fn read_only(&self) -> Error {
+        Error::from(ErrorKind::ReadOnly(format!(
+            "writes are served by the leader at {}",
+            self.state.leader
+        )))
+    }
+}
+
+impl<E: KvsEngine> KvsEngine for Follower<E> {
+    fn set(&self, _key: String, _value: String) -> Result<()> {
+        Err(self.read_only())
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.engine.get(key)
+    }
+
+    fn remove(&self, _key: String) -> Result<()> {
+        Err(self.read_only())
+    }
+
+    fn scan(&self, prefix: String) -> Result<Vec<String>> {
+        self.engine.scan(prefix)
+    }
+
+    fn scan_after(&self, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        self.engine.scan_after(after, limit)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.engine.get_many(keys)
+    }
+
+    fn set_many(&self, _pairs: Vec<(String, String)>) -> Result<()> {
+        Err(self.read_only())
+    }
+
+    fn remove_many(&self, _keys: Vec<String>) -> Result<Vec<bool>> {
+        Err(self.read_only())
+    }
+
+    fn changes(&self) -> Option<ChangeFeed> {
+        self.engine.changes()
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.engine.compact()
+    }
+
+    fn stats(&self) -> Option<EngineStats> {
+        self.engine.stats()
+    }
+}
+
+fn unexpected() -> Error {
+    Error::from(ErrorKind::UnexpectedError("Follower received an unexpected response"))
+}
@@ -0,0 +1,549 @@
+use crate::engine::{key_count, KvsEngine};
+use crate::error::{Error, ErrorKind, Result};
+use crate::metrics::Metrics;
+use crate::protocol::ErrorCode;
+use crate::{Auth, Credentials, Permission};
+use slog::{error, info, Logger};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use crate::stream::Stream;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Limits refusing absurd commands from a broken peer, as commands are read before the client
+// authenticates. Lines are as long as the inline commands of redis, and bulk strings as long as
+// the bodies of the HTTP gateway.
+const MAX_LINE_LEN: usize = 64 * 1024;
+const MAX_BULK_LEN: usize = 64 * 1024 * 1024;
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+const DEFAULT_SCAN_COUNT: usize = 10;
+// At most this many cursors of `SCAN` are kept, the oldest being forgotten.
+const MAX_SCAN_CURSORS: usize = 1024;
+
+/// A value of the redis serialization protocol (RESP2).
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Value>>),
+}
+
+impl Value {
+    fn ok() -> Self {
+        Value::Simple("OK".to_string())
+    }
+
+    fn bulk(content: impl Into<Vec<u8>>) -> Self {
+        Value::Bulk(Some(content.into()))
+    }
+
+    fn nil() -> Self {
+        Value::Bulk(None)
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Value::Error(message.into())
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        match self {
+            Value::Simple(content) => write!(writer, "+{}\r\n", content)?,
+            Value::Error(message) => write!(writer, "-{}\r\n", message)?,
+            Value::Integer(number) => write!(writer, ":{}\r\n", number)?,
+            Value::Bulk(None) => writer.write_all(b"$-1\r\n")?,
+            Value::Bulk(Some(content)) => {
+                write!(writer, "${}\r\n", content.len())?;
+                writer.write_all(content)?;
+                writer.write_all(b"\r\n")?;
+            }
+            Value::Array(None) => writer.write_all(b"*-1\r\n")?,
+            Value::Array(Some(values)) => {
+                write!(writer, "*{}\r\n", values.len())?;
+                for value in values {
+                    value.write_to(writer)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The cursors of `SCAN` given to the clients of a server, each standing for the last key of a
+/// page, as clients such as redis-cli and redis-rs parse cursors as unsigned 64-bit integers.
+///
+/// Cursors are shared by every connection, as clients with a pool of connections may send each
+/// page over another one. A scan left unfinished while many others run has to start over.
+#[derive(Clone, Default)]
+pub(crate) struct ScanCursors(Arc<Mutex<CursorTable>>);
+
+#[derive(Default)]
+struct CursorTable {
+    last_id: u64,
+    keys: BTreeMap<u64, String>,
+}
+
+impl ScanCursors {
+    /// Give a cursor to the page following a key, which is never 0.
+    fn issue(&self, key: String) -> u64 {
+        let mut table = self.0.lock().unwrap();
+        table.last_id += 1;
+        let id = table.last_id;
+        table.keys.insert(id, key);
+        if table.keys.len() > MAX_SCAN_CURSORS {
+            table.keys.pop_first();
+        }
+        id
+    }
+
+    /// Get the key the page of a cursor starts after, None for the first page.
+    /// Return None if the cursor was not given, or was forgotten.
+    fn resolve(&self, cursor: &str) -> Option<Option<String>> {
+        match cursor.parse::<u64>().ok()? {
+            0 => Some(None),
+            id => self.0.lock().unwrap().keys.get(&id).cloned().map(Some),
+        }
+    }
+}
+
+/// Read a single line terminated by CRLF, without the terminator.
+/// Return None if the peer closed the connection.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    if reader.take(MAX_LINE_LEN as u64 + 2).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    if !line.ends_with(b"\n") && line.len() > MAX_LINE_LEN {
+        return Err(ErrorKind::UnexpectedError("Protocol error: too big line").into());
+    }
+    if !line.ends_with(b"\r\n") {
+        return Err(ErrorKind::UnexpectedError("Protocol error: line is not terminated by CRLF").into());
+    }
+
+    line.truncate(line.len() - 2);
+    Ok(Some(line))
+}
+
+fn parse_len(line: &[u8]) -> Result<i64> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse::<i64>().ok())
+        .ok_or_else(|| ErrorKind::UnexpectedError("Protocol error: invalid length").into())
+}
+
+/// Read a command sent by a client.
+///
+/// Clients send commands as arrays of bulk strings, while humans using telnet send inline
+/// commands separated by spaces. Both are accepted.
+fn read_command(reader: &mut impl BufRead) -> Result<Option<Vec<Vec<u8>>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    if !line.starts_with(b"*") {
+        let args = line
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        return Ok(Some(args));
+    }
+
+    let len = parse_len(&line[1..])?;
+    if len < 0 || len as usize > MAX_ARRAY_LEN {
+        return Err(ErrorKind::UnexpectedError("Protocol error: invalid multibulk length").into());
+    }
+
+    // The arguments take room as they arrive rather than as many as announced.
+    let mut args = Vec::with_capacity((len as usize).min(16));
+    for _ in 0..len {
+        let line = read_line(reader)?
+            .ok_or(ErrorKind::UnexpectedError("Protocol error: unexpected end of stream"))?;
+        if !line.starts_with(b"$") {
+            return Err(ErrorKind::UnexpectedError("Protocol error: expected a bulk string").into());
+        }
+
+        let len = parse_len(&line[1..])?;
+        if len < 0 || len as usize > MAX_BULK_LEN {
+            return Err(ErrorKind::UnexpectedError("Protocol error: invalid bulk length").into());
+        }
+
+        let mut arg = Vec::new();
+        if reader.take(len as u64 + 2).read_to_end(&mut arg)? < len as usize + 2 {
+            return Err(ErrorKind::UnexpectedError("Protocol error: unexpected end of stream").into());
+        }
+        if !arg.ends_with(b"\r\n") {
+            return Err(ErrorKind::UnexpectedError("Protocol error: bulk string is not terminated by CRLF").into());
+        }
+
+        arg.truncate(len as usize);
+        args.push(arg);
+    }
+
+    Ok(Some(args))
+}
+
+/// Serve a client speaking RESP2, mapping redis commands onto the engine.
+/// With authentication, only `AUTH` and `QUIT` are served until `AUTH` succeeds.
+pub(crate) fn serve_resp<E: KvsEngine>(
+    store: E,
+    stream: Stream,
+    auth: Option<&Auth>,
+    metrics: &Metrics,
+    cursors: &ScanCursors,
+    logger: &Logger,
+) -> Result<()> {
+    let mut writer = BufWriter::new(&stream);
+    let mut reader = BufReader::new(&stream);
+    let peer = stream.peer_ip()?;
+    let mut user = None;
+
+    loop {
+        // Flush only before waiting for more commands, so that pipelined commands are served
+        // without waiting on writes.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+
+        let args = match read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            Err(err) => {
+                error!(logger, "can not parse the request"; "error" => format!("{}", err));
+                Value::error(format!("ERR {}", err)).write_to(&mut writer)?;
+                writer.flush()?;
+                break;
+            }
+        };
+
+        if args.is_empty() {
+            continue;
+        }
+
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        info!(logger, "request came"; "command" => &name, "args" => args.len() - 1);
+
+        let started = Instant::now();
+        let (reply, error) = match (strings(&args[1..]), name.as_str()) {
+            (Err(reply), _) => (reply, Some(ErrorCode::InvalidRequest)),
+            (Ok(args), "AUTH") => coded(authenticate(auth, peer, args, &mut user), ErrorCode::Unauthorized),
+            (Ok(_), name) if auth.is_some() && user.is_none() && name != "QUIT" => {
+                (Value::error("NOAUTH Authentication required."), Some(ErrorCode::Unauthorized))
+            }
+            (Ok(args), name) => {
+                match authorize(auth, user.as_deref(), name, &args).and_then(|()| execute(&store, cursors, name, args)) {
+                    Ok(reply) => coded(reply, ErrorCode::InvalidRequest),
+                    Err(err) => {
+                        let code = ErrorCode::from(err.kind());
+                        (engine_error(err), Some(code))
+                    }
+                }
+            }
+        };
+        metrics.observe(request_name(&name), started.elapsed(), error);
+
+        reply.write_to(&mut writer)?;
+
+        if name == "QUIT" {
+            break;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Get the type of request a command is counted as in the metrics, as the commands of the
+/// protocol of kvs doing the same are.
+fn request_name(command: &str) -> Option<&'static str> {
+    Some(match command {
+        "AUTH" => "Auth",
+        "PING" => "Ping",
+        "GET" => "Get",
+        "SET" => "Set",
+        "DEL" => "RemoveMany",
+        "EXISTS" | "MGET" => "GetMany",
+        "MSET" => "SetMany",
+        "SCAN" => "Scan",
+        "INFO" => "Info",
+        _ => return None,
+    })
+}
+
+/// Pair a reply with a code of error if it is an error, where errors of the engine are coded
+/// by their kind instead.
+fn coded(reply: Value, code: ErrorCode) -> (Value, Option<ErrorCode>) {
+    let error = matches!(reply, Value::Error(_)).then_some(code);
+    (reply, error)
+}
+
+fn strings(args: &[Vec<u8>]) -> std::result::Result<Vec<String>, Value> {
+    args.iter()
+        .map(|arg| {
+            String::from_utf8(arg.clone())
+                .map_err(|_| Value::error("ERR only UTF-8 arguments are supported"))
+        })
+        .collect()
+}
+
+fn wrong_arity(name: &str) -> Value {
+    Value::error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        name.to_ascii_lowercase()
+    ))
+}
+
+/// Authenticate with `AUTH password`, or with `AUTH user token` for the token of a user.
+fn authenticate(auth: Option<&Auth>, peer: IpAddr, mut args: Vec<String>, user: &mut Option<String>) -> Value {
+    let auth = match auth {
+        Some(auth) => auth,
+        None => return Value::error("ERR AUTH called without any password configured"),
+    };
+    let (expected, credentials) = match args.len() {
+        1 => (None, Credentials::Password(args.remove(0))),
+        2 => (Some(args.remove(0)), Credentials::Token(args.remove(0))),
+        _ => return wrong_arity("AUTH"),
+    };
+
+    match auth.authenticate(peer, &credentials) {
+        Ok(name) if expected.as_ref().is_none_or(|expected| *expected == name) => {
+            *user = Some(name);
+            Value::ok()
+        }
+        Ok(_) => Value::error("WRONGPASS invalid username-password pair"),
+        Err(err) => match err.kind() {
+            ErrorKind::Unauthorized(message) => Value::error(format!("WRONGPASS {}", message)),
+            _ => engine_error(err),
+        },
+    }
+}
+
+/// Check that the user a connection authenticated as may run a command, when clients have to
+/// authenticate. Commands which see every key, such as `SCAN` and `INFO`, take a permission on
+/// every key.
+fn authorize(auth: Option<&Auth>, user: Option<&str>, name: &str, args: &[String]) -> Result<()> {
+    let (auth, user) = match (auth, user) {
+        (Some(auth), Some(user)) => (auth, user),
+        _ => return Ok(()),
+    };
+
+    let (permission, keys) = match name {
+        "PING" | "QUIT" => return Ok(()),
+        "GET" | "EXISTS" | "MGET" => (Permission::Read, args.iter().collect::<Vec<_>>()),
+        "SET" => (Permission::Write, args.iter().take(1).collect()),
+        "DEL" => (Permission::Write, args.iter().collect()),
+        "MSET" => (Permission::Write, args.iter().step_by(2).collect()),
+        _ => return auth.authorize_key(user, Permission::Read, ""),
+    };
+    keys.into_iter()
+        .try_for_each(|key| auth.authorize_key(user, permission, key))
+}
+
+fn engine_error(err: Error) -> Value {
+    match err.kind() {
+        ErrorKind::Unauthorized(message) => Value::error(format!("NOPERM {}", message)),
+        ErrorKind::ReadOnly(message) => Value::error(format!("READONLY {}", message)),
+        ErrorKind::NotLeader { message, .. } => Value::error(format!("NOTLEADER {}", message)),
+        ErrorKind::Moved { message, .. } => Value::error(format!("MOVED {}", message)),
+        _ => Value::error(format!("ERR {}", err)),
+    }
+}
+
+fn execute<E: KvsEngine>(store: &E, cursors: &ScanCursors, name: &str, mut args: Vec<String>) -> Result<Value> {
+    Ok(match name {
+        "PING" => match args.len() {
+            0 => Value::Simple("PONG".to_string()),
+            1 => Value::bulk(args.remove(0)),
+            _ => return Ok(wrong_arity(name)),
+        },
+        "QUIT" => Value::ok(),
+        "GET" => {
+            if args.len() != 1 {
+                return Ok(wrong_arity(name));
+            }
+
+            match store.get(args.remove(0))? {
+                Some(value) => Value::bulk(value),
+                None => Value::nil(),
+            }
+        }
+        "SET" => {
+            if args.len() != 2 {
+                return Ok(wrong_arity(name));
+            }
+
+            let value = args.pop().unwrap();
+            let key = args.pop().unwrap();
+            store.set(key, value)?;
+            Value::ok()
+        }
+        "DEL" => {
+            if args.is_empty() {
+                return Ok(wrong_arity(name));
+            }
+
+            let removed = store.remove_many(args)?;
+            Value::Integer(removed.into_iter().filter(|removed| *removed).count() as i64)
+        }
+        "EXISTS" => {
+            if args.is_empty() {
+                return Ok(wrong_arity(name));
+            }
+
+            let values = store.get_many(args)?;
+            Value::Integer(values.into_iter().flatten().count() as i64)
+        }
+        "MGET" => {
+            if args.is_empty() {
+                return Ok(wrong_arity(name));
+            }
+
+            let values = store.get_many(args)?;
+            Value::Array(Some(
+                values
+                    .into_iter()
+                    .map(|value| value.map_or_else(Value::nil, Value::bulk))
+                    .collect(),
+            ))
+        }
+        "MSET" => {
+            if args.is_empty() || args.len() % 2 == 1 {
+                return Ok(wrong_arity(name));
+            }
+
+            let mut args = args.into_iter();
+            let mut pairs = Vec::with_capacity(args.len() / 2);
+            while let (Some(key), Some(value)) = (args.next(), args.next()) {
+                pairs.push((key, value));
+            }
+
+            store.set_many(pairs)?;
+            Value::ok()
+        }
+        "SCAN" => scan(store, cursors, args)?,
+        "INFO" => {
+            if args.len() > 1 {
+                return Ok(wrong_arity(name));
+            }
+
+            Value::bulk(format!(
+                "# Server\r\nkvs_version:{}\r\n\r\n# Keyspace\r\ndb0:keys={}\r\n",
+                env!("CARGO_PKG_VERSION"),
+                key_count(store)?
+            ))
+        }
+        _ => Value::error(format!("ERR unknown command '{}'", name.to_ascii_lowercase())),
+    })
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count]`
+///
+/// The cursor stands for the last key returned, and the next page starts strictly after it, so a
+/// full iteration returns every key which exists from its start to its end even if keys are
+/// removed or added meanwhile, as redis guarantees.
+fn scan<E: KvsEngine>(store: &E, cursors: &ScanCursors, args: Vec<String>) -> Result<Value> {
+    let mut args = args.into_iter();
+    let after = match args.next().map(|cursor| cursors.resolve(&cursor)) {
+        Some(Some(after)) => after,
+        Some(None) => return Ok(Value::error("ERR invalid cursor")),
+        None => return Ok(wrong_arity("SCAN")),
+    };
+
+    let (mut pattern, mut count) = (None, DEFAULT_SCAN_COUNT);
+    while let Some(option) = args.next() {
+        match (option.to_ascii_uppercase().as_str(), args.next()) {
+            ("MATCH", Some(value)) => pattern = Some(value),
+            ("COUNT", Some(value)) => match value.parse::<usize>() {
+                Ok(value) if value > 0 => count = value,
+                _ => return Ok(Value::error("ERR value is not an integer or out of range")),
+            },
+            _ => return Ok(Value::error("ERR syntax error")),
+        }
+    }
+
+    // One more key is read to tell whether the page is the last one.
+    let mut keys = store.scan_after(after, count.saturating_add(1))?;
+    let next = if keys.len() > count {
+        keys.truncate(count);
+        keys.last().map_or(0, |key| cursors.issue(key.clone())).to_string()
+    } else {
+        "0".to_string()
+    };
+    let found = keys
+        .iter()
+        .filter(|key| match &pattern {
+            Some(pattern) => glob_match(pattern.as_bytes(), key.as_bytes()),
+            None => true,
+        })
+        .map(|key| Value::bulk(key.as_str()))
+        .collect();
+
+    Ok(Value::Array(Some(vec![
+        Value::bulk(next),
+        Value::Array(Some(found)),
+    ])))
+}
+
+/// Match a string against a redis glob-style pattern, supporting `*`, `?`, `[...]` and `\`.
+///
+/// A mismatch only backtracks to the last `*`, trying it on one more byte, so that matching takes
+/// at most the product of the lengths of the pattern and the string.
+pub(crate) fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // The position in the pattern after the last `*`, and the position in the string it matches up to.
+    let mut star = None;
+
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, s));
+        } else if let Some(len) = match_byte(&pattern[p..], string[s]) {
+            p += len;
+            s += 1;
+        } else if let Some((after_star, matched)) = star {
+            p = after_star;
+            s = matched + 1;
+            star = Some((after_star, s));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+/// Match a byte against the token a pattern starts with, which is not `*`, returning the length
+/// of the token if it matches.
+fn match_byte(pattern: &[u8], byte: u8) -> Option<usize> {
+    match pattern.split_first()? {
+        (b'?', _) => Some(1),
+        (b'[', rest) => {
+            let end = rest.iter().position(|byte| *byte == b']')?;
+            let (class, negated) = match rest[..end].split_first() {
+                Some((b'^', class)) => (class, true),
+                _ => (&rest[..end], false),
+            };
+
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= class[i] <= byte && byte <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == byte;
+                    i += 1;
+                }
+            }
+
+            (matched != negated).then_some(end + 2)
+        }
+        (b'\\', rest) if !rest.is_empty() => (rest[0] == byte).then_some(2),
+        (expected, _) => (*expected == byte).then_some(1),
+    }
+}
@@ -1,76 +1,634 @@
-use crate::engine::KvsEngine;
+use crate::engine::{EngineStats, KvsEngine};
+use crate::http::{serve_http, serve_metrics};
+use crate::logging::{self, RequestLog};
+use crate::resp::{serve_resp, ScanCursors};
+use crate::error::ErrorKind;
 use crate::Result;
-use crate::{Request, Response};
-use slog::{info, error, o, Logger};
-use std::io::{BufReader, BufWriter, Write};
-use std::net::{TcpListener, TcpStream};
+use crate::protocol::{ErrorCode, ProtocolError, RequestFrame, ResponseFrame};
+use crate::pubsub::PubSub;
+use crate::slowlog::{self, SlowLog};
+use crate::stream::Stream;
+use crate::{Auth, Metrics, Raft, Replication, Request, Response, ServerTls, Sharding};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use slog::{debug, info, error, o, trace, FnValue, Logger, Record};
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
 use crate::thread_pool::{ThreadPool};
+use std::mem;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// The default threshold and length of the slow log.
+const DEFAULT_SLOWLOG_THRESHOLD: Duration = Duration::from_millis(10);
+const DEFAULT_SLOWLOG_LEN: usize = 128;
 
 /// The server of key-value store.
 pub struct KvsServer<E: KvsEngine, T: ThreadPool + Send> {
     engine: E,
     thread_pool: T,
+    idle_timeout: Option<Duration>,
+    replication: Option<Replication>,
+    raft: Option<Raft>,
+    sharding: Option<Sharding>,
+    auth: Option<Auth>,
+    tls: Option<ServerTls>,
+    unix_mode: Option<u32>,
+    metrics: Metrics,
+    started: Instant,
+    slowlog: SlowLog,
+    request_log: RequestLog,
+    log_values: bool,
+    pubsub: PubSub,
 }
 
 impl<E: KvsEngine, T: ThreadPool + Send> KvsServer<E, T> {
     /// Create a new key-value store server.
     #[inline]
     pub fn new(engine: E, thread_pool: T) -> Self {
-        KvsServer { engine, thread_pool }
+        KvsServer {
+            engine,
+            thread_pool,
+            idle_timeout: None,
+            replication: None,
+            raft: None,
+            sharding: None,
+            auth: None,
+            tls: None,
+            unix_mode: None,
+            metrics: Metrics::new(),
+            started: Instant::now(),
+            slowlog: SlowLog::new(DEFAULT_SLOWLOG_THRESHOLD, DEFAULT_SLOWLOG_LEN),
+            request_log: RequestLog::default(),
+            log_values: false,
+            pubsub: PubSub::default(),
+        }
+    }
+
+    /// Close connections of clients which send nothing, or read nothing, for a given duration.
+    /// Connections are never closed by the server if no idle timeout is set, or if it is zero.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout).filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Serve followers and report the replication state, which is taken from the `Leader` or the
+    /// `Follower` wrapping the engine.
+    pub fn replication(mut self, replication: Replication) -> Self {
+        self.replication = Some(replication);
+        self
+    }
+
+    /// Serve the messages of the other nodes of a Raft cluster, report the state and change the
+    /// members, which is taken from the `RaftNode` wrapping the engine.
+    pub fn raft(mut self, raft: Raft) -> Self {
+        self.raft = Some(raft);
+        self
+    }
+
+    /// Report and replace the shard map, and move shards to other nodes, which is taken from the
+    /// `ShardNode` wrapping the engine.
+    pub fn sharding(mut self, sharding: Sharding) -> Self {
+        self.sharding = Some(sharding);
+        self
+    }
+
+    /// Require every connection to authenticate before serving any other request.
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Accept only clients connecting with TLS, which are asked for a certificate if the settings
+    /// have certificate authorities of clients.
+    pub fn tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Set the permissions of the socket file of `run_unix`, such as `0o660` to only let the
+    /// owner and the group connect. The socket file is created with the umask of the process
+    /// otherwise.
+    pub fn unix_mode(mut self, mode: u32) -> Self {
+        self.unix_mode = Some(mode);
+        self
+    }
+
+    /// Record the requests and the connections of the server into given metrics, which may be
+    /// shared with the other listeners of the store. A server records into its own otherwise.
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Keep the latest requests which took at least a given duration in the slow log, up to a
+    /// given number of them, where zero keeps none. Requests taking 10ms are kept by default, up
+    /// to 128 of them.
+    pub fn slowlog(mut self, threshold: Duration, len: usize) -> Self {
+        self.slowlog = SlowLog::new(threshold, len);
+        self
+    }
+
+    /// Log the requests and responses of every connection at the info level from the start, which
+    /// `Request::RequestLog` switches while the server runs. They are logged at the debug level
+    /// otherwise.
+    pub fn request_log(self, enabled: bool) -> Self {
+        self.request_log.switch(enabled, None);
+        self
+    }
+
+    /// Log the values carried by requests and responses, which are left out of the log by default.
+    pub fn log_values(mut self, enabled: bool) -> Self {
+        self.log_values = enabled;
+        self
     }
 
     /// Run the server listening on a given ip address working with a slog logger.
     pub fn run(&mut self, addr: &str, logger: Logger) -> Result<()> {
+        let serve = self.service();
+        self.listen(Listener::Tcp(TcpListener::bind(addr)?), logger, serve)
+    }
+
+    /// Run the server listening on a Unix socket of a given path, for clients on the same host.
+    /// Who can connect is decided by the permissions of the socket file, and connections are
+    /// never encrypted. A socket file left by a server which is gone is replaced.
+    pub fn run_unix(&mut self, path: impl AsRef<Path>, logger: Logger) -> Result<()> {
+        let path = path.as_ref();
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another server listens on {}", path.display()),
+                )
+                .into());
+            }
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = self.unix_mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        let serve = self.service();
+        self.listen(Listener::Unix(listener), logger, serve)
+    }
+
+    /// Get the service of the key-value store protocol.
+    fn service(&self) -> impl Fn(E, Stream, &Logger) -> Result<()> + Send + Sync + 'static {
+        let replication = self.replication.clone();
+        let raft = self.raft.clone();
+        let sharding = self.sharding.clone();
+        let auth = self.auth.clone();
+        let pubsub = self.pubsub.clone();
+        let slowlog = self.slowlog.clone();
+        let request_log = self.request_log.clone();
+        let log_values = self.log_values;
+        let metrics = self.metrics.clone();
+        let started = self.started;
+        move |store, stream, logger| {
+            let services = Services {
+                metrics: &metrics,
+                started,
+                replication: replication.as_ref(),
+                raft: raft.as_ref(),
+                sharding: sharding.as_ref(),
+                auth: auth.as_ref(),
+                pubsub: &pubsub,
+                slowlog: &slowlog,
+                request_log: &request_log,
+                log_values,
+            };
+            serve(store, stream, services, logger)
+        }
+    }
+
+    /// Run the server speaking the redis protocol (RESP2) on a given ip address,
+    /// so that redis clients can work with the key-value store.
+    pub fn run_resp(&mut self, addr: &str, logger: Logger) -> Result<()> {
+        let auth = self.auth.clone();
+        let metrics = self.metrics.clone();
+        let cursors = ScanCursors::default();
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        self.listen(listener, logger, move |store, stream, logger| {
+            serve_resp(store, stream, auth.as_ref(), &metrics, &cursors, logger)
+        })
+    }
+
+    /// Run the HTTP/JSON gateway on a given ip address, for clients which can only speak HTTP.
+    pub fn run_http(&mut self, addr: &str, logger: Logger) -> Result<()> {
+        let auth = self.auth.clone();
+        let metrics = self.metrics.clone();
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        self.listen(listener, logger, move |store, stream, logger| {
+            serve_http(store, stream, auth.as_ref(), &metrics, logger)
+        })
+    }
+
+    /// Serve the metrics of the server in the Prometheus text format on `GET /metrics` of a given
+    /// ip address, along with the statistics of the engine if it keeps any.
+    pub fn run_metrics(&mut self, addr: &str, logger: Logger) -> Result<()> {
+        // Scrapes are not counted as connections of clients.
+        let metrics = mem::replace(&mut self.metrics, Metrics::new());
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        self.listen(listener, logger, move |store, stream, logger| serve_metrics(store, stream, &metrics, logger))
+    }
+
+    fn listen<S>(&mut self, listener: Listener, logger: Logger, serve: S) -> Result<()>
+    where
+        S: Fn(E, Stream, &Logger) -> Result<()> + Send + Sync + 'static,
+    {
         let logger = Arc::new(logger);
-        let listener = TcpListener::bind(addr)?;
+        let serve = Arc::new(serve);
 
-        for stream in listener.incoming() {
+        loop {
+            let stream = listener.accept();
             if let Ok(stream) = stream {
-                let peer_addr = stream.peer_addr()?;
+                let peer_addr = stream.peer_name();
                 let logger = Arc::clone(&logger);
+                // A connection which can not be set up is dropped, while the others are served.
+                if let Err(err) = stream.set_timeouts(self.idle_timeout, self.idle_timeout) {
+                    error!(logger, "Connection failed"; "address" => peer_addr, "error" => format!("{}", err));
+                    continue;
+                }
 
                 let store = self.engine.clone();
+                let serve = Arc::clone(&serve);
+                let tls = self.tls.clone();
+                let metrics = self.metrics.clone();
+                let queued = metrics.queued();
                 self.thread_pool.spawn(move || {
+                    drop(queued);
+                    let _connection = metrics.connection();
                     let client = logger.new(o!("address" => peer_addr));
                     info!(client, "incoming client");
 
-                    if let Err(err) = serve(store, stream, &client) {
-                        error!(client, "Error on serving client"; "error" => format!("{}", err));
+                    let stream = match (tls, stream) {
+                        (Some(tls), Stream::Tcp(stream)) => tls.accept(stream),
+                        (_, stream) => Ok(stream),
+                    };
+                    match stream.and_then(|stream| serve(store, stream, &client)) {
+                        Err(ref err) if matches!(err.kind(), ErrorKind::Timeout(_)) => {
+                            info!(client, "closing idle client");
+                        }
+                        Err(err) => {
+                            error!(client, "Error on serving client"; "error" => format!("{}", err));
+                        }
+                        Ok(()) => (),
                     }
                 });
             } else if let Err(err) = stream {
                 error!(logger, "Connection failed"; "error" => format!("{}", err));
             }
         }
+    }
+}
 
-        Ok(())
+/// A listener of the connections of clients.
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+/// The services of a server besides the engine, where replication, Raft, sharding and
+/// authentication are enabled by builder methods.
+#[derive(Clone, Copy)]
+struct Services<'a> {
+    replication: Option<&'a Replication>,
+    raft: Option<&'a Raft>,
+    sharding: Option<&'a Sharding>,
+    auth: Option<&'a Auth>,
+    pubsub: &'a PubSub,
+    slowlog: &'a SlowLog,
+    request_log: &'a RequestLog,
+    log_values: bool,
+    metrics: &'a Metrics,
+    started: Instant,
+}
+
+/// The state of a server, as replied to `Request::Info`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// The name of the engine, or `unknown` if it keeps no statistics.
+    pub engine: String,
+    /// The version of the server.
+    pub version: String,
+    /// Seconds since the server started.
+    pub uptime_secs: u64,
+    /// The number of keys stored.
+    pub keys: u64,
+    /// The bytes the engine takes on disk.
+    pub disk_bytes: u64,
+    /// The bytes of the log which compaction would reclaim, zero for engines without a log.
+    pub uncompacted_bytes: u64,
+    /// The generation of the log written to, zero for engines without a log.
+    pub generation: u64,
+    /// The number of connections of clients open.
+    pub connected_clients: u64,
+    /// The number of requests replied since the server started, by type.
+    pub commands: BTreeMap<String, u64>,
+}
+
+/// A compaction of the storage of the engine, as replied to `Request::Compact`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Compaction {
+    /// The bytes the engine takes on disk less than before, zero if it takes more.
+    pub reclaimed_bytes: u64,
+    /// Milliseconds the compaction took.
+    pub duration_ms: u64,
+}
+
+fn compact<E: KvsEngine>(store: &E) -> Result<Compaction> {
+    let disk_bytes = || store.stats().map_or(0, |stats| stats.disk_bytes);
+    let before = disk_bytes();
+    let start = Instant::now();
+    store.compact()?;
+    Ok(Compaction {
+        duration_ms: start.elapsed().as_millis() as u64,
+        reclaimed_bytes: before.saturating_sub(disk_bytes()),
+    })
+}
+
+fn info<E: KvsEngine>(store: &E, services: Services<'_>) -> ServerInfo {
+    let stats = store.stats().unwrap_or(EngineStats {
+        engine: "unknown",
+        ..EngineStats::default()
+    });
+    ServerInfo {
+        engine: stats.engine.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: services.started.elapsed().as_secs(),
+        keys: stats.keys,
+        disk_bytes: stats.disk_bytes,
+        uncompacted_bytes: stats.uncompacted_bytes,
+        generation: stats.generation,
+        connected_clients: services.metrics.connections(),
+        commands: services.metrics.requests(),
     }
 }
 
-fn serve<E: KvsEngine>(store: E, stream: TcpStream, logger: &Logger) -> Result<()> {
+fn serve<E: KvsEngine>(store: E, stream: Stream, services: Services<'_>, logger: &Logger) -> Result<()> {
     let mut writer = BufWriter::new(&stream);
     let mut reader = BufReader::new(&stream);
-    let reader = serde_json::de::Deserializer::from_reader(&mut reader).into_iter::<Request>();
+    let mut line = String::new();
+    let peer = stream.peer_ip()?;
+    let client = stream.peer_name();
+    // The user the connection is authenticated as.
+    let mut user = None;
 
-    for request in reader {
-        if let Ok(request) = request {
-            info!(logger, "request came"; "request" => format!("{:?}", request));
+    loop {
+        // Flush only before waiting for more requests, so that pipelined requests are served
+        // without waiting on writes.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
 
-            let response = match request {
-                Request::Set { key, value } => Response::set(store.set(key, value)),
-                Request::Get { key } => Response::get(store.get(key)),
-                Request::Remove { key } => Response::remove(store.remove(key)),
-            };
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
 
-            info!(logger, "reply"; "response" => format!("{:?}", response));
-            let content = serde_json::to_vec(&response)?;
-            writer.write_all(&content)?;
-            writer.flush()?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Raft messages are sent many times a second, and shards are imported in large chunks,
+        // so they are only traced.
+        let mut quiet = false;
+        // Requests are logged at the info level while the request log is on for the connection.
+        let mut logged = false;
+        let start = Instant::now();
+        let frame = serde_json::from_str::<RequestFrame>(&line);
+        let name = frame.as_ref().ok().map(|frame| frame.request.name());
+        // Taken before the request is moved into `handle`, for the slow log.
+        let summary = frame.as_ref().ok().map(|frame| slowlog::Summary::of(&frame.request));
+        let denied = frame
+            .as_ref()
+            .ok()
+            .and_then(|frame| authorize(services.auth, user.as_deref(), &frame.request).err());
+        let (id, response) = match frame {
+            // Credentials are never logged.
+            Ok(RequestFrame {
+                id,
+                request: Request::Auth { credentials },
+            }) => {
+                info!(logger, "client authenticating"; "id" => id);
+                let response = match services.auth {
+                    Some(auth) => Response::auth(auth.authenticate(peer, &credentials)),
+                    None => Response::Error(ProtocolError::new(
+                        ErrorCode::InvalidRequest,
+                        "authentication is not enabled on this server",
+                    )),
+                };
+                if let Response::Auth(Ok(name)) = &response {
+                    user = Some(name.clone());
+                }
+                (id, response)
+            }
+            Ok(RequestFrame { id, .. }) if services.auth.is_some() && user.is_none() => {
+                info!(logger, "refusing a request before authentication"; "id" => id);
+                (id, Response::Error(ProtocolError::new(ErrorCode::Unauthorized, "authentication required")))
+            }
+            Ok(RequestFrame { id, .. }) if denied.is_some() => {
+                let err = denied.expect("the refusal is missing");
+                info!(logger, "refusing a request the user has no permission for"; "id" => id, "error" => format!("{}", err));
+                (id, Response::Error(err.into()))
+            }
+            Ok(RequestFrame {
+                id,
+                request: Request::Sync { replid, offset },
+            }) if services.replication.is_some() => {
+                info!(logger, "follower came"; "id" => id, "offset" => offset);
+                writer.flush()?;
+                return services
+                    .replication
+                    .expect("replication is missing")
+                    .serve_follower(&store, id, (replid, offset), &mut writer, logger);
+            }
+            Ok(RequestFrame {
+                id,
+                request: Request::Watch { prefix, stream, seq },
+            }) if store.changes().is_some() => {
+                info!(logger, "watcher came"; "id" => id, "prefix" => &prefix, "seq" => seq);
+                writer.flush()?;
+                return store
+                    .changes()
+                    .expect("change feed is missing")
+                    .serve_watcher(id, &prefix, (stream, seq), &mut writer, logger);
+            }
+            Ok(RequestFrame {
+                id,
+                request: Request::Subscribe { channels, patterns },
+            }) => {
+                info!(logger, "subscriber came"; "id" => id, "channels" => channels.join(","), "patterns" => patterns.join(","));
+                writer.flush()?;
+                return services.pubsub.serve_subscriber(id, channels, patterns, &mut writer, logger);
+            }
+            Ok(RequestFrame { id, request }) => {
+                quiet = matches!(request, Request::Raft(_) | Request::ImportShard { .. });
+                logged = services.request_log.logs(&client);
+                let described = FnValue(|_: &Record<'_>| logging::request(&request, services.log_values));
+                if quiet {
+                    trace!(logger, "request came"; "id" => id, "request" => &described);
+                } else if logged {
+                    info!(logger, "request came"; "id" => id, "request" => &described);
+                } else {
+                    debug!(logger, "request came"; "id" => id, "request" => &described);
+                }
+                (id, handle(&store, services, request))
+            }
+            Err(err) => {
+                error!(logger, "can not parse the request"; "error" => format!("{}", err));
+                let id = serde_json::from_str::<FrameId>(&line).map_or(0, |frame| frame.id);
+                (id, Response::Error(ProtocolError::new(ErrorCode::InvalidRequest, err.to_string())))
+            }
+        };
+
+        let elapsed = start.elapsed();
+        services.metrics.observe(name, elapsed, response.error().map(|err| err.code));
+        if let Some(summary) = summary {
+            services.slowlog.record(&client, || summary.to_string(), elapsed);
+        }
+        let described = FnValue(|_: &Record<'_>| logging::response(&response, services.log_values));
+        if quiet {
+            trace!(logger, "reply"; "id" => id, "response" => &described);
+        } else if logged {
+            info!(logger, "reply"; "id" => id, "response" => &described);
         } else {
-            error!(logger, "can not parse the request");
+            debug!(logger, "reply"; "id" => id, "response" => &described);
         }
+        serde_json::to_writer(&mut writer, &ResponseFrame { id, response })?;
+        writer.write_all(b"\n")?;
     }
 
+    writer.flush()?;
     Ok(())
 }
+
+/// Check that the user a connection authenticated as may send a request, when clients have to
+/// authenticate.
+fn authorize(auth: Option<&Auth>, user: Option<&str>, request: &Request) -> Result<()> {
+    match (auth, user) {
+        (Some(auth), Some(user)) => auth.authorize(user, request),
+        _ => Ok(()),
+    }
+}
+
+fn handle<E: KvsEngine>(store: &E, services: Services<'_>, request: Request) -> Response {
+    let Services {
+        replication,
+        raft,
+        sharding,
+        pubsub,
+        slowlog,
+        request_log,
+        ..
+    } = services;
+    match request {
+        // Connections are authenticated by `serve`.
+        Request::Auth { .. } => unreachable!(),
+        Request::Set { key, value } => Response::set(store.set(key, value)),
+        Request::Get { key } => Response::get(store.get(key)),
+        Request::Remove { key } => Response::remove(store.remove(key)),
+        Request::GetMany { keys } => Response::get_many(store.get_many(keys)),
+        Request::SetMany { pairs } => Response::set_many(store.set_many(pairs)),
+        Request::RemoveMany { keys } => Response::remove_many(store.remove_many(keys)),
+        Request::Scan { prefix } => Response::scan(store.scan(prefix)),
+        Request::Ping => Response::Pong,
+        Request::Info => Response::Info(info(store, services)),
+        Request::Compact => Response::compact(compact(store)),
+        Request::SlowLog { reset } => Response::SlowLog(slowlog.entries(reset)),
+        Request::RequestLog { enabled, client } => Response::RequestLog(request_log.switch(enabled, client)),
+        Request::ReplicationInfo => match replication {
+            Some(replication) => Response::ReplicationInfo(replication.info()),
+            None => replication_disabled(),
+        },
+        // Followers are served by `Replication::serve_follower` when replication is enabled.
+        Request::Sync { .. } => replication_disabled(),
+        Request::Raft(request) => match raft.map(|raft| raft.handle(request)) {
+            Some(Ok(response)) => Response::Raft(response),
+            Some(Err(err)) => Response::Error(err.into()),
+            None => raft_disabled(),
+        },
+        Request::ClusterInfo => match raft {
+            Some(raft) => Response::ClusterInfo(raft.info()),
+            None => raft_disabled(),
+        },
+        Request::AddMember { addr } => match raft {
+            Some(raft) => Response::members(raft.change_members(Some(addr), None)),
+            None => raft_disabled(),
+        },
+        Request::RemoveMember { addr } => match raft {
+            Some(raft) => Response::members(raft.change_members(None, Some(addr))),
+            None => raft_disabled(),
+        },
+        Request::ShardMap => match sharding {
+            Some(sharding) => Response::shard_map(Ok(sharding.map())),
+            None => sharding_disabled(),
+        },
+        Request::SetShardMap { map } => match sharding {
+            Some(sharding) => Response::shard_map(sharding.set_map(map)),
+            None => sharding_disabled(),
+        },
+        Request::MoveShard { shard, to } => match sharding {
+            Some(sharding) => Response::shard_map(sharding.move_shard(shard, to)),
+            None => sharding_disabled(),
+        },
+        Request::ImportShard {
+            shard,
+            first,
+            pairs,
+            removed,
+        } => match sharding {
+            Some(sharding) => Response::import_shard(sharding.import(shard, first, pairs, removed)),
+            None => sharding_disabled(),
+        },
+        Request::Publish { channel, message } => Response::Publish(Ok(pubsub.publish(channel, message))),
+        // Subscribers are served by `PubSub::serve_subscriber`.
+        Request::Subscribe { .. } => unreachable!(),
+        // Watchers are served by `ChangeFeed::serve_watcher` when the engine publishes its changes.
+        Request::Watch { .. } => Response::Error(ProtocolError::new(
+            ErrorCode::InvalidRequest,
+            "the engine of this server does not publish its changes",
+        )),
+    }
+}
+
+fn replication_disabled() -> Response {
+    Response::Error(ProtocolError::new(
+        ErrorCode::InvalidRequest,
+        "replication is not enabled on this server",
+    ))
+}
+
+fn raft_disabled() -> Response {
+    Response::Error(ProtocolError::new(
+        ErrorCode::InvalidRequest,
+        "this server is not a node of a Raft cluster",
+    ))
+}
+
+fn sharding_disabled() -> Response {
+    Response::Error(ProtocolError::new(
+        ErrorCode::InvalidRequest,
+        "this server is not a node of a sharded cluster",
+    ))
+}
+
+/// The id of a request frame which can not be parsed as a whole.
+#[derive(Deserialize)]
+struct FrameId {
+    id: u64,
+}
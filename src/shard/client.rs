@@ -0,0 +1,309 @@
+use super::ShardMap;
+use crate::client::{KvsClient, Timeouts, MAX_REDIRECTS};
+use crate::error::{Error, ErrorKind};
+use crate::protocol::{ErrorCode, ProtocolError};
+use crate::{ClientTls, Credentials, Request, Response, Result};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+use std::time::Duration;
+
+// How long to wait before following a redirect which did not give a newer shard map, as the node
+// a shard moved to may not have taken the map yet and would redirect back.
+const REDIRECT_DELAY: Duration = Duration::from_millis(50);
+
+/// A client of a sharded cluster, which sends each key to the node serving its shard.
+///
+/// The shard map is fetched when the client connects, and again from the node a shard moved to
+/// whenever a node replies that it does not serve a key. Requests of many keys are split into one
+/// request per node, and scans are sent to every node.
+///
+/// # Example
+///
+/// ```no_run
+/// # use kvs::{Result, ShardedClient};
+/// # fn main() -> Result<()> {
+/// let mut client = ShardedClient::connect(&["127.0.0.1:4001", "127.0.0.1:4002"])?;
+/// client.set("key".to_string(), "value".to_string())?;
+/// assert_eq!(client.get("key".to_string())?, Some("value".to_string()));
+/// # Ok(())
+/// # }
+/// ```
+pub struct ShardedClient {
+    map: ShardMap,
+    seeds: Vec<String>,
+    clients: HashMap<String, KvsClient>,
+    timeouts: Timeouts,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
+}
+
+impl ShardedClient {
+    /// Connect a sharded cluster through any of given nodes, and get a new client.
+    pub fn connect(addrs: &[&str]) -> Result<ShardedClient> {
+        ShardedClient::connect_with(addrs, Timeouts::default())
+    }
+
+    /// Connect a sharded cluster through any of given nodes with given timeouts for every
+    /// connection, and get a new client.
+    pub fn connect_with(addrs: &[&str], timeouts: Timeouts) -> Result<ShardedClient> {
+        ShardedClient::open(addrs, timeouts, None, None)
+    }
+
+    /// Connect a sharded cluster through any of given nodes with given timeouts for every
+    /// connection, authenticating every connection with given credentials, and get a new client.
+    pub fn connect_with_credentials(addrs: &[&str], timeouts: Timeouts, credentials: Credentials) -> Result<ShardedClient> {
+        ShardedClient::open(addrs, timeouts, Some(credentials), None)
+    }
+
+    /// Connect a sharded cluster through any of given nodes with TLS and given timeouts for every
+    /// connection, authenticating every connection with given credentials if any, and get a new
+    /// client.
+    pub fn connect_tls(
+        addrs: &[&str],
+        timeouts: Timeouts,
+        credentials: Option<Credentials>,
+        tls: ClientTls,
+    ) -> Result<ShardedClient> {
+        ShardedClient::open(addrs, timeouts, credentials, Some(tls))
+    }
+
+    fn open(
+        addrs: &[&str],
+        timeouts: Timeouts,
+        credentials: Option<Credentials>,
+        tls: Option<ClientTls>,
+    ) -> Result<ShardedClient> {
+        let mut client = ShardedClient {
+            map: ShardMap::default(),
+            seeds: addrs.iter().map(ToString::to_string).collect(),
+            clients: HashMap::new(),
+            timeouts,
+            credentials,
+            tls,
+        };
+
+        client.refresh()?;
+        if client.map.shards.is_empty() {
+            return Err(Error::from(ErrorKind::Busy(
+                "the cluster has no shard map yet".to_string(),
+            )));
+        }
+        Ok(client)
+    }
+
+    /// Get the shard map the client routes keys with.
+    pub fn shard_map(&self) -> &ShardMap {
+        &self.map
+    }
+
+    /// Fetch the shard map from the given nodes and the nodes of the map, keeping the newest one.
+    /// Return an error if no node can be reached.
+    pub fn refresh(&mut self) -> Result<()> {
+        let mut nodes = self.seeds.clone();
+        nodes.extend(self.map.nodes().into_iter().map(ToString::to_string));
+        nodes.sort_unstable();
+        nodes.dedup();
+
+        let mut last_err = None;
+        let mut fetched = false;
+        for node in nodes {
+            match self.fetch_map(&node) {
+                Ok(_) => fetched = true,
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        match last_err {
+            Some(err) if !fetched => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the value of a string key to a string.
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        let node = self.node_of(&key)?;
+        self.call(node, Request::Set { key, value })?.into_set()
+    }
+
+    /// Gets the string value of the a string key.
+    /// If the key does not exist, return None.
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        let node = self.node_of(&key)?;
+        self.call(node, Request::Get { key })?.into_get()
+    }
+
+    /// Removes a given key.
+    /// Return an error if the key does not exist or is not removed successfully.
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        let node = self.node_of(&key)?;
+        self.call(node, Request::Remove { key })?.into_remove()
+    }
+
+    /// Gets the string values of many string keys with one request per node, in the order of
+    /// the keys. The value of a key which does not exist is None.
+    pub fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.call_many(
+            &keys,
+            |indices| Request::GetMany {
+                keys: indices.iter().map(|index| keys[*index].clone()).collect(),
+            },
+            |response, _| response.into_get_many(),
+        )
+    }
+
+    /// Sets the values of many string keys with one request per node.
+    /// Pairs sent to other nodes may be written when the request fails on some node.
+    pub fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        let keys = pairs.iter().map(|(key, _)| key.clone()).collect::<Vec<String>>();
+        self.call_many(
+            &keys,
+            |indices| Request::SetMany {
+                pairs: indices.iter().map(|index| pairs[*index].clone()).collect(),
+            },
+            |response, len| response.into_set_many().map(|()| vec![(); len]),
+        )
+        .map(drop)
+    }
+
+    /// Removes many keys with one request per node, and tells whether each key existed.
+    pub fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<bool>> {
+        self.call_many(
+            &keys,
+            |indices| Request::RemoveMany {
+                keys: indices.iter().map(|index| keys[*index].clone()).collect(),
+            },
+            |response, _| response.into_remove_many(),
+        )
+    }
+
+    /// Gets all keys starting with a given prefix from every node, in sorted order.
+    pub fn scan(&mut self, prefix: String) -> Result<Vec<String>> {
+        let nodes = self.map.nodes().into_iter().map(ToString::to_string).collect::<Vec<String>>();
+        let mut keys = Vec::new();
+        for node in nodes {
+            let request = Request::Scan { prefix: prefix.clone() };
+            keys.extend(self.send(&node, &request)?.into_scan()?);
+        }
+
+        keys.sort_unstable();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    fn node_of(&self, key: &str) -> Result<String> {
+        match self.map.node_of(key) {
+            Some(node) => Ok(node.to_string()),
+            None => Err(Error::from(ErrorKind::Busy(
+                "the cluster has no shard map yet".to_string(),
+            ))),
+        }
+    }
+
+    /// Send a request of one key, following the node the shard of the key moved to.
+    fn call(&mut self, mut node: String, request: Request) -> Result<Response> {
+        let mut response = self.send(&node, &request)?;
+        for _ in 0..MAX_REDIRECTS {
+            match moved_to(&response) {
+                Some(to) => {
+                    if !self.fetch_map(&to)? {
+                        thread::sleep(REDIRECT_DELAY);
+                    }
+                    node = to;
+                }
+                None => break,
+            }
+            response = self.send(&node, &request)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Send a request for the keys of each node, built from the indices of its keys, and collect
+    /// the results by the indices of the keys. The keys of nodes which reply that they do not
+    /// serve them are sent again with the map of the node their shard moved to.
+    fn call_many<T>(
+        &mut self,
+        keys: &[String],
+        request: impl Fn(&[usize]) -> Request,
+        into: impl Fn(Response, usize) -> Result<Vec<T>>,
+    ) -> Result<Vec<T>> {
+        let mut results = Vec::with_capacity(keys.len());
+        results.resize_with(keys.len(), || None);
+
+        let mut pending = (0..keys.len()).collect::<Vec<usize>>();
+        let mut moved_err: Option<ProtocolError> = None;
+        for _ in 0..=MAX_REDIRECTS {
+            let mut routes: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+            for index in pending.drain(..) {
+                routes.entry(self.node_of(&keys[index])?).or_default().push(index);
+            }
+
+            let mut moved = Vec::new();
+            for (node, indices) in routes {
+                let response = self.send(&node, &request(&indices))?;
+                if let Some(to) = moved_to(&response) {
+                    moved_err = response.error().cloned();
+                    moved.push(to);
+                    pending.extend(indices);
+                    continue;
+                }
+
+                for (index, result) in indices.iter().zip(into(response, indices.len())?) {
+                    results[*index] = Some(result);
+                }
+            }
+
+            if pending.is_empty() {
+                return Ok(results.into_iter().flatten().collect());
+            }
+            let mut newer = false;
+            for to in moved {
+                newer |= self.fetch_map(&to)?;
+            }
+            if !newer {
+                thread::sleep(REDIRECT_DELAY);
+            }
+        }
+
+        Err(Error::from(moved_err.expect("keys were moved")))
+    }
+
+    /// Send a request to a node over its connection, which is opened again when it breaks.
+    fn send(&mut self, node: &str, request: &Request) -> Result<Response> {
+        let (timeouts, credentials, tls) = (self.timeouts, self.credentials.as_ref(), self.tls.as_ref());
+        let connect = || KvsClient::connect_as(node, timeouts, credentials, tls);
+        let client = match self.clients.entry(node.to_string()) {
+            Entry::Occupied(entry) if !entry.get().is_closed() => entry.into_mut(),
+            Entry::Occupied(mut entry) => {
+                entry.insert(connect()?);
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(connect()?),
+        };
+
+        let response = client.call_once(request);
+        if response.is_err() {
+            self.clients.remove(node);
+        }
+        response
+    }
+
+    /// Fetch the shard map of a node, and keep it if it is newer, telling whether it was.
+    fn fetch_map(&mut self, node: &str) -> Result<bool> {
+        let map = self.send(node, &Request::ShardMap)?.into_shard_map()?;
+        let newer = map.version > self.map.version;
+        if newer {
+            self.map = map;
+        }
+        Ok(newer)
+    }
+}
+
+/// Get the node a response tells the shard of the key moved to.
+fn moved_to(response: &Response) -> Option<String> {
+    response
+        .error()
+        .filter(|err| err.code == ErrorCode::Moved)
+        .and_then(|err| err.redirect.clone())
+}
@@ -0,0 +1,493 @@
+use crate::client::{KvsClient, Timeouts};
+use crate::engine::{EngineStats, KvsEngine};
+use crate::watch::ChangeFeed;
+use crate::error::{Error, ErrorKind};
+use crate::{ClientTls, Credentials, Result};
+use serde::{Deserialize, Serialize};
+use slog::{info, warn, Logger};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::Duration;
+
+pub use client::ShardedClient;
+
+mod client;
+
+// The keys of a moving shard are sent in chunks of at most this many keys.
+const CHUNK_LEN: usize = 1024;
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+// The node a shard is moved to is sent the new map at most this many times.
+const MAP_ATTEMPTS: usize = 3;
+const RETRY_INTERVAL: Duration = Duration::from_millis(200);
+// The file of the shard map in the data directory of a node.
+const MAP_FILE: &str = "shards";
+
+/// Which node serves each shard of a sharded cluster.
+///
+/// Keys are hashed into a fixed number of shards, so that moving a shard to another node moves
+/// the keys of this shard only, whatever the number of nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardMap {
+    /// Incremented by every change, so that nodes never go back to an older map.
+    pub version: u64,
+    /// The address of the node serving each shard, indexed by shard.
+    pub shards: Vec<String>,
+}
+
+impl ShardMap {
+    /// Spread a given number of shards over given nodes in turn, as the first version of a map.
+    pub fn new(shards: usize, nodes: &[String]) -> ShardMap {
+        ShardMap {
+            version: 1,
+            shards: nodes.iter().cycle().take(shards).cloned().collect(),
+        }
+    }
+
+    /// Get the shard of a key, or None if the map has no shards.
+    pub fn shard_of(&self, key: &str) -> Option<usize> {
+        let shard = fnv1a(key.as_bytes()).checked_rem(self.shards.len() as u64)?;
+        Some(shard as usize)
+    }
+
+    /// Get the address of the node serving a key, or None if the map has no shards.
+    pub fn node_of(&self, key: &str) -> Option<&str> {
+        self.shard_of(key).map(|shard| self.shards[shard].as_str())
+    }
+
+    /// Get the addresses of the nodes serving any shard, in sorted order.
+    pub fn nodes(&self) -> Vec<&str> {
+        let nodes = self.shards.iter().map(String::as_str).collect::<BTreeSet<&str>>();
+        nodes.into_iter().collect()
+    }
+
+    /// Get the shards served by a given node, in order.
+    pub fn shards_of(&self, node: &str) -> Vec<usize> {
+        (0..self.shards.len()).filter(|shard| self.shards[*shard] == node).collect()
+    }
+}
+
+/// Hash with 64-bit FNV-1a, which is the same on every node whatever it runs on.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The shard state of a server, given to `KvsServer::sharding` so that it can report and
+/// replace the shard map, and move shards to other nodes.
+#[derive(Clone)]
+pub struct Sharding(Arc<dyn Shards>);
+
+impl Sharding {
+    /// Get the shard map of the node.
+    pub fn map(&self) -> ShardMap {
+        self.0.map()
+    }
+
+    pub(crate) fn set_map(&self, map: ShardMap) -> Result<ShardMap> {
+        self.0.set_map(map)
+    }
+
+    /// Move a shard served by the node to another node, and return the map after the move.
+    pub(crate) fn move_shard(&self, shard: usize, to: String) -> Result<ShardMap> {
+        self.0.move_shard(shard, to)
+    }
+
+    pub(crate) fn import(
+        &self,
+        shard: usize,
+        first: bool,
+        pairs: Vec<(String, String)>,
+        removed: Vec<String>,
+    ) -> Result<()> {
+        self.0.import(shard, first, pairs, removed)
+    }
+}
+
+trait Shards: Send + Sync {
+    fn map(&self) -> ShardMap;
+    fn set_map(&self, map: ShardMap) -> Result<ShardMap>;
+    fn move_shard(&self, shard: usize, to: String) -> Result<ShardMap>;
+    fn import(&self, shard: usize, first: bool, pairs: Vec<(String, String)>, removed: Vec<String>) -> Result<()>;
+}
+
+/// An engine serving the shards of a sharded cluster assigned to a node by the shard map.
+///
+/// Requests for keys of other shards are refused with `ErrorKind::Moved`, which tells clients
+/// where the shard is served, and scans only see the keys of the shards of the node. A node
+/// without a shard map, such as a new one, serves nothing until `kvs-admin` gives it one.
+///
+/// A shard is moved by the node serving it: the keys of the shard are copied to the other node
+/// while they are still served, then the requests of every shard are held while the keys written
+/// during the copy are sent again and the new map is taken, after which the moved keys are removed.
+/// Moves are expected to run one at a time in a cluster, as `kvs-admin` does.
+#[derive(Clone)]
+pub struct ShardNode<E: KvsEngine> {
+    engine: E,
+    shared: Arc<Shared>,
+}
+
+impl<E: KvsEngine> ShardNode<E> {
+    /// Serve the shards of a node with a given address, where `path` is the data directory of
+    /// the engine, which keeps the shard map. The node authenticates to the other nodes with
+    /// given credentials, and connects to them with given TLS settings, if they require them.
+    pub fn open(
+        engine: E,
+        id: impl Into<String>,
+        credentials: Option<Credentials>,
+        tls: Option<ClientTls>,
+        path: impl Into<PathBuf>,
+        logger: Logger,
+    ) -> Result<ShardNode<E>> {
+        let path = path.into().join(MAP_FILE);
+        let map: ShardMap = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => ShardMap::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let id = id.into();
+        info!(logger, "shard node opened"; "version" => map.version, "shards" => format!("{:?}", map.shards_of(&id)));
+        let shared = Arc::new(Shared {
+            id,
+            credentials,
+            tls,
+            path,
+            map: RwLock::new(map),
+            moving: Mutex::new(None),
+            logger,
+        });
+
+        Ok(ShardNode { engine, shared })
+    }
+
+    /// Get the shard state, to be given to `KvsServer::sharding`.
+    pub fn sharding(&self) -> Sharding {
+        Sharding(Arc::new(Handle {
+            engine: Mutex::new(self.engine.clone()),
+            shared: Arc::clone(&self.shared),
+        }))
+    }
+}
+
+impl<E: KvsEngine> KvsEngine for ShardNode<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let _map = self.shared.enter(&[&key], true)?;
+        self.engine.set(key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        let _map = self.shared.enter(&[&key], false)?;
+        self.engine.get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let _map = self.shared.enter(&[&key], true)?;
+        self.engine.remove(key)
+    }
+
+    fn scan(&self, prefix: String) -> Result<Vec<String>> {
+        let map = self.shared.map.read().unwrap();
+        let mut keys = self.engine.scan(prefix)?;
+        keys.retain(|key| map.node_of(key) == Some(self.shared.id.as_str()));
+        Ok(keys)
+    }
+
+    /// Gets the keys of the shards of the node following a given key, reading pages of the engine
+    /// until enough of them are found, as the keys of other shards are skipped.
+    fn scan_after(&self, mut after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        let map = self.shared.map.read().unwrap();
+        let mut keys = Vec::new();
+        while keys.len() < limit {
+            let page = self.engine.scan_after(after, limit)?;
+            let done = page.len() < limit;
+            after = page.last().cloned();
+            keys.extend(page.into_iter().filter(|key| map.node_of(key) == Some(self.shared.id.as_str())));
+            if done {
+                break;
+            }
+        }
+        keys.truncate(limit);
+        Ok(keys)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        let _map = self.shared.enter(&keys.iter().map(String::as_str).collect::<Vec<&str>>(), false)?;
+        self.engine.get_many(keys)
+    }
+
+    fn set_many(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        let _map = self.shared.enter(&pairs.iter().map(|(key, _)| key.as_str()).collect::<Vec<&str>>(), true)?;
+        self.engine.set_many(pairs)
+    }
+
+    fn remove_many(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        let _map = self.shared.enter(&keys.iter().map(String::as_str).collect::<Vec<&str>>(), true)?;
+        self.engine.remove_many(keys)
+    }
+
+    fn changes(&self) -> Option<ChangeFeed> {
+        self.engine.changes()
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.engine.compact()
+    }
+
+    fn stats(&self) -> Option<EngineStats> {
+        self.engine.stats()
+    }
+}
+
+struct Handle<E: KvsEngine> {
+    engine: Mutex<E>,
+    shared: Arc<Shared>,
+}
+
+impl<E: KvsEngine> Shards for Handle<E> {
+    fn map(&self) -> ShardMap {
+        self.shared.map.read().unwrap().clone()
+    }
+
+    fn set_map(&self, map: ShardMap) -> Result<ShardMap> {
+        let mut current = self.shared.map.write().unwrap();
+        if map.version < current.version || (map.version == current.version && map != *current) {
+            return Err(Error::from(ErrorKind::InvalidRequest(format!(
+                "the node has version {} of the shard map, which is newer than version {}",
+                current.version, map.version
+            ))));
+        }
+        if !current.shards.is_empty() && map.shards.len() != current.shards.len() {
+            return Err(Error::from(ErrorKind::InvalidRequest(format!(
+                "the cluster has {} shards, which never changes",
+                current.shards.len()
+            ))));
+        }
+
+        if map.version > current.version {
+            info!(self.shared.logger, "shard map changed";
+                "version" => map.version,
+                "shards" => format!("{:?}", map.shards_of(&self.shared.id))
+            );
+            self.shared.save(&map)?;
+            *current = map;
+        }
+        Ok(current.clone())
+    }
+
+    fn move_shard(&self, shard: usize, to: String) -> Result<ShardMap> {
+        let engine = self.engine.lock().unwrap().clone();
+        let map = self.map();
+        match map.shards.get(shard) {
+            None => {
+                return Err(Error::from(ErrorKind::InvalidRequest(format!(
+                    "the cluster has no shard {}",
+                    shard
+                ))))
+            }
+            Some(node) if *node != self.shared.id => return Err(moved(shard, node)),
+            Some(_) if to == self.shared.id => return Ok(map),
+            Some(_) => (),
+        }
+
+        {
+            let mut moving = self.shared.moving.lock().unwrap();
+            if let Some((moving, _)) = *moving {
+                return Err(Error::from(ErrorKind::Busy(format!("shard {} is being moved", moving))));
+            }
+            *moving = Some((shard, HashSet::new()));
+        }
+        let moved = self.shared.move_shard(&engine, map, shard, &to);
+        *self.shared.moving.lock().unwrap() = None;
+        let map = moved?;
+
+        // The other nodes learn the new map now, or else by the redirects of the nodes which did.
+        for node in map.nodes().into_iter().filter(|node| *node != to && *node != self.shared.id) {
+            if let Err(err) = self.shared.send_map(node, &map) {
+                warn!(self.shared.logger, "can not send the shard map"; "node" => node, "error" => format!("{}", err));
+            }
+        }
+
+        let removed = keys_of(&engine, &map, shard).and_then(|keys| {
+            keys.chunks(CHUNK_LEN)
+                .try_for_each(|chunk| engine.remove_many(chunk.to_vec()).map(drop))
+        });
+        if let Err(err) = removed {
+            warn!(self.shared.logger, "can not remove the moved keys"; "shard" => shard, "error" => format!("{}", err));
+        }
+        Ok(map)
+    }
+
+    fn import(&self, shard: usize, first: bool, pairs: Vec<(String, String)>, removed: Vec<String>) -> Result<()> {
+        let engine = self.engine.lock().unwrap().clone();
+        let map = self.shared.map.read().unwrap();
+        match map.shards.get(shard) {
+            None => {
+                return Err(Error::from(ErrorKind::InvalidRequest(format!(
+                    "the node has no shard {} in version {} of the shard map",
+                    shard, map.version
+                ))))
+            }
+            Some(node) if *node == self.shared.id => {
+                return Err(Error::from(ErrorKind::InvalidRequest(format!(
+                    "shard {} is already served by this node",
+                    shard
+                ))))
+            }
+            Some(_) => (),
+        }
+
+        // Keys left by a move which failed would otherwise come back with the shard.
+        if first {
+            for chunk in keys_of(&engine, &map, shard)?.chunks(CHUNK_LEN) {
+                engine.remove_many(chunk.to_vec())?;
+            }
+        }
+        engine.set_many(pairs)?;
+        engine.remove_many(removed).map(drop)
+    }
+}
+
+struct Shared {
+    id: String,
+    credentials: Option<Credentials>,
+    tls: Option<ClientTls>,
+    path: PathBuf,
+    // Read by every request, and written when the map changes, which holds the requests while
+    // a shard is handed over.
+    map: RwLock<ShardMap>,
+    // The shard being moved away, and the keys of it written since the move started.
+    moving: Mutex<Option<(usize, HashSet<String>)>>,
+    logger: Logger,
+}
+
+impl Shared {
+    /// Connect another node of the cluster.
+    fn connect(&self, node: &str) -> Result<KvsClient> {
+        KvsClient::connect_as(node, Timeouts::all(RPC_TIMEOUT), self.credentials.as_ref(), self.tls.as_ref())
+    }
+
+    fn send_map(&self, node: &str, map: &ShardMap) -> Result<()> {
+        self.connect(node)?.set_shard_map(map.clone()).map(drop)
+    }
+
+    /// Check that the node serves given keys, and hold the map until the request is served.
+    fn enter(&self, keys: &[&str], write: bool) -> Result<RwLockReadGuard<'_, ShardMap>> {
+        let map = self.map.read().unwrap();
+        for key in keys {
+            match map.shard_of(key) {
+                None => {
+                    return Err(Error::from(ErrorKind::Busy(
+                        "the node has no shard map yet".to_string(),
+                    )))
+                }
+                Some(shard) if map.shards[shard] != self.id => return Err(moved(shard, &map.shards[shard])),
+                Some(_) => (),
+            }
+        }
+
+        if write {
+            if let Some((shard, written)) = self.moving.lock().unwrap().as_mut() {
+                let keys = keys.iter().filter(|key| map.shard_of(key) == Some(*shard));
+                written.extend(keys.map(|key| key.to_string()));
+            }
+        }
+        Ok(map)
+    }
+
+    fn move_shard<E: KvsEngine>(&self, engine: &E, map: ShardMap, shard: usize, to: &str) -> Result<ShardMap> {
+        let mut target = self.connect(to)?;
+        target.set_shard_map(map.clone())?;
+        info!(self.logger, "moving shard"; "shard" => shard, "to" => to);
+
+        target.import_shard(shard, true, Vec::new(), Vec::new())?;
+        let keys = keys_of(engine, &map, shard)?;
+        for chunk in keys.chunks(CHUNK_LEN) {
+            send_keys(engine, &mut target, shard, chunk)?;
+        }
+
+        let mut current = self.map.write().unwrap();
+        if *current != map {
+            return Err(Error::from(ErrorKind::Busy(
+                "the shard map changed while the shard was moved".to_string(),
+            )));
+        }
+        let written = match self.moving.lock().unwrap().take() {
+            Some((_, written)) => written.into_iter().collect::<Vec<String>>(),
+            None => Vec::new(),
+        };
+        for chunk in written.chunks(CHUNK_LEN) {
+            send_keys(engine, &mut target, shard, chunk)?;
+        }
+
+        // The node stops serving the shard before the other node starts, so that the shard is
+        // never written on both of them.
+        let mut moved = map;
+        moved.version += 1;
+        moved.shards[shard] = to.to_string();
+        self.save(&moved)?;
+        *current = moved.clone();
+        drop(current);
+
+        let mut sent = self.send_map(to, &moved);
+        for _ in 1..MAP_ATTEMPTS {
+            if sent.is_ok() {
+                break;
+            }
+            thread::sleep(RETRY_INTERVAL);
+            sent = self.send_map(to, &moved);
+        }
+        match sent {
+            Ok(()) => {
+                info!(self.logger, "shard moved"; "shard" => shard, "to" => to, "version" => moved.version);
+                Ok(moved)
+            }
+            Err(err) => Err(Error::from(ErrorKind::StringError(format!(
+                "shard {} was handed over, but {} did not take version {} of the shard map: {}",
+                shard, to, moved.version, err
+            )))),
+        }
+    }
+
+    /// Write the shard map atomically, through a temporary file.
+    fn save(&self, map: &ShardMap) -> Result<()> {
+        let temp = self.path.with_extension("tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(&serde_json::to_vec(map)?)?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn moved(shard: usize, node: &str) -> Error {
+    Error::from(ErrorKind::Moved {
+        message: format!("shard {} is served by {}", shard, node),
+        node: node.to_string(),
+    })
+}
+
+/// Get the keys of a shard in an engine, which the node may serve or not.
+fn keys_of<E: KvsEngine>(engine: &E, map: &ShardMap, shard: usize) -> Result<Vec<String>> {
+    let mut keys = engine.scan(String::new())?;
+    keys.retain(|key| map.shard_of(key) == Some(shard));
+    Ok(keys)
+}
+
+/// Send the current values of given keys of a shard, where missing keys are sent as removed.
+fn send_keys<E: KvsEngine>(engine: &E, target: &mut KvsClient, shard: usize, keys: &[String]) -> Result<()> {
+    let values = engine.get_many(keys.to_vec())?;
+    let (mut pairs, mut removed) = (Vec::new(), Vec::new());
+    for (key, value) in keys.iter().cloned().zip(values) {
+        match value {
+            Some(value) => pairs.push((key, value)),
+            None => removed.push(key),
+        }
+    }
+
+    target.import_shard(shard, false, pairs, removed)
+}
@@ -0,0 +1,131 @@
+use crate::Request;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::iter;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Summaries list at most this many keys of a request, each cut to at most this many chars.
+const SUMMARY_KEYS: usize = 5;
+const SUMMARY_KEY_LEN: usize = 64;
+
+/// A request which took longer than the threshold of the slow log, as replied to
+/// `Request::SlowLog`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowRequest {
+    /// The id of the entry, which grows with every entry logged since the server started.
+    pub id: u64,
+    /// Milliseconds since the Unix epoch when the request was replied.
+    pub timestamp_ms: u64,
+    /// The address of the client, or `unix` for a client of a Unix socket.
+    pub client: String,
+    /// The type of the request and the keys it works on, without values.
+    pub request: String,
+    /// Microseconds the request took.
+    pub duration_us: u64,
+}
+
+/// The requests of a server which took longer than a threshold, newest first, of which only the
+/// latest are kept.
+#[derive(Clone)]
+pub(crate) struct SlowLog(Arc<Inner>);
+
+struct Inner {
+    threshold: Duration,
+    len: usize,
+    entries: Mutex<(u64, VecDeque<SlowRequest>)>,
+}
+
+impl SlowLog {
+    pub(crate) fn new(threshold: Duration, len: usize) -> Self {
+        SlowLog(Arc::new(Inner {
+            threshold,
+            len,
+            entries: Mutex::new((0, VecDeque::new())),
+        }))
+    }
+
+    /// Log a request if it took longer than the threshold, where the summary is only made then.
+    pub(crate) fn record(&self, client: &str, summary: impl FnOnce() -> String, elapsed: Duration) {
+        if elapsed < self.0.threshold || self.0.len == 0 {
+            return;
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut entries = self.0.entries.lock().unwrap();
+        let (next_id, entries) = &mut *entries;
+        entries.push_front(SlowRequest {
+            id: *next_id,
+            timestamp_ms: timestamp.as_millis() as u64,
+            client: client.to_string(),
+            request: summary(),
+            duration_us: elapsed.as_micros() as u64,
+        });
+        entries.truncate(self.0.len);
+        *next_id += 1;
+    }
+
+    /// Get the entries, newest first, and clear them if asked to.
+    pub(crate) fn entries(&self, reset: bool) -> Vec<SlowRequest> {
+        let mut entries = self.0.entries.lock().unwrap();
+        if reset {
+            entries.1.drain(..).collect()
+        } else {
+            entries.1.iter().cloned().collect()
+        }
+    }
+}
+
+/// The type of a request and the first keys it works on, leaving values out, which is taken
+/// before the request is served and only written out for slow requests.
+pub(crate) struct Summary {
+    name: &'static str,
+    keys: Vec<String>,
+    total: usize,
+}
+
+impl Summary {
+    pub(crate) fn of(request: &Request) -> Self {
+        let keys: Box<dyn ExactSizeIterator<Item = &String>> = match request {
+            Request::Get { key } | Request::Set { key, .. } | Request::Remove { key } => Box::new(iter::once(key)),
+            Request::GetMany { keys } | Request::RemoveMany { keys } => Box::new(keys.iter()),
+            Request::SetMany { pairs } => Box::new(pairs.iter().map(|(key, _)| key)),
+            Request::Scan { prefix } | Request::Watch { prefix, .. } => Box::new(iter::once(prefix)),
+            Request::Publish { channel, .. } => Box::new(iter::once(channel)),
+            _ => Box::new(iter::empty()),
+        };
+
+        let total = keys.len();
+        let keys = keys
+            .take(SUMMARY_KEYS)
+            .map(|key| match key.char_indices().nth(SUMMARY_KEY_LEN) {
+                Some((end, _)) => format!("{}...", &key[..end]),
+                None => key.clone(),
+            })
+            .collect();
+        Summary {
+            name: request.name(),
+            keys,
+            total,
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        for key in &self.keys {
+            write!(f, " {}", key)?;
+        }
+        if self.total > self.keys.len() {
+            write!(f, " (+{} more)", self.total - self.keys.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Summarize a request by its type and the keys it works on, leaving values out.
+pub(crate) fn summary(request: &Request) -> String {
+    Summary::of(request).to_string()
+}
@@ -0,0 +1,145 @@
+use crate::tls::Tls;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A connection between a client and a server, over TCP or a Unix socket, which is encrypted if
+/// they use TLS.
+///
+/// A stream is cloned into handles sharing the connection, so that one of them reads while
+/// another one writes. The handles of a TLS connection take turns, which is enough as clients
+/// and servers never read and write a connection at the same time.
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    Tls {
+        tls: Arc<Mutex<Tls>>,
+        // A handle of the socket, to get its address and change its settings without waiting
+        // for a read of another handle.
+        socket: TcpStream,
+    },
+}
+
+impl Stream {
+    pub(crate) fn tls(tls: Tls, socket: TcpStream) -> io::Result<Stream> {
+        Ok(Stream::Tls {
+            socket: socket.try_clone()?,
+            tls: Arc::new(Mutex::new(tls)),
+        })
+    }
+
+    /// Get another handle of the connection.
+    pub(crate) fn try_clone(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+            Stream::Tls { tls, socket } => Stream::Tls {
+                tls: Arc::clone(tls),
+                socket: socket.try_clone()?,
+            },
+        })
+    }
+
+    /// Get the address of the peer, where clients connected to a Unix socket are local.
+    pub(crate) fn peer_ip(&self) -> io::Result<IpAddr> {
+        match self {
+            Stream::Unix(_) => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            Stream::Tcp(stream) | Stream::Tls { socket: stream, .. } => Ok(stream.peer_addr()?.ip()),
+        }
+    }
+
+    /// Describe the peer for logs.
+    pub(crate) fn peer_name(&self) -> String {
+        match self {
+            Stream::Unix(_) => "unix".to_string(),
+            Stream::Tcp(stream) | Stream::Tls { socket: stream, .. } => {
+                stream.peer_addr().map_or_else(|_| "unknown".to_string(), |addr| addr.to_string())
+            }
+        }
+    }
+
+    /// Set the read and write timeouts, where None waits forever.
+    pub(crate) fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+            Stream::Tcp(stream) | Stream::Tls { socket: stream, .. } => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+        }
+    }
+
+    /// Check without blocking whether the peer closed the connection, or sent anything, which
+    /// makes a connection nothing is expected from unusable.
+    pub(crate) fn is_closed(&self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return true;
+        }
+
+        // Records of a TLS connection are read rather than peeked, as the peer may send some
+        // which carry no data, such as session tickets. Unix sockets can not be peeked.
+        let result = match self {
+            Stream::Tcp(stream) => stream.peek(&mut [0u8]),
+            Stream::Unix(stream) => (&*stream).read(&mut [0u8]),
+            Stream::Tls { tls, .. } => tls.lock().unwrap().read(&mut [0u8]),
+        };
+        let closed = !matches!(result, Err(ref err) if err.kind() == io::ErrorKind::WouldBlock);
+        self.set_nonblocking(false).is_err() || closed
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Tcp(stream) | Stream::Tls { socket: stream, .. } => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).read(buf),
+            Stream::Unix(stream) => (&*stream).read(buf),
+            Stream::Tls { tls, .. } => tls.lock().unwrap().read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            Stream::Unix(stream) => (&*stream).write(buf),
+            Stream::Tls { tls, .. } => tls.lock().unwrap().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            Stream::Unix(stream) => (&*stream).flush(),
+            Stream::Tls { tls, .. } => tls.lock().unwrap().flush(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
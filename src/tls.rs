@@ -0,0 +1,205 @@
+use crate::error::ErrorKind;
+use crate::stream::Stream;
+use crate::Result;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{
+    Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerConfig, ServerConnection, ServerName,
+    StreamOwned,
+};
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Formatter};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The TLS settings of a server, as read from its config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// The PEM file of the certificate chain of the server.
+    pub cert: PathBuf,
+    /// The PEM file of the private key of the server.
+    pub key: PathBuf,
+    /// The PEM file of the certificate authorities which clients have to present a certificate
+    /// signed by. Clients are not asked for a certificate without it.
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    /// The PEM file of the certificate authorities which signed the certificates of the other
+    /// nodes of a cluster, which is the certificate of the server if they share a self-signed one.
+    #[serde(default)]
+    pub ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Get the TLS settings the nodes of a cluster connect to each other with, which present the
+    /// certificate of the server as their client certificate.
+    pub fn peer_tls(&self) -> Result<ClientTls> {
+        ClientTls::new(self.ca.as_ref().unwrap_or(&self.cert), Some((&self.cert, &self.key)))
+    }
+}
+
+/// The TLS settings of a server, given to `KvsServer::tls` so that clients connect with TLS.
+#[derive(Clone)]
+pub struct ServerTls(Arc<ServerConfig>);
+
+impl ServerTls {
+    /// Read the certificates and the key of given settings.
+    pub fn new(config: &TlsConfig) -> Result<ServerTls> {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &config.client_ca {
+            Some(path) => builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(read_roots(path)?).boxed()),
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(read_certs(&config.cert)?, read_key(&config.key)?)
+            .map_err(|err| ErrorKind::Tls(err.to_string()))?;
+        Ok(ServerTls(Arc::new(config)))
+    }
+
+    /// Wrap the connection of a client, which handshakes on its first read or write.
+    pub(crate) fn accept(&self, stream: TcpStream) -> Result<Stream> {
+        let connection = ServerConnection::new(Arc::clone(&self.0)).map_err(|err| ErrorKind::Tls(err.to_string()))?;
+        let socket = stream.try_clone()?;
+        Ok(Stream::tls(Tls::Server(StreamOwned::new(connection, stream)), socket)?)
+    }
+}
+
+/// The TLS settings of a client, which connects to servers with TLS when given them.
+#[derive(Clone)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl ClientTls {
+    /// Trust servers presenting a certificate signed by the certificate authorities of a PEM
+    /// file, and present the certificate chain and the private key of given PEM files to servers
+    /// asking for a client certificate.
+    pub fn new(ca: impl AsRef<Path>, identity: Option<(impl AsRef<Path>, impl AsRef<Path>)>) -> Result<ClientTls> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(read_roots(ca.as_ref())?);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(read_certs(cert.as_ref())?, read_key(key.as_ref())?)
+                .map_err(|err| ErrorKind::Tls(err.to_string()))?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(ClientTls {
+            config: Arc::new(config),
+            server_name: None,
+        })
+    }
+
+    /// Check the certificates of servers against a given name, rather than against the host of
+    /// the address connected to.
+    pub fn server_name(mut self, name: String) -> Self {
+        self.server_name = Some(name);
+        self
+    }
+
+    /// Wrap a connection to a server of a given address, which handshakes on its first read or
+    /// write.
+    pub(crate) fn connect(&self, addr: &str, stream: TcpStream) -> Result<Stream> {
+        let name = self.server_name.as_deref().unwrap_or_else(|| host_of(addr));
+        let name = ServerName::try_from(name)
+            .map_err(|_| ErrorKind::Tls(format!("{} is not a valid server name", name)))?;
+        let connection =
+            ClientConnection::new(Arc::clone(&self.config), name).map_err(|err| ErrorKind::Tls(err.to_string()))?;
+        let socket = stream.try_clone()?;
+        Ok(Stream::tls(Tls::Client(StreamOwned::new(connection, stream)), socket)?)
+    }
+}
+
+impl Debug for ClientTls {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTls")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+/// A TLS connection, from either of its ends.
+pub(crate) enum Tls {
+    Client(StreamOwned<ClientConnection, TcpStream>),
+    Server(StreamOwned<ServerConnection, TcpStream>),
+}
+
+impl Read for Tls {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = match self {
+            Tls::Client(stream) => stream.read(buf),
+            Tls::Server(stream) => stream.read(buf),
+        };
+
+        // Peers close connections without telling TLS first, which is as good as a plain close
+        // since requests and responses are framed by lines.
+        match result {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+            result => result,
+        }
+    }
+}
+
+impl Write for Tls {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Tls::Client(stream) => stream.write(buf),
+            Tls::Server(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Tls::Client(stream) => stream.flush(),
+            Tls::Server(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Get the host of an address such as `127.0.0.1:4000`, `[::1]:4000` or `localhost:4000`.
+fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>> {
+    let file = File::open(path).map_err(|err| ErrorKind::Tls(format!("can not read {}: {}", path.display(), err)))?;
+    Ok(rustls_pemfile::read_all(&mut BufReader::new(file))?)
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let certs = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(ErrorKind::Tls(format!("{} holds no certificate", path.display())).into());
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| ErrorKind::Tls(format!("{} holds no private key", path.display())).into())
+}
+
+fn read_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        roots.add(&cert).map_err(|err| ErrorKind::Tls(format!("{}: {}", path.display(), err)))?;
+    }
+    Ok(roots)
+}
@@ -0,0 +1,206 @@
+use crate::error::Error;
+use crate::protocol::{ErrorCode, ProtocolError, ResponseFrame};
+use crate::replication::Command;
+use crate::{Response, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// A watcher is sent changes, or a heartbeat without changes, at least this often.
+const HEARTBEAT: Duration = Duration::from_secs(1);
+// The feed keeps the latest changes, up to this many bytes of keys and values.
+const BACKLOG_BYTES: usize = 8 * 1024 * 1024;
+// Changes are sent in chunks of at most this many changes.
+const CHUNK_LEN: usize = 1024;
+
+/// A write committed to an engine, numbered by its position in the change feed of the engine,
+/// starting from 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// The position of the change.
+    pub seq: u64,
+    /// The write.
+    pub command: Command,
+}
+
+/// The replies streamed by a server to a watcher which sent `Request::Watch`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum WatchEvent {
+    /// The changes after `seq` follow. Unless the watcher is `resumed` from the position it
+    /// sent, it missed the changes before, so it reads the keys it cares about again.
+    Start {
+        /// The id of the change feed of the engine.
+        stream: String,
+        /// The position the watch starts after.
+        seq: u64,
+        /// Whether the watcher is resumed from the position it sent.
+        resumed: bool,
+    },
+    /// Changes of the watched keys committed since the previous ones, in order, which are sent
+    /// without changes as heartbeats.
+    Changes(Vec<Change>),
+}
+
+/// The changes committed to an engine, which watchers follow.
+///
+/// The latest changes are kept so that watchers can be resumed after reconnecting, as long as
+/// the engine was not opened again, which starts a feed with another id.
+#[derive(Clone)]
+pub struct ChangeFeed(Arc<Feed>);
+
+struct Feed {
+    stream: String,
+    backlog: Mutex<Backlog>,
+    published: Condvar,
+}
+
+impl ChangeFeed {
+    pub(crate) fn new() -> ChangeFeed {
+        let mut rng = rand::thread_rng();
+        let stream = (0..20).map(|_| format!("{:02x}", rng.gen::<u8>())).collect();
+
+        ChangeFeed(Arc::new(Feed {
+            stream,
+            backlog: Mutex::new(Backlog {
+                changes: VecDeque::new(),
+                seq: 0,
+                bytes: 0,
+            }),
+            published: Condvar::new(),
+        }))
+    }
+
+    /// Get the id of the feed, which changes whenever the engine is opened.
+    pub fn stream(&self) -> &str {
+        &self.0.stream
+    }
+
+    /// Get the position of the last change.
+    pub fn seq(&self) -> u64 {
+        self.0.backlog.lock().unwrap().seq
+    }
+
+    /// Number committed writes in order, and wake up the watchers.
+    pub(crate) fn publish(&self, commands: Vec<Command>) {
+        if commands.is_empty() {
+            return;
+        }
+
+        self.0.backlog.lock().unwrap().append(commands);
+        self.0.published.notify_all();
+    }
+
+    /// Wait until changes are published after a given position, or a heartbeat is due.
+    /// Return None if the changes were dropped from the backlog.
+    fn wait_changes_after(&self, seq: u64) -> Option<Vec<Change>> {
+        let backlog = self.0.backlog.lock().unwrap();
+        let (backlog, _) = self
+            .0
+            .published
+            .wait_timeout_while(backlog, HEARTBEAT, |backlog| backlog.seq == seq)
+            .unwrap();
+        backlog.changes_after(seq)
+    }
+
+    /// Stream the changes of keys starting with a given prefix to a watcher which sent
+    /// `Request::Watch` with a given id, until the connection breaks.
+    pub(crate) fn serve_watcher(
+        &self,
+        id: u64,
+        prefix: &str,
+        position: (Option<String>, u64),
+        writer: &mut impl Write,
+        logger: &Logger,
+    ) -> Result<()> {
+        let mut reply = |response: Response| -> Result<()> {
+            serde_json::to_writer(&mut *writer, &ResponseFrame { id, response })?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+            Ok(())
+        };
+
+        let (mut seq, resumed) = match position {
+            (Some(stream), seq) if stream == self.0.stream && self.has_changes_after(seq) => {
+                info!(logger, "watcher resumed"; "seq" => seq);
+                (seq, true)
+            }
+            _ => (self.seq(), false),
+        };
+        reply(Response::Watch(WatchEvent::Start {
+            stream: self.0.stream.clone(),
+            seq,
+            resumed,
+        }))?;
+
+        let mut last_sent = Instant::now();
+        loop {
+            // The watcher is told why it is dropped, so that it reads the keys again.
+            let changes = match self.wait_changes_after(seq) {
+                Some(changes) => changes,
+                None => {
+                    let err = ProtocolError::new(
+                        ErrorCode::Busy,
+                        format!("the watcher fell behind the change feed after seq {}", seq),
+                    );
+                    reply(Response::Error(err.clone()))?;
+                    return Err(Error::from(err));
+                }
+            };
+            if let Some(change) = changes.last() {
+                seq = change.seq;
+            }
+
+            let changes = changes
+                .into_iter()
+                .filter(|change| change.command.key().starts_with(prefix))
+                .collect::<Vec<Change>>();
+            if !changes.is_empty() || last_sent.elapsed() >= HEARTBEAT {
+                reply(Response::Watch(WatchEvent::Changes(changes)))?;
+                last_sent = Instant::now();
+            }
+        }
+    }
+
+    fn has_changes_after(&self, seq: u64) -> bool {
+        self.0.backlog.lock().unwrap().changes_after(seq).is_some()
+    }
+}
+
+/// The latest changes of an engine.
+struct Backlog {
+    changes: VecDeque<Change>,
+    seq: u64,
+    bytes: usize,
+}
+
+impl Backlog {
+    fn append(&mut self, commands: Vec<Command>) {
+        for command in commands {
+            self.seq += 1;
+            self.bytes += command.len();
+            self.changes.push_back(Change { seq: self.seq, command });
+        }
+
+        while self.bytes > BACKLOG_BYTES && self.changes.len() > 1 {
+            if let Some(change) = self.changes.pop_front() {
+                self.bytes -= change.command.len();
+            }
+        }
+    }
+
+    /// Get the first changes after a given position,
+    /// or None if the position is not in the backlog.
+    fn changes_after(&self, seq: u64) -> Option<Vec<Change>> {
+        let first = self.changes.front().map_or(self.seq + 1, |change| change.seq);
+        if seq + 1 < first || seq > self.seq {
+            return None;
+        }
+
+        let skip = (seq + 1 - first) as usize;
+        Some(self.changes.iter().skip(skip).take(CHUNK_LEN).cloned().collect())
+    }
+}
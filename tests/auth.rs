@@ -0,0 +1,128 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::KilledOnDrop;
+use kvs::{Credentials, ErrorKind, KvsClient, Result, DEFAULT_USER};
+use predicates::str::contains;
+use std::fs;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const CONFIG: &str = r#"{"auth": {"password": "secret", "tokens": {"alice": "alice-token"}}}"#;
+
+fn spawn_server(dir: &TempDir, addr: &str, config: &str) -> KilledOnDrop {
+    fs::write(dir.path().join("config.json"), config).unwrap();
+    common::spawn_server(dir, &["--addr", addr, "--config", "config.json"])
+}
+
+fn is_unauthorized(err: &kvs::Error) -> bool {
+    matches!(err.kind(), ErrorKind::Unauthorized(_))
+}
+
+fn acl_config(bob: &str) -> String {
+    format!(
+        r#"{{"auth": {{"password": "secret", "tokens": {{"alice": "alice-token", "bob": "bob-token"}},
+            "acl": {{"alice": [{{"prefix": "team-a/", "permission": "write"}}],
+                     "bob": [{{"prefix": "team-a/", "permission": "{}"}}]}}}}}}"#,
+        bob
+    )
+}
+
+fn login(addr: &str, credentials: Credentials) -> Result<KvsClient> {
+    let mut client = KvsClient::connect(addr)?;
+    client.authenticate(credentials)?;
+    Ok(client)
+}
+
+// Should refuse requests until the client authenticates with the password or a token
+#[test]
+fn client_must_authenticate() -> Result<()> {
+    let addr = "127.0.0.1:4042";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, addr, CONFIG);
+
+    let mut client = KvsClient::connect(addr)?;
+    assert!(is_unauthorized(&client.get("key".to_owned()).unwrap_err()));
+    assert!(is_unauthorized(&client.authenticate(Credentials::Password("wrong".to_owned())).unwrap_err()));
+
+    assert_eq!(client.authenticate(Credentials::Password("secret".to_owned()))?, DEFAULT_USER);
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    let mut client = KvsClient::connect(addr)?;
+    assert_eq!(client.authenticate(Credentials::Token("alice-token".to_owned()))?, "alice");
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    // A client which keeps failing is refused even with the right password for a while.
+    let mut client = KvsClient::connect(addr)?;
+    for _ in 0..5 {
+        assert!(is_unauthorized(&client.authenticate(Credentials::Token("guess".to_owned())).unwrap_err()));
+    }
+    let err = client.authenticate(Credentials::Password("secret".to_owned())).unwrap_err();
+    assert!(format!("{}", err).contains("too many failed attempts"));
+
+    Ok(())
+}
+
+// Should authenticate `kvs-client` with --password or the KVS_TOKEN environment variable
+#[test]
+fn cli_authenticates() {
+    let addr = "127.0.0.1:4043";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, addr, CONFIG);
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key", "value", "--addr", addr])
+        .assert()
+        .failure()
+        .stderr(contains("authentication required"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key", "value", "--addr", addr, "--password", "secret"])
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key", "--addr", addr])
+        .env("KVS_TOKEN", "alice-token")
+        .assert()
+        .success()
+        .stdout("value\n");
+}
+
+// Should only let users do what the rules of the access control list grant them, as reloaded
+#[test]
+fn acl_restricts_users_by_prefix() -> Result<()> {
+    let addr = "127.0.0.1:4044";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, addr, &acl_config("read"));
+
+    let mut alice = login(addr, Credentials::Token("alice-token".to_owned()))?;
+    alice.set("team-a/key".to_owned(), "value".to_owned())?;
+    assert!(is_unauthorized(&alice.set("team-b/key".to_owned(), "value".to_owned()).unwrap_err()));
+    assert!(is_unauthorized(&alice.scan(String::new()).unwrap_err()));
+    assert!(is_unauthorized(&alice.cluster_info().unwrap_err()));
+    assert!(is_unauthorized(&alice.compact().unwrap_err()));
+
+    let mut bob = login(addr, Credentials::Token("bob-token".to_owned()))?;
+    assert_eq!(bob.get("team-a/key".to_owned())?, Some("value".to_owned()));
+    assert_eq!(bob.scan("team-a/".to_owned())?, vec!["team-a/key".to_owned()]);
+    assert!(is_unauthorized(&bob.remove("team-a/key".to_owned()).unwrap_err()));
+
+    // The shared password is never restricted.
+    let mut admin = login(addr, Credentials::Password("secret".to_owned()))?;
+    admin.set("team-b/key".to_owned(), "value".to_owned())?;
+    admin.compact()?;
+
+    // The rules apply to connections already authenticated once the config file is reloaded.
+    fs::write(dir.path().join("config.json"), acl_config("write")).unwrap();
+    thread::sleep(Duration::from_secs(3));
+    bob.remove("team-a/key".to_owned())?;
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use assert_cmd::prelude::*;
+use assert_cmd::stdin::CommandStdInExt;
 use predicates::str::{contains, is_empty};
 use std::fs::{self, File};
 use std::process::Command;
@@ -7,7 +8,7 @@ use std::thread;
 use std::time::Duration;
 use tempfile::TempDir;
 
-// `kvs-client` with no args should exit with a non-zero code.
+// `kvs-client` with no args opens a session, and should exit with a non-zero code without a server.
 #[test]
 fn client_cli_no_args() {
     let temp_dir = TempDir::new().unwrap();
@@ -291,6 +292,30 @@ fn cli_access_server(engine: &str, addr: &str) {
         .success()
         .stdout(is_empty());
 
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key3", "value4", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key2", "key1", "key3", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value3\nKey not found\nvalue4\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["rm", "key3", "key1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr("Key not found: key1\n");
+
     sender.send(()).unwrap();
     handle.join().unwrap();
 
@@ -335,3 +360,223 @@ fn cli_access_server_kvs_engine() {
 fn cli_access_server_sled_engine() {
     cli_access_server("sled", "127.0.0.1:4005");
 }
+
+#[test]
+fn cli_repl() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4017"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let output = Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["repl", "--addr", "127.0.0.1:4017"])
+        .env("HOME", temp_dir.path())
+        .with_stdin()
+        .buffer("set key1 \"value 1\"\nset key2 value2\nget key1 key3\nscan key\nrm key2\nscan\nunknown\nquit\nping\n")
+        .output()
+        .unwrap();
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let results = stdout
+        .lines()
+        .filter(|line| !line.starts_with('('))
+        .collect::<Vec<&str>>();
+    assert_eq!(results, ["OK", "OK", "value 1", "Key not found", "key1", "key2", "OK", "key1"]);
+    assert!(stdout.contains("(error) unknown command 'unknown'"));
+    assert!(!stdout.contains("PONG"));
+}
+
+#[test]
+fn cli_batch() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4018"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let csv = temp_dir.path().join("pairs.csv");
+    fs::write(&csv, "key1,value1\n\"key,2\",\"say \"\"hi\"\"\"\n").unwrap();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["batch", "--addr", "127.0.0.1:4018", "--file"])
+        .arg(&csv)
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stderr("2 succeeded, 0 failed\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["batch", "--addr", "127.0.0.1:4018", "--format", "jsonl"])
+        .with_stdin()
+        .buffer("{\"key\": \"key3\", \"value\": \"value3\"}\n")
+        .assert()
+        .success()
+        .stderr("1 succeeded, 0 failed\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["batch", "--addr", "127.0.0.1:4018"])
+        .with_stdin()
+        .buffer("# comment\nget key1 \"key,2\" key3\nrm key4\nunknown\nget key4\n")
+        .assert()
+        .failure()
+        .stdout("value1\nsay \"hi\"\nvalue3\nKey not found\n")
+        .stderr("line 3: Key not found\nline 4: unknown command 'unknown'\n4 succeeded, 2 failed\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["batch", "--addr", "127.0.0.1:4018", "--stop-on-error"])
+        .with_stdin()
+        .buffer("set key5 value5\nset key6\nset key7 value7\n")
+        .assert()
+        .failure()
+        .stderr("line 2: wrong number of arguments for 'set'\n1 succeeded, 1 failed\n");
+
+    // A request failing on the server stops the batch before the following requests are sent.
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["batch", "--addr", "127.0.0.1:4018", "--stop-on-error"])
+        .with_stdin()
+        .buffer("rm missing\nset key8 value8\n")
+        .assert()
+        .failure()
+        .stderr("line 1: Key not found\n0 succeeded, 1 failed\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key5", "key7", "key8", "--addr", "127.0.0.1:4018"])
+        .assert()
+        .success()
+        .stdout("value5\nKey not found\nKey not found\n");
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+#[test]
+fn cli_output_formats() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4019"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4019", "--output", "json"])
+        .assert()
+        .success()
+        .stdout("{\"key\":\"key1\",\"value\":\"value1\",\"exists\":true,\"error\":null}\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "key2", "--addr", "127.0.0.1:4019", "-o", "json"])
+        .assert()
+        .success()
+        .stdout(
+            "{\"key\":\"key1\",\"value\":\"value1\",\"exists\":true,\"error\":null}\n\
+             {\"key\":\"key2\",\"value\":null,\"exists\":false,\"error\":null}\n",
+        );
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "key2", "--fail-missing", "--addr", "127.0.0.1:4019", "-o", "json"])
+        .assert()
+        .code(4)
+        .stdout(
+            "{\"key\":\"key1\",\"value\":\"value1\",\"exists\":true,\"error\":null}\n\
+             {\"key\":\"key2\",\"value\":null,\"exists\":false,\
+             \"error\":{\"code\":\"NotFound\",\"message\":\"Key not found\"}}\n",
+        );
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--fail-missing", "--addr", "127.0.0.1:4019"])
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    // A usage error exits with 1, which no failure of a command shares.
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--no-such-flag", "--addr", "127.0.0.1:4019"])
+        .assert()
+        .code(1);
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "key2", "--addr", "127.0.0.1:4019", "-o", "json"])
+        .assert()
+        .code(4)
+        .stdout(
+            "{\"key\":\"key2\",\"value\":null,\"exists\":false,\
+             \"error\":{\"code\":\"NotFound\",\"message\":\"Key not found\"}}\n",
+        );
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "key1", "key2", "--addr", "127.0.0.1:4019", "-o", "table"])
+        .assert()
+        .code(4)
+        .stdout(
+            "KEY   VALUE  EXISTS  ERROR\n\
+             key1         true\n\
+             key2         false   NotFound: Key not found\n",
+        );
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", "127.0.0.1:4020"])
+        .assert()
+        .code(3)
+        .stdout(is_empty());
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+#[test]
+fn cli_bench() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4021"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4021", "-c", "2", "-n", "200", "-k", "50"])
+        .args(["--value-size", "1-64", "--distribution", "zipfian", "--preload"])
+        .assert()
+        .success()
+        .stdout(contains("preloaded 50 keys"))
+        .stdout(contains("200 requests over 2 connections"))
+        .stdout(contains("TOTAL: 200 requests, 0 errors"));
+
+    Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4021", "--read-ratio", "1.5"])
+        .assert()
+        .failure();
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
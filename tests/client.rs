@@ -0,0 +1,275 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::spawn_server;
+use kvs::thread_pool::{NaiveThreadPool, ThreadPool};
+use kvs::{
+    ErrorCode, ErrorKind, KvStore, KvsClient, KvsClientPool, KvsServer, PoolConfig, Response, ResponseFrame,
+    Result, Timeouts,
+};
+use predicates::prelude::*;
+use predicates::str::contains;
+use slog::{o, Discard, Logger};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn start_server(addr: &'static str) -> TempDir {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path()).unwrap();
+    thread::spawn(move || {
+        let mut server = KvsServer::new(store, NaiveThreadPool::new(4).unwrap());
+        server.run(addr, Logger::root(Discard, o!())).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+    temp_dir
+}
+
+// Should get the responses of pipelined requests in order
+#[test]
+fn pipeline_requests() -> Result<()> {
+    let _dir = start_server("127.0.0.1:4012");
+    let mut client = KvsClient::connect("127.0.0.1:4012")?;
+
+    let mut pipeline = client.pipeline();
+    for i in 0..5000 {
+        pipeline.set(format!("key{}", i), format!("value{}", i));
+    }
+    pipeline.get("key42".to_owned()).remove("key43".to_owned());
+    pipeline.remove("key43".to_owned()).get("key43".to_owned());
+    assert_eq!(pipeline.len(), 5004);
+
+    let responses = pipeline.execute()?;
+    assert_eq!(responses.len(), 5004);
+    assert!(responses[..5000]
+        .iter()
+        .all(|response| matches!(response, Response::Set(Ok(())))));
+    assert!(matches!(&responses[5000], Response::Get(Ok(Some(value))) if value == "value42"));
+    assert!(matches!(&responses[5001], Response::Remove(Ok(()))));
+    assert!(matches!(&responses[5002], Response::Remove(Err(_))));
+    assert!(matches!(&responses[5003], Response::Get(Ok(None))));
+    assert!(pipeline.is_empty());
+
+    // The connection is still usable for single requests.
+    assert_eq!(client.get("key4999".to_owned())?, Some("value4999".to_owned()));
+    assert_eq!(client.pipeline().execute()?.len(), 0);
+
+    Ok(())
+}
+
+// Should tell errors apart by their codes instead of their messages
+#[test]
+fn typed_errors() -> Result<()> {
+    let _dir = start_server("127.0.0.1:4013");
+    let mut client = KvsClient::connect("127.0.0.1:4013")?;
+
+    let err = client.remove("key1".to_owned()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::KeyNotFound));
+
+    let responses = client.pipeline().remove("key1".to_owned()).execute()?;
+    assert!(matches!(&responses[0], Response::Remove(Err(err)) if err.code == ErrorCode::NotFound));
+
+    // A request which can not be parsed is replied to with the id it carries.
+    let mut stream = TcpStream::connect("127.0.0.1:4013")?;
+    stream.write_all(b"{\"id\":7,\"request\":{\"Unknown\":{}}}\n")?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let frame: ResponseFrame = serde_json::from_str(&line)?;
+    assert_eq!(frame.id, 7);
+    assert!(matches!(frame.response, Response::Error(err) if err.code == ErrorCode::InvalidRequest));
+
+    Ok(())
+}
+
+// Should share connections between threads, and reconnect after the server restarts
+#[test]
+fn pool_reconnects() -> Result<()> {
+    let addr = "127.0.0.1:4014";
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = spawn_server(&temp_dir, &["--addr", addr]);
+
+    let config = PoolConfig {
+        size: 4,
+        ..PoolConfig::default()
+    };
+    let pool = KvsClientPool::connect(addr, config)?;
+    let handles = (0..8)
+        .map(|i| {
+            let pool = pool.clone();
+            thread::spawn(move || pool.set(format!("key{}", i), format!("value{}", i)))
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+    assert_eq!(pool.open_connections(), 4);
+
+    drop(server);
+    let server = spawn_server(&temp_dir, &["--addr", addr]);
+
+    // Every idle connection is broken now, yet requests succeed on new connections.
+    for i in 0..8 {
+        assert_eq!(pool.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    pool.set("key8".to_owned(), "value8".to_owned())?;
+    pool.ping()?;
+
+    drop(server);
+    assert!(pool.get("key8".to_owned()).is_err());
+
+    Ok(())
+}
+
+// Should time out on a server which never replies, and close idle connections on the server
+#[test]
+fn timeouts() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:4015")?;
+    let timeouts = Timeouts {
+        read: Some(Duration::from_millis(200)),
+        ..Timeouts::default()
+    };
+    let mut client = KvsClient::connect_with("127.0.0.1:4015", timeouts)?;
+    let _silent = listener.accept()?;
+    let err = client.get("key1".to_owned()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Timeout(_)));
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    thread::spawn(move || {
+        let server = KvsServer::new(store, NaiveThreadPool::new(4).unwrap());
+        let mut server = server.idle_timeout(Duration::from_millis(200));
+        server.run("127.0.0.1:4016", Logger::root(Discard, o!())).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let mut client = KvsClient::connect("127.0.0.1:4016")?;
+    client.ping()?;
+    let mut stream = TcpStream::connect("127.0.0.1:4016")?;
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(stream.read(&mut [0u8; 16])?, 0);
+    assert!(client.ping().is_err());
+
+    // A zero idle timeout never closes connections.
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    thread::spawn(move || {
+        let server = KvsServer::new(store, NaiveThreadPool::new(4).unwrap());
+        let mut server = server.idle_timeout(Duration::ZERO);
+        server.run("127.0.0.1:4057", Logger::root(Discard, o!())).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let mut client = KvsClient::connect("127.0.0.1:4057")?;
+    client.ping()?;
+    thread::sleep(Duration::from_millis(300));
+    client.ping()?;
+    KvsClient::connect("127.0.0.1:4057")?.ping()?;
+
+    Ok(())
+}
+
+// Should report the engine, the keys, the connected clients and the requests replied by type
+#[test]
+fn server_info() -> Result<()> {
+    let _dir = start_server("127.0.0.1:4049");
+    let mut client = KvsClient::connect("127.0.0.1:4049")?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    client.set("key2".to_owned(), "value2".to_owned())?;
+    client.get("key1".to_owned())?;
+
+    let info = client.info()?;
+    assert_eq!(info.engine, "kvs");
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.keys, 2);
+    assert!(info.disk_bytes > 0);
+    assert_eq!(info.generation, 1);
+    assert_eq!(info.connected_clients, 1);
+    assert_eq!(info.commands.get("Set"), Some(&2));
+    assert_eq!(info.commands.get("Get"), Some(&1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["info", "--addr", "127.0.0.1:4049"])
+        .assert()
+        .success()
+        .stdout(contains("engine: kvs\n").and(contains("  Set: 2\n")));
+
+    Ok(())
+}
+
+// Should compact the store on request, reclaiming the space of overwritten and removed values
+#[test]
+fn compact_on_request() -> Result<()> {
+    let _dir = start_server("127.0.0.1:4050");
+    let mut client = KvsClient::connect("127.0.0.1:4050")?;
+    for i in 0..1000 {
+        client.set(format!("key{}", i % 10), format!("value{}", i))?;
+    }
+    client.remove("key0".to_owned())?;
+
+    let compaction = client.compact()?;
+    assert!(compaction.reclaimed_bytes > 0);
+    let info = client.info()?;
+    assert_eq!(info.uncompacted_bytes, 0);
+    assert_eq!(info.keys, 9);
+    assert_eq!(client.get("key1".to_owned())?, Some("value991".to_owned()));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["admin", "compact", "--addr", "127.0.0.1:4050"])
+        .assert()
+        .success()
+        .stdout(contains("reclaimed 0 B in "));
+
+    Ok(())
+}
+
+// Should keep the latest requests above the threshold of the slow log, without their values
+#[test]
+fn slowlog_keeps_latest_slow_requests() -> Result<()> {
+    let addr = "127.0.0.1:4051";
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    thread::spawn(move || {
+        let server = KvsServer::new(store, NaiveThreadPool::new(4).unwrap());
+        let mut server = server.slowlog(Duration::from_secs(0), 2);
+        server.run(addr, Logger::root(Discard, o!())).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "secret".to_owned())?;
+    client.set("key2".to_owned(), "secret".to_owned())?;
+    client.get_many(vec!["key1".to_owned(), "key2".to_owned()])?;
+
+    let entries = client.slowlog(true)?;
+    let requests = entries.iter().map(|entry| entry.request.as_str()).collect::<Vec<_>>();
+    assert_eq!(requests, ["GetMany key1 key2", "Set key2"]);
+    assert!(entries[0].id > entries[1].id);
+    assert!(entries[0].client.starts_with("127.0.0.1:"));
+
+    // The slow log was cleared, and only the request which cleared it was kept since.
+    let entries = client.slowlog(false)?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].request, "SlowLog");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["slowlog", "--addr", addr])
+        .assert()
+        .success()
+        .stdout(contains(" SlowLog\n"));
+
+    // Summaries list the first keys, cut to 64 chars, and count the others.
+    let long = "k".repeat(70);
+    let mut keys = vec![long.clone()];
+    keys.extend((1..7).map(|index| format!("key{}", index)));
+    client.get_many(keys)?;
+    let entries = client.slowlog(false)?;
+    assert_eq!(entries[0].request, format!("GetMany {}... key1 key2 key3 key4 (+2 more)", &long[..64]));
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Helpers shared by the tests which spawn `kvs-server`.
+
+// Each test crate uses only some of the helpers.
+#![allow(dead_code)]
+
+use assert_cmd::prelude::*;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A spawned process, which is killed when dropped.
+pub struct KilledOnDrop(pub Child);
+
+impl Drop for KilledOnDrop {
+    fn drop(&mut self) {
+        self.0.kill().expect("process exited before killed");
+        self.0.wait().unwrap();
+    }
+}
+
+/// Spawn `kvs-server` with the given arguments in a directory, and wait until each address it is
+/// given with `--addr`, `--unix`, `--http-addr` or `--metrics-addr` accepts connections.
+pub fn spawn_server<P: AsRef<Path>>(dir: P, args: &[&str]) -> KilledOnDrop {
+    let child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(args)
+        .current_dir(dir.as_ref())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut server = KilledOnDrop(child);
+    for pair in args.windows(2) {
+        match pair[0] {
+            "--addr" | "--http-addr" | "--metrics-addr" => {
+                wait_until_accepting(&mut server, pair[1], || TcpStream::connect(pair[1]).is_ok())
+            }
+            "--unix" => {
+                let socket = dir.as_ref().join(pair[1]);
+                wait_until_accepting(&mut server, pair[1], || UnixStream::connect(&socket).is_ok())
+            }
+            _ => {}
+        }
+    }
+    server
+}
+
+/// Poll an address of a spawned server until a connection to it succeeds.
+fn wait_until_accepting(server: &mut KilledOnDrop, addr: &str, connects: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !connects() {
+        if let Some(status) = server.0.try_wait().unwrap() {
+            panic!("server exited with {} before accepting connections on {}", status, addr);
+        }
+        assert!(Instant::now() < deadline, "server did not accept connections on {}", addr);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,147 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn request(addr: &str, method: &str, path: &str, headers: &[&str], body: impl AsRef<[u8]>) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut content = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, path, addr);
+    for header in headers {
+        content.push_str(&format!("{}\r\n", header));
+    }
+    let body = body.as_ref();
+    content.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+    stream.write_all(content.as_bytes()).unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+fn http_access_server(engine: &str, addr: &str, http_addr: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", engine, "--addr", addr, "--http-addr", http_addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    assert_eq!(request(http_addr, "GET", "/health", &[], ""), (200, r#"{"status":"ok"}"#.to_string()));
+    assert_eq!(request(http_addr, "PUT", "/keys/key1", &["Content-Type: text/plain"], "value1").0, 204);
+    assert_eq!(request(http_addr, "PUT", "/keys/key%202", &[], "value 2").0, 204);
+    assert_eq!(request(http_addr, "PUT", "/keys/other", &[], "value3").0, 204);
+    assert_eq!(request(http_addr, "GET", "/keys/key1", &[], ""), (200, "value1".to_string()));
+    assert_eq!(request(http_addr, "GET", "/keys/key%202", &[], ""), (200, "value 2".to_string()));
+    assert_eq!(request(http_addr, "GET", "/keys/key3", &[], "").0, 404);
+    assert_eq!(
+        request(http_addr, "GET", "/keys?prefix=key", &[], ""),
+        (200, r#"["key 2","key1"]"#.to_string())
+    );
+    assert_eq!(
+        request(http_addr, "PUT", "/keys/json", &["Content-Type: application/json"], r#"{"a":1}"#).0,
+        204
+    );
+    assert_eq!(
+        request(http_addr, "GET", "/keys/json", &["Accept: application/octet-stream"], ""),
+        (200, r#"{"a":1}"#.to_string())
+    );
+    assert_eq!(request(http_addr, "DELETE", "/keys/json", &[], "").0, 204);
+    assert_eq!(request(http_addr, "PUT", "/keys/key1", &["Content-Type: image/png"], "").0, 415);
+    // Values are strings, so binary values are refused rather than stored mangled.
+    assert_eq!(
+        request(http_addr, "PUT", "/keys/binary", &["Content-Type: application/octet-stream"], [0xff, 0x00, 0xfe]).0,
+        422
+    );
+    assert_eq!(request(http_addr, "GET", "/keys/binary", &[], "").0, 404);
+    assert_eq!(request(http_addr, "DELETE", "/keys/key1", &[], "").0, 204);
+    assert_eq!(request(http_addr, "DELETE", "/keys/key1", &[], "").0, 404);
+    assert_eq!(request(http_addr, "POST", "/keys/key1", &[], "").0, 405);
+    assert_eq!(request(http_addr, "GET", "/unknown", &[], "").0, 404);
+    assert!(request(http_addr, "GET", "/stats", &[], "").1.contains(r#""keys":2"#));
+
+    // The gateway works on the same engine as the kvs protocol.
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "other", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value3\n");
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+#[test]
+fn http_access_server_kvs_engine() {
+    http_access_server("kvs", "127.0.0.1:4008", "127.0.0.1:4009");
+}
+
+#[test]
+fn http_access_server_sled_engine() {
+    http_access_server("sled", "127.0.0.1:4010", "127.0.0.1:4011");
+}
+
+// Should refuse a request without a token before reading the body it announces
+#[test]
+fn http_refuses_before_reading_body() {
+    let (addr, http_addr) = ("127.0.0.1:4055", "127.0.0.1:4056");
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("config.json"), r#"{"auth": {"tokens": {"alice": "alice-token"}}}"#).unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", addr, "--http-addr", http_addr, "--config", "config.json"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    // The body is never sent, so the reply only comes if the server does not wait for it.
+    let mut stream = TcpStream::connect(http_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "PUT /keys/key HTTP/1.1\r\nHost: {}\r\nContent-Length: 60000000\r\n\r\n", http_addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"));
+
+    let token = ["Authorization: Bearer alice-token"];
+    assert_eq!(request(http_addr, "PUT", "/keys/key", &token, "value").0, 204);
+    assert_eq!(request(http_addr, "GET", "/keys/key", &token, ""), (200, "value".to_string()));
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+// Should take the shared password as the bearer, for servers with no tokens
+#[test]
+fn http_authenticates_with_password() {
+    let (addr, http_addr) = ("127.0.0.1:4061", "127.0.0.1:4062");
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("config.json"), r#"{"auth": {"password": "secret"}}"#).unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", addr, "--http-addr", http_addr, "--config", "config.json"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let password = ["Authorization: Bearer secret"];
+    assert_eq!(request(http_addr, "PUT", "/keys/key", &password, "value").0, 204);
+    assert_eq!(request(http_addr, "GET", "/keys/key", &password, ""), (200, "value".to_string()));
+    assert_eq!(request(http_addr, "GET", "/keys/key", &["Authorization: Bearer wrong"], "").0, 401);
+    assert_eq!(request(http_addr, "GET", "/keys/key", &[], "").0, 401);
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
@@ -81,6 +81,38 @@ fn remove_key() -> Result<()> {
     Ok(())
 }
 
+// Should set, get and remove many keys at once, and tell which keys existed
+#[test]
+fn many_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set_many(vec![
+        ("key1".to_owned(), "value1".to_owned()),
+        ("key2".to_owned(), "value2".to_owned()),
+        ("key1".to_owned(), "value3".to_owned()),
+    ])?;
+    assert_eq!(
+        store.get_many(vec!["key1".to_owned(), "key3".to_owned(), "key2".to_owned()])?,
+        vec![Some("value3".to_owned()), None, Some("value2".to_owned())]
+    );
+
+    assert_eq!(
+        store.remove_many(vec!["key2".to_owned(), "key3".to_owned(), "key2".to_owned()])?,
+        vec![true, false, false]
+    );
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(
+        store.get_many(vec!["key1".to_owned(), "key2".to_owned()])?,
+        vec![Some("value3".to_owned()), None]
+    );
+
+    Ok(())
+}
+
 // Insert data until total size of the directory decreases.
 // Test data correctness after compaction.
 #[test]
@@ -0,0 +1,67 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::spawn_server;
+use kvs::{KvsClient, RequestLogState, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn read_log(path: &Path) -> Vec<Value> {
+    // Lines are written from a thread of the logger.
+    thread::sleep(Duration::from_millis(300));
+    let log = fs::read_to_string(path).unwrap();
+    log.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+fn requests(log: &[Value]) -> Vec<&str> {
+    log.iter()
+        .filter(|line| line["msg"] == "request came")
+        .map(|line| line["request"].as_str().unwrap())
+        .collect()
+}
+
+// Should log JSON lines without values, and log requests at info only while the request log is on
+#[test]
+fn request_log_switched_at_runtime() -> Result<()> {
+    let addr = "127.0.0.1:4052";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = dir.path().join("kvs.log");
+    let _server = spawn_server(&dir, &["--addr", addr, "--log-format", "json", "--log-file", "kvs.log"]);
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("before".to_owned(), "secret".to_owned())?;
+    assert!(requests(&read_log(&path)).is_empty());
+
+    let state = client.request_log(true, Some("127.0.0.1"))?;
+    assert_eq!(
+        state,
+        RequestLogState {
+            all: false,
+            clients: vec!["127.0.0.1".to_owned()],
+        }
+    );
+    client.set("key".to_owned(), "secret".to_owned())?;
+    client.get("key".to_owned())?;
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["admin", "request-log", "off", "--addr", addr])
+        .assert()
+        .success()
+        .stdout("logging no connection\n");
+    client.set("after".to_owned(), "secret".to_owned())?;
+
+    let log = read_log(&path);
+    // The request switching the log off is logged, as it was on when the request came.
+    assert_eq!(requests(&log), ["Set key", "Get key", "RequestLog"]);
+    assert!(log.iter().all(|line| line["level"] == "INFO"));
+    assert!(log.iter().any(|line| line["response"] == "Get(Ok(Some(<6 bytes>)))"));
+    assert!(!fs::read_to_string(&path)?.contains("secret"));
+
+    Ok(())
+}
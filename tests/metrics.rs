@@ -0,0 +1,111 @@
+mod common;
+
+use common::spawn_server;
+use kvs::{KvsClient, Result};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn scrape(addr: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET /metrics HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    response.split_once("\r\n\r\n").unwrap().1.to_string()
+}
+
+fn exchange(addr: &str, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+// Should export the requests, the errors, the connections and the store in the Prometheus format
+#[test]
+fn server_exports_metrics() -> Result<()> {
+    let (addr, metrics_addr) = ("127.0.0.1:4047", "127.0.0.1:4048");
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, &["--addr", addr, "--metrics-addr", metrics_addr]);
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value".to_owned())?;
+    client.set("key1".to_owned(), "value".to_owned())?;
+    client.set("key2".to_owned(), "value".to_owned())?;
+    client.get("key1".to_owned())?;
+    assert!(client.remove("missing".to_owned()).is_err());
+
+    let metrics = scrape(metrics_addr);
+    let lines = metrics.lines().collect::<Vec<_>>();
+    for line in [
+        "# TYPE kvs_request_duration_seconds histogram",
+        "kvs_requests_total{request=\"Set\"} 3",
+        "kvs_requests_total{request=\"Get\"} 1",
+        "kvs_request_duration_seconds_bucket{request=\"Set\",le=\"+Inf\"} 3",
+        "kvs_request_duration_seconds_count{request=\"Remove\"} 1",
+        "kvs_errors_total{code=\"NotFound\"} 1",
+        "kvs_open_connections 1",
+        "kvs_thread_pool_queued_jobs 0",
+        "kvs_store_keys 2",
+        "kvs_store_generations 1",
+        "kvs_store_compactions_total 0",
+    ] {
+        assert!(lines.contains(&line), "{} is missing from\n{}", line, metrics);
+    }
+    assert!(lines.iter().any(|line| line.starts_with("kvs_store_uncompacted_bytes ") && !line.ends_with(" 0")));
+
+    drop(client);
+    thread::sleep(Duration::from_millis(200));
+    assert!(scrape(metrics_addr).lines().any(|line| line == "kvs_open_connections 0"));
+
+    Ok(())
+}
+
+// Should count the requests of redis clients and of the HTTP gateway as the requests doing the same
+#[test]
+fn server_exports_metrics_of_resp_and_http() -> Result<()> {
+    let (addr, http_addr, metrics_addr) = ("127.0.0.1:4058", "127.0.0.1:4059", "127.0.0.1:4060");
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(
+        &dir,
+        &["--addr", addr, "--protocol", "resp", "--http-addr", http_addr, "--metrics-addr", metrics_addr],
+    );
+
+    let replies = exchange(
+        addr,
+        "*3\r\n$3\r\nSET\r\n$4\r\nkey1\r\n$5\r\nvalue\r\n\
+         *2\r\n$3\r\nGET\r\n$4\r\nkey1\r\n\
+         *1\r\n$7\r\nUNKNOWN\r\n\
+         *1\r\n$4\r\nQUIT\r\n",
+    );
+    assert_eq!(replies, "+OK\r\n$5\r\nvalue\r\n-ERR unknown command 'unknown'\r\n+OK\r\n");
+
+    let put = exchange(
+        http_addr,
+        "PUT /keys/key2 HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nvalue",
+    );
+    assert!(put.starts_with("HTTP/1.1 204"), "{}", put);
+    let get = exchange(http_addr, "GET /keys/key2 HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(get.starts_with("HTTP/1.1 200"), "{}", get);
+    let missing = exchange(http_addr, "GET /keys/missing HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(missing.starts_with("HTTP/1.1 404"), "{}", missing);
+
+    let metrics = scrape(metrics_addr);
+    let lines = metrics.lines().collect::<Vec<_>>();
+    for line in [
+        "kvs_requests_total{request=\"Set\"} 2",
+        "kvs_requests_total{request=\"Get\"} 3",
+        "kvs_errors_total{code=\"InvalidRequest\"} 1",
+        "kvs_errors_total{code=\"NotFound\"} 1",
+        "kvs_store_keys 2",
+    ] {
+        assert!(lines.contains(&line), "{} is missing from\n{}", line, metrics);
+    }
+    assert!(!metrics.contains("request=\"UNKNOWN\""));
+
+    Ok(())
+}
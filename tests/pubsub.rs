@@ -0,0 +1,97 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{spawn_server, KilledOnDrop};
+use kvs::{KvsClient, Message, Result, Subscription, Timeouts};
+use predicates::str::contains;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn subscribe(addr: &str, channels: &[&str], patterns: &[&str]) -> Result<Subscription> {
+    let strings = |values: &[&str]| values.iter().map(ToString::to_string).collect();
+    KvsClient::connect_with(addr, Timeouts::all(Duration::from_secs(5)))?.subscribe(strings(channels), strings(patterns))
+}
+
+fn message(channel: &str, pattern: Option<&str>, message: &str) -> Message {
+    Message {
+        channel: channel.to_owned(),
+        pattern: pattern.map(ToString::to_string),
+        message: message.to_owned(),
+    }
+}
+
+// Should fan out published messages to the subscribers of the channel and of matching patterns
+#[test]
+fn publish_fans_out_to_subscribers() -> Result<()> {
+    let addr = "127.0.0.1:4040";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, &["--addr", addr]);
+
+    let mut news = subscribe(addr, &["news"], &[])?;
+    let mut users = subscribe(addr, &["user.1", "user.1"], &["user.*", "user.*"])?;
+    let mut client = KvsClient::connect(addr)?;
+
+    assert_eq!(client.publish("news".to_owned(), "hello".to_owned())?, 1);
+    assert_eq!(client.publish("user.1".to_owned(), "alice".to_owned())?, 1);
+    assert_eq!(client.publish("user.2".to_owned(), "bob".to_owned())?, 1);
+    assert_eq!(client.publish("other".to_owned(), "nobody".to_owned())?, 0);
+    // Messages are not kept in the store.
+    assert_eq!(client.scan(String::new())?, Vec::<String>::new());
+
+    assert_eq!(news.next_message()?, message("news", None, "hello"));
+    // A message is delivered once for the channel and once for each matching pattern, however many
+    // times they were subscribed.
+    assert_eq!(users.next_message()?, message("user.1", None, "alice"));
+    assert_eq!(users.next_message()?, message("user.1", Some("user.*"), "alice"));
+    assert_eq!(users.next_message()?, message("user.2", Some("user.*"), "bob"));
+
+    // Subscribers which are gone are dropped once the heartbeats sent to them fail.
+    drop(news);
+    thread::sleep(Duration::from_secs(3));
+    assert_eq!(client.publish("news".to_owned(), "again".to_owned())?, 0);
+
+    // Patterns which would take long to match against every channel are refused.
+    assert!(subscribe(addr, &[], &["*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b"]).is_err());
+    assert!(subscribe(addr, &[], &[&"a".repeat(300)]).is_err());
+    assert_eq!(client.publish("user.3".to_owned(), "carol".to_owned())?, 1);
+
+    Ok(())
+}
+
+// Should print the messages of the subscribed channels with `kvs-client subscribe`
+#[test]
+fn cli_publish_subscribe() {
+    let addr = "127.0.0.1:4041";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, &["--addr", addr]);
+
+    let mut subscriber = KilledOnDrop(
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["subscribe", "news", "--pattern", "user.*", "--addr", addr])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    thread::sleep(Duration::from_secs(1));
+
+    for (channel, message, receivers) in [("news", "hello", "1"), ("other", "nobody", "0"), ("user.1", "hi alice", "1")] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["publish", channel, message, "--addr", addr])
+            .assert()
+            .success()
+            .stdout(contains(receivers));
+    }
+
+    let lines = BufReader::new(subscriber.0.stdout.take().unwrap())
+        .lines()
+        .take(2)
+        .collect::<std::io::Result<Vec<String>>>()
+        .unwrap();
+    drop(subscriber);
+    assert_eq!(lines, vec!["news hello", "user.1 hi alice"]);
+}
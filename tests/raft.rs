@@ -0,0 +1,180 @@
+mod common;
+
+use common::{spawn_server, KilledOnDrop};
+use kvs::{ClusterInfo, KvStore, KvsClient, KvsEngine, RaftRole, Result, Timeouts};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn cluster_info(addr: &str) -> Result<ClusterInfo> {
+    KvsClient::connect_with(addr, Timeouts::all(Duration::from_secs(1)))?.cluster_info()
+}
+
+/// Poll the nodes until one of them is the leader, and get its address.
+fn wait_for_leader(addrs: &[&str]) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        for addr in addrs {
+            if let Ok(info) = cluster_info(addr) {
+                if info.role == RaftRole::Leader {
+                    return info.id;
+                }
+            }
+        }
+        assert!(Instant::now() < deadline, "no leader was elected");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Poll a node until it has applied every entry committed by the leader.
+fn wait_for_catch_up(addr: &str, leader: &str) -> ClusterInfo {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let (Ok(info), Ok(leader)) = (cluster_info(addr), cluster_info(leader)) {
+            if info.last_applied >= leader.commit_index {
+                return info;
+            }
+        }
+        assert!(Instant::now() < deadline, "{} did not catch up with the leader", addr);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Should keep every acknowledged write while the leader is killed, and bring it up to date
+// once it restarts
+#[test]
+fn cluster_survives_leader_failure() -> Result<()> {
+    let addrs = ["127.0.0.1:4026", "127.0.0.1:4027", "127.0.0.1:4028"];
+    let members = addrs.join(",");
+    let dirs = (0..addrs.len())
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect::<Vec<TempDir>>();
+    let mut nodes = dirs
+        .iter()
+        .zip(&addrs)
+        .map(|(dir, addr)| Some(spawn_server(dir, &["--addr", addr, "--cluster", &members])))
+        .collect::<Vec<Option<KilledOnDrop>>>();
+    let leader = wait_for_leader(&addrs);
+
+    // Writes go to any node, and are retried on the next one until they are acknowledged.
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let mut acknowledged = Vec::new();
+            let mut node = 0;
+            let mut client = None;
+            while !stop.load(Ordering::SeqCst) || acknowledged.len() < 100 {
+                let index = acknowledged.len();
+                let connected = match client.take() {
+                    Some(connected) => Ok(connected),
+                    None => KvsClient::connect_with(addrs[node], Timeouts::all(Duration::from_secs(2))),
+                };
+                let written = connected.and_then(|mut connected| {
+                    connected.set(format!("key{}", index), format!("value{}", index))?;
+                    Ok(connected)
+                });
+                match written {
+                    Ok(connected) => {
+                        client = Some(connected);
+                        acknowledged.push(index);
+                    }
+                    Err(_) => {
+                        node = (node + 1) % addrs.len();
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                }
+            }
+            acknowledged.len()
+        })
+    };
+
+    thread::sleep(Duration::from_millis(500));
+    let killed = addrs.iter().position(|addr| **addr == leader).unwrap();
+    nodes[killed] = None;
+
+    let survivors = addrs
+        .iter()
+        .copied()
+        .filter(|addr| **addr != leader)
+        .collect::<Vec<&str>>();
+    let new_leader = wait_for_leader(&survivors);
+    assert_ne!(new_leader, leader);
+
+    thread::sleep(Duration::from_millis(500));
+    stop.store(true, Ordering::SeqCst);
+    let written = writer.join().unwrap();
+
+    let keys = (0..written).map(|index| format!("key{}", index)).collect::<Vec<String>>();
+    let expected = (0..written)
+        .map(|index| Some(format!("value{}", index)))
+        .collect::<Vec<Option<String>>>();
+    // Reads sent to a follower are redirected to the leader.
+    let follower = survivors.iter().find(|addr| **addr != new_leader).unwrap();
+    assert_eq!(KvsClient::connect(follower)?.get_many(keys.clone())?, expected);
+
+    nodes[killed] = Some(spawn_server(&dirs[killed], &["--addr", &leader, "--cluster", &members]));
+    let info = wait_for_catch_up(&leader, &new_leader);
+    assert_eq!(info.role, RaftRole::Follower);
+    assert_eq!(info.leader.as_deref(), Some(new_leader.as_str()));
+
+    // The engine of the restarted node has every write.
+    drop(nodes);
+    let store = KvStore::open(dirs[killed].path())?;
+    assert_eq!(store.get_many(keys)?, expected);
+
+    Ok(())
+}
+
+// Should add a node from a snapshot of the leader, and remove the leader itself
+#[test]
+fn cluster_changes_members() -> Result<()> {
+    let addrs = ["127.0.0.1:4029", "127.0.0.1:4030", "127.0.0.1:4031"];
+    let joining = "127.0.0.1:4032";
+    let members = addrs.join(",");
+    let dirs = (0..addrs.len() + 1)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect::<Vec<TempDir>>();
+    let snapshot_args = ["--snapshot-threshold", "10"];
+
+    let mut nodes = Vec::new();
+    for (dir, addr) in dirs.iter().zip(&addrs) {
+        nodes.push(spawn_server(dir, &["--addr", addr, "--cluster", &members, snapshot_args[0], snapshot_args[1]]));
+    }
+    nodes.push(spawn_server(&dirs[3], &["--addr", joining, "--join", snapshot_args[0], snapshot_args[1]]));
+    let leader = wait_for_leader(&addrs);
+
+    let mut client = KvsClient::connect(addrs[0])?;
+    for index in 0..30 {
+        client.set(format!("key{}", index), format!("value{}", index))?;
+    }
+    assert!(cluster_info(&leader)?.snapshot_index >= 10);
+
+    let added = client.add_member(joining.to_owned())?;
+    assert_eq!(added.len(), 4);
+    let info = wait_for_catch_up(joining, &leader);
+    assert_eq!(info.members, added);
+    assert!(info.snapshot_index >= 10);
+
+    // The leader steps down once its removal is committed.
+    let remaining = client.remove_member(leader.clone())?;
+    assert_eq!(remaining.len(), 3);
+    assert!(!remaining.contains(&leader));
+
+    let remaining = remaining.iter().map(String::as_str).collect::<Vec<&str>>();
+    let new_leader = wait_for_leader(&remaining);
+    let mut client = KvsClient::connect(joining)?;
+    client.set("key30".to_owned(), "value30".to_owned())?;
+    assert_eq!(client.get("key0".to_owned())?, Some("value0".to_owned()));
+    assert_eq!(cluster_info(&leader)?.role, RaftRole::Follower);
+
+    wait_for_catch_up(joining, &new_leader);
+    drop(nodes);
+    let store = KvStore::open(dirs[3].path())?;
+    assert_eq!(store.get("key0".to_owned())?, Some("value0".to_owned()));
+    assert_eq!(store.get("key30".to_owned())?, Some("value30".to_owned()));
+
+    Ok(())
+}
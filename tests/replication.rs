@@ -0,0 +1,107 @@
+mod common;
+
+use common::spawn_server;
+use kvs::{ErrorKind, KvsClient, Result, Role};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Poll the follower until it has a given value for each key.
+fn wait_for(addr: &str, expected: &[(&str, Option<&str>)]) -> Result<()> {
+    let keys = expected.iter().map(|(key, _)| key.to_string()).collect::<Vec<String>>();
+    let expected = expected
+        .iter()
+        .map(|(_, value)| value.map(ToString::to_string))
+        .collect::<Vec<Option<String>>>();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut client = KvsClient::connect(addr)?;
+    loop {
+        let values = client.get_many(keys.clone())?;
+        if values == expected {
+            return Ok(());
+        }
+        assert!(Instant::now() < deadline, "follower has {:?}, expected {:?}", values, expected);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Should stream a snapshot and then every write to a follower, which serves reads only
+#[test]
+fn follower_replicates_leader() -> Result<()> {
+    let (leader_addr, follower_addr) = ("127.0.0.1:4022", "127.0.0.1:4023");
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let _leader = spawn_server(&leader_dir, &["--addr", leader_addr]);
+    let mut leader = KvsClient::connect(leader_addr)?;
+    leader.set("key1".to_owned(), "value1".to_owned())?;
+    leader.set("key2".to_owned(), "value2".to_owned())?;
+
+    let follower_args = ["--addr", follower_addr, "--replica-of", leader_addr];
+    let follower = spawn_server(&follower_dir, &follower_args);
+    wait_for(follower_addr, &[("key1", Some("value1")), ("key2", Some("value2"))])?;
+
+    leader.set("key3".to_owned(), "value3".to_owned())?;
+    leader.remove("key1".to_owned())?;
+    leader.set_many(vec![("key2".to_owned(), "value4".to_owned())])?;
+    wait_for(
+        follower_addr,
+        &[("key1", None), ("key2", Some("value4")), ("key3", Some("value3"))],
+    )?;
+
+    let mut client = KvsClient::connect(follower_addr)?;
+    let err = client.set("key4".to_owned(), "value4".to_owned()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::ReadOnly(_)));
+    let err = client.remove_many(vec!["key2".to_owned()]).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::ReadOnly(_)));
+
+    let info = leader.replication_info()?;
+    assert_eq!(info.role, Role::Leader);
+    assert_eq!(info.offset, 5);
+    assert_eq!(info.followers, 1);
+
+    let follower_info = client.replication_info()?;
+    assert_eq!(follower_info.role, Role::Follower);
+    assert_eq!(follower_info.replid, info.replid);
+    assert_eq!(follower_info.offset, 5);
+    assert_eq!(follower_info.lag, Some(0));
+    assert!(follower_info.connected);
+
+    // A restarted follower is resumed from its position
+    drop(client);
+    drop(follower);
+    leader.set("key5".to_owned(), "value5".to_owned())?;
+
+    let _follower = spawn_server(&follower_dir, &follower_args);
+    wait_for(follower_addr, &[("key2", Some("value4")), ("key5", Some("value5"))])?;
+    let follower_info = KvsClient::connect(follower_addr)?.replication_info()?;
+    assert_eq!(follower_info.replid, info.replid);
+    assert_eq!(follower_info.offset, 6);
+
+    Ok(())
+}
+
+// Should load a new snapshot after the leader restarts, dropping the keys removed meanwhile
+#[test]
+fn follower_resyncs_after_leader_restart() -> Result<()> {
+    let (leader_addr, follower_addr) = ("127.0.0.1:4024", "127.0.0.1:4025");
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let leader = spawn_server(&leader_dir, &["--addr", leader_addr]);
+    KvsClient::connect(leader_addr)?.set_many(vec![
+        ("key1".to_owned(), "value1".to_owned()),
+        ("key2".to_owned(), "value2".to_owned()),
+    ])?;
+
+    let _follower = spawn_server(&follower_dir, &["--addr", follower_addr, "--replica-of", leader_addr]);
+    wait_for(follower_addr, &[("key1", Some("value1")), ("key2", Some("value2"))])?;
+
+    drop(leader);
+    let _leader = spawn_server(&leader_dir, &["--addr", leader_addr]);
+    KvsClient::connect(leader_addr)?.remove("key1".to_owned())?;
+    wait_for(follower_addr, &[("key1", None), ("key2", Some("value2"))])?;
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use assert_cmd::prelude::*;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn request(stream: &mut TcpStream, command: &[&str]) -> String {
+    let mut content = format!("*{}\r\n", command.len());
+    for arg in command {
+        content.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    stream.write_all(content.as_bytes()).unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+    let mut buffer = [0u8; 1024];
+    let len = stream.read(&mut buffer).unwrap();
+    String::from_utf8_lossy(&buffer[..len]).to_string()
+}
+
+fn resp_access_server(engine: &str, addr: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", engine, "--addr", addr, "--protocol", "resp"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    assert_eq!(request(&mut stream, &["PING"]), "+PONG\r\n");
+    assert_eq!(request(&mut stream, &["SET", "key1", "value1"]), "+OK\r\n");
+    assert_eq!(request(&mut stream, &["GET", "key1"]), "$6\r\nvalue1\r\n");
+    assert_eq!(request(&mut stream, &["GET", "key2"]), "$-1\r\n");
+    assert_eq!(
+        request(&mut stream, &["MSET", "key2", "value2", "other", "value3"]),
+        "+OK\r\n"
+    );
+    assert_eq!(
+        request(&mut stream, &["MGET", "key1", "key3", "key2"]),
+        "*3\r\n$6\r\nvalue1\r\n$-1\r\n$6\r\nvalue2\r\n"
+    );
+    assert_eq!(
+        request(&mut stream, &["EXISTS", "key1", "key3", "other"]),
+        ":2\r\n"
+    );
+    assert_eq!(
+        request(&mut stream, &["SCAN", "0", "MATCH", "key*"]),
+        "*2\r\n$1\r\n0\r\n*2\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n"
+    );
+    assert_eq!(
+        request(&mut stream, &["SCAN", "0", "COUNT", "2"]),
+        "*2\r\n$1\r\n1\r\n*2\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n"
+    );
+    // Cursors are shared by connections, for clients with a pool of them.
+    let mut other = TcpStream::connect(addr).unwrap();
+    assert_eq!(
+        request(&mut other, &["SCAN", "1", "COUNT", "2"]),
+        "*2\r\n$1\r\n0\r\n*1\r\n$5\r\nother\r\n"
+    );
+    assert!(request(&mut stream, &["SCAN", "2"]).starts_with("-ERR invalid cursor"));
+    assert_eq!(request(&mut stream, &["DEL", "key1", "key3"]), ":1\r\n");
+    assert_eq!(request(&mut stream, &["GET", "key1"]), "$-1\r\n");
+    assert!(request(&mut stream, &["INFO"]).contains("db0:keys=2"));
+    assert!(request(&mut stream, &["UNKNOWN"]).starts_with("-ERR"));
+
+    // Inline commands and pipelined commands are both supported.
+    stream.write_all(b"GET key2\r\nPING\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut buffer = [0u8; 1024];
+    let len = stream.read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..len], b"$6\r\nvalue2\r\n+PONG\r\n");
+
+    // Absurd bulk strings and endless lines are refused before they are read.
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"*1\r\n$100000000\r\n").unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).unwrap();
+    assert!(reply.contains("invalid bulk length"), "{}", reply);
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(&[b'a'; 100 * 1024]).unwrap();
+    // The connection is reset rather than closed if the rest of the line was sent already.
+    let mut reply = String::new();
+    if stream.read_to_string(&mut reply).is_ok() {
+        assert!(reply.contains("too big line"), "{}", reply);
+    }
+    let mut stream = TcpStream::connect(addr).unwrap();
+    assert_eq!(request(&mut stream, &["PING"]), "+PONG\r\n");
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+/// Get a page of `SCAN`, returning the next cursor and the keys.
+fn scan(stream: &mut TcpStream, cursor: &str) -> (String, Vec<String>) {
+    let reply = request(stream, &["SCAN", cursor, "COUNT", "3"]);
+    let lines = reply.split("\r\n").collect::<Vec<_>>();
+    let keys = lines[5..].iter().step_by(2).filter(|key| !key.is_empty()).map(|key| key.to_string());
+    (lines[2].to_string(), keys.collect())
+}
+
+fn resp_scan_with_removals(engine: &str, addr: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", engine, "--addr", addr, "--protocol", "resp"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    // Keys are longer than the 6 bytes a cursor holding the key in digits would fit a u64 with.
+    let keys = (0..10).map(|i| format!("a-longer-key-{}", i)).collect::<Vec<_>>();
+    for key in &keys {
+        assert_eq!(request(&mut stream, &["SET", key, "value"]), "+OK\r\n");
+    }
+
+    // Keys already returned are removed between pages, which must not skip the later keys.
+    let (mut cursor, mut found) = scan(&mut stream, "0");
+    while cursor != "0" {
+        assert!(cursor.parse::<u64>().is_ok(), "{} is not a u64", cursor);
+        for key in found.iter().rev().take(2) {
+            assert_eq!(request(&mut stream, &["DEL", key]), ":1\r\n");
+        }
+        let (next, page) = scan(&mut stream, &cursor);
+        cursor = next;
+        found.extend(page);
+    }
+    assert_eq!(found, keys);
+
+    // Patterns of many stars are matched without trying every way to split the key.
+    let key = "a".repeat(200);
+    assert_eq!(request(&mut stream, &["SET", &key, "value"]), "+OK\r\n");
+    let start = Instant::now();
+    let reply = request(&mut stream, &["SCAN", "0", "MATCH", "*a*a*a*a*a*a*a*a*a*a*a*a*b"]);
+    assert!(reply.ends_with("*0\r\n"), "{}", reply);
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+#[test]
+fn resp_access_server_kvs_engine() {
+    resp_access_server("kvs", "127.0.0.1:4006");
+}
+
+#[test]
+fn resp_access_server_sled_engine() {
+    resp_access_server("sled", "127.0.0.1:4007");
+}
+
+#[test]
+fn resp_scan_with_removals_kvs_engine() {
+    resp_scan_with_removals("kvs", "127.0.0.1:4053");
+}
+
+#[test]
+fn resp_scan_with_removals_sled_engine() {
+    resp_scan_with_removals("sled", "127.0.0.1:4054");
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{spawn_server, KilledOnDrop};
+use kvs::{ErrorKind, KvStore, KvsClient, KvsEngine, Result, ShardMap, ShardedClient};
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn spawn_nodes(dirs: &[TempDir], addrs: &[&str]) -> Vec<KilledOnDrop> {
+    dirs.iter()
+        .zip(addrs)
+        .map(|(dir, addr)| spawn_server(dir, &["--addr", addr, "--sharded"]))
+        .collect()
+}
+
+fn temp_dirs(count: usize) -> Vec<TempDir> {
+    (0..count)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect()
+}
+
+// Should route each key to the node serving its shard, and redirect plain clients there
+#[test]
+fn sharded_client_routes_keys() -> Result<()> {
+    let addrs = ["127.0.0.1:4033", "127.0.0.1:4034"];
+    let dirs = temp_dirs(addrs.len());
+    let _nodes = spawn_nodes(&dirs, &addrs);
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["init", addrs[0], addrs[1], "--shards", "8"])
+        .assert()
+        .success()
+        .stdout(contains("version: 1").and(contains("127.0.0.1:4033: 0 2 4 6")));
+    // A running cluster is never given another map.
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["init", addrs[0], addrs[1]])
+        .assert()
+        .failure()
+        .stderr(contains("already has version 1"));
+
+    let mut client = ShardedClient::connect(&[addrs[1]])?;
+    assert_eq!(client.shard_map(), &ShardMap::new(8, &[addrs[0].to_owned(), addrs[1].to_owned()]));
+    let keys = (0..100).map(|index| format!("key{:02}", index)).collect::<Vec<String>>();
+    let pairs = keys.iter().map(|key| (key.clone(), format!("value of {}", key))).collect::<Vec<_>>();
+    client.set_many(pairs.clone())?;
+    client.set("single".to_owned(), "value".to_owned())?;
+
+    let expected = pairs.iter().map(|(_, value)| Some(value.clone())).collect::<Vec<_>>();
+    assert_eq!(client.get_many(keys.clone())?, expected);
+    assert_eq!(client.scan("key".to_owned())?, keys);
+
+    // Each node only has the keys of its own shards.
+    let map = client.shard_map().clone();
+    for addr in &addrs {
+        let local = KvsClient::connect(addr)?.scan("key".to_owned())?;
+        assert!(!local.is_empty());
+        assert!(local.iter().all(|key| map.node_of(key) == Some(addr)));
+    }
+
+    // A plain client follows the redirect of a node which does not serve the key.
+    let key = keys.iter().find(|key| map.node_of(key) == Some(addrs[1])).unwrap();
+    let mut plain = KvsClient::connect(addrs[0])?;
+    assert_eq!(plain.get(key.clone())?, Some(format!("value of {}", key)));
+
+    assert_eq!(client.remove_many(vec![keys[0].clone(), "missing".to_owned()])?, vec![true, false]);
+    client.remove("single".to_owned())?;
+    match client.remove("single".to_owned()) {
+        Err(err) => assert!(matches!(err.kind(), ErrorKind::KeyNotFound)),
+        Ok(()) => panic!("a removed key was removed again"),
+    }
+    assert_eq!(client.scan(String::new())?.len(), 99);
+
+    Ok(())
+}
+
+// Should move a shard to a new node while it is written, keeping every acknowledged write
+#[test]
+fn move_shard_streams_keys() -> Result<()> {
+    let addrs = ["127.0.0.1:4035", "127.0.0.1:4036", "127.0.0.1:4037"];
+    let dirs = temp_dirs(addrs.len());
+    let nodes = spawn_nodes(&dirs, &addrs);
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["init", addrs[0], addrs[1], "--shards", "4"])
+        .assert()
+        .success();
+    let mut client = ShardedClient::connect(&addrs[..2])?;
+    for chunk in (0..2000).collect::<Vec<usize>>().chunks(500) {
+        let pairs = chunk
+            .iter()
+            .map(|index| (format!("key{}", index), format!("value{}", index)))
+            .collect();
+        client.set_many(pairs)?;
+    }
+
+    // Writes keep going while the shard is moved, with a client which never refreshes its map
+    // by itself.
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = Arc::clone(&stop);
+        let mut client = ShardedClient::connect(&addrs[..2])?;
+        thread::spawn(move || -> Result<usize> {
+            let mut written = 0;
+            while !stop.load(Ordering::SeqCst) {
+                client.set(format!("new{}", written), format!("value{}", written))?;
+                written += 1;
+            }
+            Ok(written)
+        })
+    };
+
+    thread::sleep(Duration::from_millis(200));
+    // The request is redirected to the node serving shard 0.
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["move", "0", "--to", addrs[2], "--addr", addrs[1]])
+        .assert()
+        .success()
+        .stdout(contains("version: 2").and(contains("127.0.0.1:4037: 0")));
+    thread::sleep(Duration::from_millis(200));
+    stop.store(true, Ordering::SeqCst);
+    let written = writer.join().unwrap()?;
+
+    let mut keys = (0..2000).map(|index| format!("key{}", index)).collect::<Vec<String>>();
+    keys.extend((0..written).map(|index| format!("new{}", index)));
+    let expected = (0..2000)
+        .chain(0..written)
+        .map(|index| Some(format!("value{}", index)))
+        .collect::<Vec<Option<String>>>();
+    // The client learns the new map from the node which no longer serves the shard.
+    assert_eq!(client.get_many(keys.clone())?, expected);
+    assert_eq!(client.shard_map().version, 2);
+    assert_eq!(client.scan(String::new())?.len(), keys.len());
+
+    // The keys of the shard were removed from the node it moved from.
+    let map = client.shard_map().clone();
+    drop(nodes);
+    let (from, to) = (KvStore::open(dirs[0].path())?, KvStore::open(dirs[2].path())?);
+    let moved = keys.iter().filter(|key| map.shard_of(key) == Some(0)).cloned().collect::<Vec<_>>();
+    assert!(!moved.is_empty());
+    assert!(from.get_many(moved.clone())?.iter().all(Option::is_none));
+    assert!(to.get_many(moved)?.iter().all(Option::is_some));
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::KilledOnDrop;
+use kvs::{ClientTls, KvsClient, Result, Timeouts};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Write the PEM files of a certificate and its key, signed by a given authority or by itself,
+/// and return their paths.
+fn write_cert(dir: &Path, name: &str, cert: &Certificate, signer: Option<&Certificate>) -> (PathBuf, PathBuf) {
+    let (cert_path, key_path) = (dir.join(format!("{}.pem", name)), dir.join(format!("{}.key", name)));
+    let pem = match signer {
+        Some(signer) => cert.serialize_pem_with_signer(signer).unwrap(),
+        None => cert.serialize_pem().unwrap(),
+    };
+    fs::write(&cert_path, pem).unwrap();
+    fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+fn server_cert(dir: &Path) -> (PathBuf, PathBuf) {
+    let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_owned()]).unwrap();
+    write_cert(dir, "server", &cert, None)
+}
+
+fn spawn_server(dir: &TempDir, addr: &str, config: serde_json::Value) -> KilledOnDrop {
+    fs::write(dir.path().join("config.json"), config.to_string()).unwrap();
+    common::spawn_server(dir, &["--addr", addr, "--config", "config.json"])
+}
+
+fn timeouts() -> Timeouts {
+    Timeouts::all(Duration::from_secs(5))
+}
+
+// Should serve clients connecting with TLS which trust the certificate of the server, and only them
+#[test]
+fn client_connects_with_tls() -> Result<()> {
+    let addr = "127.0.0.1:4045";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let (cert, key) = server_cert(dir.path());
+    let _server = spawn_server(&dir, addr, serde_json::json!({ "tls": { "cert": cert, "key": key } }));
+
+    let mut client = KvsClient::connect_tls(addr, timeouts(), ClientTls::new(&cert, None::<(&Path, &Path)>)?)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    // A client trusting another certificate refuses the server.
+    let other = TempDir::new().expect("unable to create temporary working directory");
+    let (other_cert, _) = server_cert(other.path());
+    let mut client = KvsClient::connect_tls(addr, timeouts(), ClientTls::new(&other_cert, None::<(&Path, &Path)>)?)?;
+    assert!(client.get("key".to_owned()).is_err());
+
+    // The server refuses clients which do not speak TLS.
+    let mut client = KvsClient::connect_with(addr, timeouts())?;
+    assert!(client.get("key".to_owned()).is_err());
+
+    // `kvs-client` connects with TLS given the certificate authority.
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key", "--addr", addr])
+        .arg("--tls-ca")
+        .arg(&cert)
+        .assert()
+        .success()
+        .stdout("value\n");
+
+    Ok(())
+}
+
+// Should only serve clients presenting a certificate signed by the client authority with mutual TLS
+#[test]
+fn mutual_tls_requires_client_certificate() -> Result<()> {
+    let addr = "127.0.0.1:4046";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let (cert, key) = server_cert(dir.path());
+
+    let mut params = CertificateParams::new(vec!["clients".to_owned()]);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let client_ca = Certificate::from_params(params).unwrap();
+    let (client_ca_path, _) = write_cert(dir.path(), "client-ca", &client_ca, None);
+    let client = rcgen::generate_simple_self_signed(vec!["client".to_owned()]).unwrap();
+    let (client_cert, client_key) = write_cert(dir.path(), "client", &client, Some(&client_ca));
+    let stranger = rcgen::generate_simple_self_signed(vec!["stranger".to_owned()]).unwrap();
+    let (stranger_cert, stranger_key) = write_cert(dir.path(), "stranger", &stranger, None);
+
+    let _server = spawn_server(
+        &dir,
+        addr,
+        serde_json::json!({ "tls": { "cert": cert, "key": key, "client_ca": client_ca_path } }),
+    );
+
+    let tls = ClientTls::new(&cert, Some((&client_cert, &client_key)))?;
+    let mut client = KvsClient::connect_tls(addr, timeouts(), tls)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+
+    let tls = ClientTls::new(&cert, None::<(&Path, &Path)>)?;
+    let mut client = KvsClient::connect_tls(addr, timeouts(), tls)?;
+    assert!(client.get("key".to_owned()).is_err());
+
+    let tls = ClientTls::new(&cert, Some((&stranger_cert, &stranger_key)))?;
+    let mut client = KvsClient::connect_tls(addr, timeouts(), tls)?;
+    assert!(client.get("key".to_owned()).is_err());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key", "--addr", addr])
+        .arg("--tls-ca")
+        .arg(&cert)
+        .arg("--tls-cert")
+        .arg(&client_cert)
+        .arg("--tls-key")
+        .arg(&client_key)
+        .assert()
+        .success()
+        .stdout("value\n");
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::spawn_server;
+use kvs::{KvsClient, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use tempfile::TempDir;
+
+// Should serve the kvs protocol on a Unix socket with the permissions given to it
+#[test]
+fn client_connects_unix_socket() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let socket = dir.path().join("kvs.sock");
+    let server = spawn_server(&dir, &["--unix", "kvs.sock", "--unix-mode", "600"]);
+    assert_eq!(fs::metadata(&socket)?.permissions().mode() & 0o777, 0o600);
+
+    let mut client = KvsClient::connect_unix(&socket)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    let addr = format!("unix:{}", socket.display());
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key", "--addr", &addr])
+        .assert()
+        .success()
+        .stdout("value\n");
+
+    // The socket file left by a killed server is replaced by the next one.
+    drop(server);
+    let _server = spawn_server(&dir, &["--unix", "kvs.sock"]);
+    let mut client = KvsClient::connect(&addr)?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    Ok(())
+}
+
+// Should refuse to take over the socket of a running server
+#[test]
+fn unix_socket_in_use() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, &["--unix", "kvs.sock"]);
+
+    let other = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .arg("--unix")
+        .arg(dir.path().join("kvs.sock"))
+        .current_dir(&other)
+        .assert()
+        .failure();
+    assert!(KvsClient::connect_unix(dir.path().join("kvs.sock"))?.get("key".to_owned())?.is_none());
+    Ok(())
+}
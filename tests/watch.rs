@@ -0,0 +1,114 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{spawn_server, KilledOnDrop};
+use kvs::{Change, Command, KvsClient, Result, Timeouts, Watch};
+use std::io::{BufRead, BufReader};
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn watch(addr: &str, prefix: &str, resume: Option<(String, u64)>) -> Result<Watch> {
+    KvsClient::connect_with(addr, Timeouts::all(Duration::from_secs(5)))?.watch(prefix.to_owned(), resume)
+}
+
+fn set(key: &str, value: &str) -> Command {
+    Command::Set {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    }
+}
+
+fn remove(key: &str) -> Command {
+    Command::Remove { key: key.to_owned() }
+}
+
+// Should push the committed writes of the watched keys in order, and resume after a given change
+#[test]
+fn watch_streams_changes() -> Result<()> {
+    let addr = "127.0.0.1:4038";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, &["--addr", addr]);
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("user:0".to_owned(), "before".to_owned())?;
+    let mut watcher = watch(addr, "user:", None)?;
+    assert!(!watcher.resumed());
+    let stream = watcher.stream().to_owned();
+    let start = watcher.seq();
+
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    client.set("item:1".to_owned(), "apple".to_owned())?;
+    client.set_many(vec![
+        ("user:2".to_owned(), "bob".to_owned()),
+        ("item:2".to_owned(), "pear".to_owned()),
+    ])?;
+    client.remove("user:1".to_owned())?;
+    // Keys which do not exist are not changed.
+    client.remove_many(vec!["user:2".to_owned(), "user:9".to_owned()])?;
+
+    let changes = (0..4).map(|_| watcher.next_change()).collect::<Result<Vec<Change>>>()?;
+    let commands = changes.iter().map(|change| change.command.clone()).collect::<Vec<Command>>();
+    assert_eq!(commands, vec![set("user:1", "alice"), set("user:2", "bob"), remove("user:1"), remove("user:2")]);
+    // The seqs count the changes of every key.
+    let seqs = changes.iter().map(|change| change.seq - start).collect::<Vec<u64>>();
+    assert_eq!(seqs, vec![1, 3, 5, 6]);
+    assert_eq!(watcher.seq(), start + 6);
+
+    // A watch resumed after a change gets the changes after it again.
+    let mut resumed = watch(addr, "", Some((stream.clone(), changes[1].seq)))?;
+    assert!(resumed.resumed());
+    assert_eq!(resumed.next_change()?, Change {
+        seq: start + 4,
+        command: set("item:2", "pear"),
+    });
+    assert_eq!(resumed.next_change()?.command, remove("user:1"));
+
+    // A watch of another stream starts after the last change.
+    let mut missed = watch(addr, "", Some(("another".to_owned(), 1)))?;
+    assert!(!missed.resumed());
+    assert_eq!(missed.seq(), start + 6);
+    client.set("user:3".to_owned(), "carol".to_owned())?;
+    assert_eq!(missed.next_change()?.command, set("user:3", "carol"));
+    assert_eq!(watcher.next_change()?.command, set("user:3", "carol"));
+
+    Ok(())
+}
+
+// Should print the changes told by the subscriber of sled with `kvs-client watch`
+#[test]
+fn cli_watch_sled() -> Result<()> {
+    let addr = "127.0.0.1:4039";
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let _server = spawn_server(&dir, &["--addr", addr, "--engine", "sled"]);
+
+    let mut watcher = KilledOnDrop(
+        process::Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["watch", "key", "--addr", addr])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    thread::sleep(Duration::from_secs(1));
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    client.set("other".to_owned(), "value".to_owned())?;
+    client.remove("key1".to_owned())?;
+
+    let lines = BufReader::new(watcher.0.stdout.take().unwrap())
+        .lines()
+        .take(2)
+        .collect::<std::io::Result<Vec<String>>>()?;
+    drop(watcher);
+    assert_eq!(lines, vec!["1 set key1 value1", "3 rm key1"]);
+
+    // Changes which were never published can not be resumed after.
+    let stream = watch(addr, "", None)?.stream().to_owned();
+    assert!(!watch(addr, "", Some((stream, 100)))?.resumed());
+
+    Ok(())
+}